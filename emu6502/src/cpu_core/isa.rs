@@ -1,4 +1,5 @@
 use super::{ Instructions, Mnemonic, AddrModeEnum, Instruction};
+use emucore::isa::{IsaDbase, IsaResult};
 
 pub struct Isa {
     pub instructions: Instructions,
@@ -50,3 +51,58 @@ impl Isa {
         }
     }
 }
+
+/// The instruction set as an emucore ISA database
+pub fn load_isa() -> IsaResult<IsaDbase<AddrModeEnum>> {
+    IsaDbase::parse(include_str!("../../resources/opcodes6502.json"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_isa_valid() {
+        if let Err(e) = load_isa().unwrap().checked() {
+            panic!("{e}")
+        }
+    }
+
+    #[test]
+    fn test_fixed_opcodes() {
+        use AddrModeEnum::*;
+        use Mnemonic::*;
+
+        let json = include_str!("../../resources/opcodes6502.json");
+        let isa = Isa::new(serde_json::from_str(json).unwrap());
+
+        let want = [
+            (0x61, Adc, IndirectX, 2),
+            (0x71, Adc, IndirectY, 2),
+            (0xd1, Cmp, IndirectY, 2),
+            (0xbd, Lda, AbsoluteX, 3),
+            (0x6e, Ror, Absolute, 3),
+            (0x7e, Ror, AbsoluteX, 3),
+            (0x2e, Rol, Absolute, 3),
+            (0x8c, Sty, Absolute, 3),
+            (0x94, Sty, ZeroPageX, 2),
+            (0xc4, Cpy, ZeroPage, 2),
+            (0xb6, Ldx, ZeroPageY, 2),
+            (0xb4, Ldy, ZeroPageX, 2),
+            (0x85, Sta, ZeroPage, 2),
+            (0x8d, Sta, Absolute, 3),
+            (0x91, Sta, IndirectY, 2),
+            (0x0a, Asl, Inherent, 1),
+            (0x6a, Ror, Inherent, 1),
+        ];
+
+        for (opcode, mnemonic, mode, size) in want {
+            let i = isa.get_instruction_info(opcode).unwrap();
+            assert_eq!(
+                (i.mnemonic, i.addr_mode, i.size),
+                (mnemonic, mode, size),
+                "opcode ${opcode:02x}"
+            );
+        }
+    }
+}
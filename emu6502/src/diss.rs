@@ -0,0 +1,142 @@
+use crate::cpu_core::{load_isa, AddrModeEnum};
+use emucore::diss::{Disassembler, OpFlow, Operand, OperandFormat};
+use emucore::flowdiss::FlowDiss;
+use emucore::isa::{IsaOp, IsaResult};
+use emucore::mem::MemoryIO;
+use std::ops::Range;
+
+/// 6502 operand syntax, words are little endian
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Syntax;
+
+impl OperandFormat<AddrModeEnum> for Syntax {
+    fn operand(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> String {
+        use AddrModeEnum::*;
+
+        let zp = || o.name(o.byte(0) as usize, 2, "$");
+        let addr = || o.name(o.word_le(0) as usize, 4, "$");
+        // Force absolute where an assembler would pick zero page, ca65 style
+        let abs = || match o.word_le(0) {
+            0..=0xff => format!("a:{}", addr()),
+            _ => addr(),
+        };
+
+        match op.addr_mode {
+            Immediate => format!("#${:02x}", o.byte(0)),
+            Inherent | Illegal => String::new(),
+            ZeroPage => zp(),
+            ZeroPageX => format!("{},x", zp()),
+            ZeroPageY => format!("{},y", zp()),
+            Absolute => abs(),
+            AbsoluteX => format!("{},x", abs()),
+            AbsoluteY => format!("{},y", abs()),
+            IndirectX => format!("({},x)", zp()),
+            IndirectY => format!("({}),y", zp()),
+            Relative => o.name(o.rel8(0), 4, "$"),
+            Indirect => format!("({})", addr()),
+        }
+    }
+
+    fn flow(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> OpFlow {
+        use AddrModeEnum::*;
+
+        match (op.mnemonic.as_str(), op.addr_mode) {
+            ("Jsr", _) => OpFlow::Call(o.word_le(0) as usize),
+            ("Jmp", Absolute) => OpFlow::Jump(o.word_le(0) as usize),
+            ("Jmp", _) | ("Rts", _) | ("Rti", _) | ("Brk", _) => OpFlow::Stop,
+            (_, Relative) => OpFlow::Branch(o.rel8(0)),
+            _ => OpFlow::Next,
+        }
+    }
+
+    fn data_ref(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> Option<usize> {
+        use AddrModeEnum::*;
+
+        match op.addr_mode {
+            _ if op.mnemonic.starts_with('J') => None,
+            ZeroPage | ZeroPageX | ZeroPageY | IndirectX | IndirectY => Some(o.byte(0) as usize),
+            Absolute | AbsoluteX | AbsoluteY => Some(o.word_le(0) as usize),
+            _ => None,
+        }
+    }
+
+    fn big_endian(&self) -> bool {
+        false
+    }
+}
+
+/// Nmi, reset and irq/brk
+pub const VECTORS: [(usize, &str); 3] = [(0xfffa, "nmi"), (0xfffc, "reset"), (0xfffe, "irq")];
+
+pub type Diss = Disassembler<AddrModeEnum, Syntax>;
+
+/// A disassembler built from the ISA json
+pub fn disassembler() -> IsaResult<Diss> {
+    Ok(Disassembler::new(load_isa()?, Syntax))
+}
+
+/// Flow following disassembly of a rom image, starting from the vectors it holds
+pub fn rom_diss<'a>(
+    diss: &'a Diss,
+    mem: &'a dyn MemoryIO,
+    range: Range<usize>,
+) -> FlowDiss<'a, AddrModeEnum, Syntax> {
+    FlowDiss::new(diss, mem, range).vectors(&VECTORS)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use emucore::byteorder;
+    use emucore::mem::MemBlock;
+
+    #[test]
+    fn test_diss() {
+        let code = [
+            0xa9, 0x01, 0x8d, 0x00, 0x02, 0xb1, 0x10, 0xd0, 0xf7, 0x6c, 0xfc, 0xff, 0x0a, 0xad,
+            0x10, 0x00, 0x02,
+        ];
+        let mem = MemBlock::<byteorder::LittleEndian>::from_data(0x600, "ram", &code, true);
+        let diss = disassembler().unwrap();
+
+        let text: Vec<_> = diss
+            .iter(&mem, 0x600..0x600 + code.len())
+            .map(|l| l.text())
+            .collect();
+
+        assert_eq!(
+            text,
+            [
+                "lda #$01",
+                "sta $0200",
+                "lda ($10),y",
+                "bne $0600",
+                "jmp ($fffc)",
+                "asl",
+                "lda a:$0010",
+                "fcb $02"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rom_diss() {
+        // reset: jsr sub, loop: bne loop, jmp reset; sub: inc $10, rts
+        let mut rom = vec![0xea; 0x20];
+        rom[..11].copy_from_slice(&[
+            0x20, 0xe8, 0xff, 0xd0, 0xfe, 0x4c, 0xe0, 0xff, 0xe6, 0x10, 0x60,
+        ]);
+        rom[0x1a..].copy_from_slice(&[0xea, 0xff, 0xe0, 0xff, 0xea, 0xff]);
+
+        let mem = MemBlock::<byteorder::LittleEndian>::from_data(0xffe0, "rom", &rom, true);
+        let diss = disassembler().unwrap();
+        let listing = rom_diss(&diss, &mem, 0xffe0..0x10000).trace().unwrap();
+
+        assert_eq!(listing.bytes(), rom);
+        assert!(listing.is_code(0xffe9));
+        assert!(!listing.is_code(0xffeb));
+
+        let names: Vec<_> = listing.labels.values().map(String::as_str).collect();
+        assert_eq!(names, ["reset", "loc_ffe3", "sub_ffe8", "nmi"]);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod cpu;
 pub mod cpu_core;
+pub mod diss;
@@ -151,7 +151,7 @@ impl Bus for Immediate8 {
         _m: &mut Machine<M, R>,
     ) -> MemResult<u16> {
         let pc = _m.regs.pc();
-        let ret = _m.mem_mut().load_word(pc as usize)?;
+        let ret = _m.mem_mut().fetch_operand_word(pc as usize)?;
         _m.regs.set_pc(pc.wrapping_add(2));
         Ok(ret)
     }
@@ -160,7 +160,7 @@ impl Bus for Immediate8 {
         _m: &mut Machine<M, R>,
     ) -> MemResult<u8> {
         let pc = _m.regs.pc();
-        let ret = _m.mem_mut().load_byte(pc as usize)?;
+        let ret = _m.mem_mut().fetch_operand_byte(pc as usize)?;
         _m.regs.set_pc(pc.wrapping_add(1));
         Ok(ret)
     }
@@ -106,6 +106,16 @@ impl<'a> RegisterFileTrait for DebugRegisterFile<'a> {
 
 }
 
+impl<'a> emucore::traits::RegisterFileTrait<RegEnum> for DebugRegisterFile<'a> {
+    fn get(&self, r: &RegEnum) -> u64 {
+        emucore::traits::RegisterFileTrait::get(self.regs, r)
+    }
+
+    fn set(&mut self, r: &RegEnum, v: u64) {
+        emucore::traits::RegisterFileTrait::set(self.regs, r, v)
+    }
+}
+
 impl<'a> StatusRegTrait for DebugRegisterFile<'a> {
     #[inline]
     fn set_n(&mut self, val: bool) -> &mut Self {
@@ -3,7 +3,10 @@ use std::usize;
 use emucore::mem::{MemErrorTypes, MemResult, MemoryIO};
 use itertools::MergeJoinBy;
 
-use crate::cpu_core::{calc_rel, InstructionInfo, IsaDatabase};
+use crate::cpu_core::{calc_rel, AddrModeEnum, InstructionInfo, IsaDatabase};
+use emucore::diss::{Disassembler, OpFlow, Operand, OperandFormat};
+use emucore::flowdiss::FlowDiss;
+use emucore::isa::{IsaOp, IsaResult};
 
 pub struct Disassmbly<'a> {
     pub text: String,
@@ -40,7 +43,7 @@ pub enum DisError {
 
 pub type DisResult<T> = Result<T, DisError>;
 
-pub fn diss<'a, M: MemoryIO>(
+pub fn diss<'a, M: MemoryIO + ?Sized>(
     mem: &M,
     pc: usize,
 ) -> DisResult<Disassmbly<'a>> {
@@ -73,8 +76,15 @@ pub fn diss<'a, M: MemoryIO>(
     Ok(ret)
 }
 
+/// Instruction text at pc, for reports
+pub fn describe(mem: &dyn MemoryIO, pc: usize) -> String {
+    diss(mem, pc)
+        .map(|d| d.text)
+        .unwrap_or_else(|e| e.to_string())
+}
+
 /// Returns operand + next ins PC
-pub fn diss_operand<M: MemoryIO>(mem: &M, addr: u16, ins: &InstructionInfo) -> DisResult<String> {
+pub fn diss_operand<M: MemoryIO + ?Sized>(mem: &M, addr: u16, ins: &InstructionInfo) -> DisResult<String> {
     let addr_usize = addr as usize;
 
     use crate::cpu_core::AddrModeEnum::*;
@@ -117,3 +127,128 @@ pub fn diss_operand<M: MemoryIO>(mem: &M, addr: u16, ins: &InstructionInfo) -> D
     };
     Ok(text)
 }
+
+/// 6800 operand syntax for the emucore disassembler
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Syntax;
+
+impl OperandFormat<AddrModeEnum> for Syntax {
+    fn operand(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> String {
+        use crate::cpu_core::AddrModeEnum::*;
+
+        match op.addr_mode {
+            Immediate8 => format!("#0x{:02x}", o.byte(0)),
+            Immediate16 => format!("#0x{:04x}", o.word_be(0)),
+            Direct => format!("<{}", o.name(o.byte(0) as usize, 2, "0x")),
+            Extended => {
+                let w = o.word_be(0) as usize;
+                // Force extended where an assembler would pick direct
+                let force = if w < 0x100 { ">" } else { "" };
+                format!("{force}{}", o.name(w, 4, "0x"))
+            }
+            Indexed => format!("0x{:02x},x", o.byte(0)),
+            Inherent => "".to_owned(),
+            Relative => o.name(o.rel8(0), 4, "0x"),
+            Illegal => "????".to_owned(),
+        }
+    }
+
+    fn flow(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> OpFlow {
+        use crate::cpu_core::AddrModeEnum::*;
+
+        let ext = o.word_be(0) as usize;
+
+        match (op.mnemonic.as_str(), op.addr_mode) {
+            ("Jsr", Extended) => OpFlow::Call(ext),
+            ("Jmp", Extended) => OpFlow::Jump(ext),
+            ("Jmp", _) | ("Rts", _) | ("Rti", _) => OpFlow::Stop,
+            ("Bsr", _) => OpFlow::Call(o.rel8(0)),
+            ("Bra", _) => OpFlow::Jump(o.rel8(0)),
+            (_, Relative) => OpFlow::Branch(o.rel8(0)),
+            _ => OpFlow::Next,
+        }
+    }
+
+    fn data_ref(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> Option<usize> {
+        use crate::cpu_core::AddrModeEnum::*;
+
+        match op.addr_mode {
+            _ if op.mnemonic.starts_with('J') => None,
+            Direct => Some(o.byte(0) as usize),
+            Extended => Some(o.word_be(0) as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Irq, swi, nmi and reset
+pub const VECTORS: [(usize, &str); 4] = [
+    (0xfff8, "irq"),
+    (0xfffa, "swi"),
+    (0xfffc, "nmi"),
+    (0xfffe, "reset"),
+];
+
+/// A table driven disassembler built from the ISA json
+pub fn disassembler() -> IsaResult<Disassembler<AddrModeEnum, Syntax>> {
+    Ok(Disassembler::new(super::load_isa()?, Syntax))
+}
+
+/// Flow following disassembly of a rom image, starting from the vectors it holds
+pub fn rom_diss<'a>(
+    diss: &'a Disassembler<AddrModeEnum, Syntax>,
+    mem: &'a dyn MemoryIO,
+    range: std::ops::Range<usize>,
+) -> FlowDiss<'a, AddrModeEnum, Syntax> {
+    FlowDiss::new(diss, mem, range).vectors(&VECTORS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emucore::byteorder;
+    use emucore::mem::MemBlock;
+
+    #[test]
+    fn syntax() {
+        // ldaa $0010, bra self, ldaa <$20, ldaa #1
+        let code = [0xb6, 0x00, 0x10, 0x20, 0xfe, 0x96, 0x20, 0x86, 0x01];
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0x1000, "rom", &code, true);
+        let diss = disassembler().unwrap();
+
+        let text: Vec<_> = diss
+            .iter(&mem, 0x1000..0x1000 + code.len())
+            .map(|l| l.text())
+            .collect();
+
+        assert_eq!(
+            text,
+            ["ldaa >0x0010", "bra 0x1003", "ldaa <0x20", "ldaa #0x01"]
+        );
+    }
+
+    #[test]
+    fn test_rom_diss() {
+        // reset: jsr sub, loop: bne loop, jmp reset; sub: inc $0010, rts; irq: rti
+        let mut rom = vec![0x01; 0x20];
+        rom[..14].copy_from_slice(&[
+            0xbd, 0xff, 0xe8, 0x26, 0xfe, 0x7e, 0xff, 0xe0, 0x7c, 0x00, 0x10, 0x39, 0x3b, 0x02,
+        ]);
+        rom[0x18..].copy_from_slice(&[0xff, 0xec, 0xff, 0xec, 0xff, 0xec, 0xff, 0xe0]);
+
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0xffe0, "rom", &rom, true);
+        let diss = disassembler().unwrap();
+        let listing = rom_diss(&diss, &mem, 0xffe0..0x10000).trace().unwrap();
+
+        assert_eq!(listing.bytes(), rom);
+        assert!(listing.is_code(0xffec));
+        assert!(!listing.is_code(0xffed));
+
+        let names: Vec<_> = listing.labels.values().map(String::as_str).collect();
+        assert_eq!(names, ["reset", "loc_ffe3", "sub_ffe8", "irq"]);
+
+        let text = listing.to_string();
+        assert!(text.contains("sub_ffe8        inc   >0x0010\n"), "{text}");
+        assert!(text.contains("loc_ffe3        bne   loc_ffe3\n"), "{text}");
+    }
+}
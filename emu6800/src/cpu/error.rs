@@ -1,10 +1,21 @@
 use emucore::mem::MemErrorTypes;
+use emucore::stackguard::StackErr;
 use thiserror::Error;
 
-#[derive(Copy,Clone,Error,Debug)]
+#[derive(Clone,Error,Debug)]
 pub enum CpuErrKind {
     #[error(transparent)]
-    Memory(#[from] MemErrorTypes)
+    Memory(#[from] MemErrorTypes),
+    #[error(transparent)]
+    Stack(#[from] StackErr),
+    #[error("Instruction fetch from ${pc:04x}, previous pc ${prev_pc:04x}: {err}")]
+    IllegalFetch {
+        pc: usize,
+        prev_pc: usize,
+        err: MemErrorTypes,
+    },
+    #[error("Illegal opcode ${opcode:02x} at ${pc:04x}")]
+    IllegalInstruction { pc: usize, opcode: u8 },
 }
 
 pub type CpuResult<T> = Result<T,CpuErrKind>;
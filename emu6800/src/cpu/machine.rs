@@ -1,8 +1,21 @@
-use super::{diss, CpuResult, DisResult, Disassmbly, RegisterFileTrait, StatusRegTrait};
+use super::{diss, CpuErrKind, CpuResult, DisResult, Disassmbly, RegisterFileTrait, StatusRegTrait};
 use crate::cpu::Ins;
-use crate::cpu_core::u8_sign_extend;
+use crate::cpu_core::{u8_sign_extend, RegEnum};
 
+use emucore::callstack::CallStack;
+use emucore::flagcheck::FlagTarget;
+use emucore::flow::Flow;
+use emucore::golden::GoldenTarget;
+use emucore::intsched::InterruptTarget;
 use emucore::mem::{MemResult, MemoryIO};
+use emucore::regwatch::RegWatches;
+use emucore::replay::{hash_state, Pin, ReplayTarget};
+use emucore::run::{RunTarget, StepTarget};
+use emucore::stackguard::StackGuard;
+
+const IRQ_VEC: usize = 0xfff8;
+const NMI_VEC: usize = 0xfffc;
+const RESET_VEC: usize = 0xfffe;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CpuState {
@@ -49,10 +62,24 @@ where
     pub regs: R,
     pub mem: M,
     pub cycle: usize,
+    /// Opcodes executed, interrupt entries and reset aren't counted
+    pub instructions: usize,
     pub nmi: bool,
     pub reset: bool,
     pub irq: bool,
     pub wai: bool,
+    /// Address of the instruction executing or last executed
+    pub prev_pc: usize,
+    /// Flow of control recorded by the last step
+    pub flow: Flow,
+    /// Shadow call stack built from flow
+    pub call_stack: CallStack,
+    /// Optional bounds check for SP
+    pub stack_guard: Option<StackGuard>,
+    /// Register watchpoints, hits are left here after each step
+    pub reg_watches: RegWatches<RegEnum>,
+    /// A register watch triggered on the last step
+    pub reg_hit: bool,
 }
 
 
@@ -121,14 +148,21 @@ where
         let addr = self.mem_mut().load_word(vec_addr)?;
         self.regs.set_pc(addr);
         self.regs.sei();
+        self.flow = Flow::Interrupt {
+            vector: vec_addr,
+            dest: addr as usize,
+            ret: pc as usize,
+        };
         Ok(addr.into())
     }
 
+    /// Step until at least this many more cycles have passed
+    /// Counts on from the current cycle, the last instruction can overshoot
     pub fn step_cycles(&mut self, cycles: usize) -> CpuResult<()> {
-        self.cycle = 0;
+        let target = self.cycle + cycles;
 
         loop {
-            if self.cycle >= cycles {
+            if self.cycle >= target {
                 break;
             } else {
                 self.step()?;
@@ -139,29 +173,26 @@ where
     }
 
     pub fn step(&mut self) -> CpuResult<StepResult> {
-        const IRQ_VEC: usize = 0xfff8;
-        const SWI_VEC: usize = 0xfffa;
-        const NMI_VEC: usize = 0xfffc;
-        const RESET_VEC: usize = 0xfffe;
-
         use super::addrmodes::*;
         let cycle = self.cycle;
+        self.flow = Flow::Normal;
+        self.mem.set_bus_cycle(cycle);
 
         let pc = self.regs.pc() as usize;
 
         use CpuState::*;
 
-        match self.get_cpu_state() {
+        let ret = match self.get_cpu_state() {
             NmiPending => {
                 let pc = self.interrupt(NMI_VEC)?;
                 self.nmi = false;
-                self.cycle += 1;
+                self.cycle += 1 + self.mem.take_wait_cycles();
                 Ok(StepResult::Nmi(pc))
             }
             IrqPending => {
                 let pc = self.interrupt(IRQ_VEC)?;
                 self.irq = false;
-                self.cycle += 1;
+                self.cycle += 1 + self.mem.take_wait_cycles();
                 Ok(StepResult::Irq(pc))
             }
 
@@ -170,13 +201,22 @@ where
                 let v = self.mem_mut().load_word(RESET_VEC)?;
                 self.regs.set_pc(v);
                 self.regs.sei();
-                self.cycle += 1;
+                self.cycle += 1 + self.mem.take_wait_cycles();
+                self.call_stack.reset();
                 Ok(StepResult::Reset(v.into()))
             }
 
             Running => {
                 let addr = self.regs.pc();
-                let op_code = self.mem_mut().load_byte(addr as usize)?;
+                let op_code = self
+                    .mem_mut()
+                    .fetch_byte(addr as usize)
+                    .map_err(|err| CpuErrKind::IllegalFetch {
+                        pc: addr as usize,
+                        prev_pc: self.prev_pc,
+                        err,
+                    })?;
+                self.prev_pc = addr as usize;
                 self.regs.inc_pc();
 
                 macro_rules! handle_op {
@@ -191,16 +231,30 @@ where
                 }
 
                 op_table!(op_code, {
-                    panic!("NOT IMP PC: {:04x} {:02x}", addr, op_code)
+                    return Err(CpuErrKind::IllegalInstruction {
+                        pc: addr as usize,
+                        opcode: op_code,
+                    })
                 });
 
+                self.instructions += 1;
+                // Stretched bus accesses
+                self.cycle += self.mem.take_wait_cycles();
+
                 Ok(StepResult::new(
                     pc,
                     self.regs.pc().into(),
                     self.cycle - cycle,
                 ))
             }
-        }
+        };
+
+        let sp = self.regs.sp() as usize;
+        self.call_stack.update(pc, sp, self.flow);
+
+        self.reg_hit = !self.reg_watches.is_empty() && self.reg_watches.check(&self.regs, pc);
+
+        ret
     }
 
     pub fn reset(&mut self) {
@@ -220,10 +274,17 @@ where
             mem,
             regs,
             cycle: 0,
+            instructions: 0,
             irq: false,
             reset: false,
             nmi: false,
             wai: false,
+            prev_pc: 0,
+            flow: Flow::Normal,
+            call_stack: CallStack::new(),
+            stack_guard: None,
+            reg_watches: RegWatches::new(),
+            reg_hit: false,
         }
     }
 
@@ -245,14 +306,14 @@ where
     #[inline]
     pub fn fetch_rel_addr(&mut self) -> MemResult<u16> {
         let pc = self.regs.pc();
-        let byte = self.mem.load_byte(pc as usize)?;
+        let byte = self.mem.fetch_operand_byte(pc as usize)?;
         let res = pc.wrapping_add(u8_sign_extend(byte));
         Ok(res)
     }
 
     pub fn fetch_byte(&mut self) -> MemResult<u8> {
         let pc = self.regs.pc();
-        let byte = self.mem.load_byte(pc as usize)?;
+        let byte = self.mem.fetch_operand_byte(pc as usize)?;
         self.inc_pc();
         Ok(byte)
     }
@@ -261,10 +322,23 @@ where
         &self.mem
     }
 
+    fn guard_stack(&mut self, addr: u16, val: u8, push: bool) -> CpuResult<()> {
+        let pc = self.prev_pc;
+
+        if let Some(g) = &mut self.stack_guard {
+            if push {
+                g.check_push(pc, addr as usize, val.into(), false)?
+            } else {
+                g.check_pop(pc, addr as usize, val.into(), false)?
+            }
+        }
+        Ok(())
+    }
+
     // [[SP]] ← [val(LO)],
     // [[SP] - 1] ← [val(HI)],
     // [SP] ← [SP] - 2,
-    pub fn push_word(&mut self, val: u16) -> MemResult<()> {
+    pub fn push_word(&mut self, val: u16) -> CpuResult<()> {
         let lo = (val & 0xff) as u8;
         let hi = (val >> 8) as u8;
 
@@ -275,25 +349,369 @@ where
     // [res(HI)] ← [[SP] + 1],
     // [res(LO)] ← [[SP] + 2],
     // [SP] ← [SP] + 2
-    pub fn pop_word(&mut self) -> MemResult<u16> {
+    pub fn pop_word(&mut self) -> CpuResult<u16> {
         let hi = self.pop_byte()?;
         let lo = self.pop_byte()?;
         Ok(lo as u16 | ((hi as u16) << 8))
     }
 
     // [[SP]] ← [A], [SP] ← [SP] - 1
-    pub fn push_byte(&mut self, val: u8) -> MemResult<()> {
+    pub fn push_byte(&mut self, val: u8) -> CpuResult<()> {
         let sp = self.regs.sp();
+        self.guard_stack(sp, val, true)?;
         self.mem.store_byte(sp as usize, val)?;
         self.regs.set_sp(sp.wrapping_sub(1));
         Ok(())
     }
 
     //[SP] ← [SP] + 1, [A] ← [[SP]]
-    pub fn pop_byte(&mut self) -> MemResult<u8> {
+    pub fn pop_byte(&mut self) -> CpuResult<u8> {
         let sp = self.regs.sp().wrapping_add(1);
         let byte = self.mem.load_byte(sp as usize)?;
+        self.guard_stack(sp, byte, false)?;
         self.regs.set_sp(sp);
         Ok(byte)
     }
 }
+
+impl<M, R> ReplayTarget for Machine<M, R>
+where
+    M: MemoryIO,
+    R: RegisterFileTrait + StatusRegTrait,
+{
+    fn set_pin(&mut self, pin: Pin, level: bool) {
+        match pin {
+            Pin::Irq => self.irq = level,
+            Pin::Nmi => self.nmi = level,
+            Pin::Reset => self.reset = level,
+            // No FIRQ on a 6800
+            Pin::Firq => (),
+        }
+    }
+
+    fn state_hash(&self) -> String {
+        let r = &self.regs;
+
+        let mut bytes = vec![
+            r.get_reg_8(RegEnum::A),
+            r.get_reg_8(RegEnum::B),
+            r.get_reg_8(RegEnum::SR),
+        ];
+
+        for reg in [RegEnum::X, RegEnum::SP, RegEnum::PC] {
+            bytes.extend(r.get_reg_16(reg).to_be_bytes())
+        }
+
+        bytes.extend([self.irq, self.nmi, self.reset, self.wai].map(u8::from));
+
+        hash_state(&bytes, &self.mem)
+    }
+}
+
+impl<M, R> InterruptTarget for Machine<M, R>
+where
+    M: MemoryIO,
+    R: RegisterFileTrait + StatusRegTrait,
+{
+    fn is_masked(&self, pin: Pin) -> bool {
+        pin == Pin::Irq && self.regs.i()
+    }
+
+    fn taken_interrupt(&self) -> Option<Pin> {
+        match self.flow {
+            Flow::Interrupt {
+                vector: IRQ_VEC, ..
+            } => Some(Pin::Irq),
+            Flow::Interrupt {
+                vector: NMI_VEC, ..
+            } => Some(Pin::Nmi),
+            _ => None,
+        }
+    }
+}
+
+impl<M, R> GoldenTarget for Machine<M, R>
+where
+    M: MemoryIO,
+    R: RegisterFileTrait + StatusRegTrait,
+{
+    type Err = CpuErrKind;
+
+    fn cycles(&self) -> usize {
+        self.cycle
+    }
+
+    fn step(&mut self) -> CpuResult<()> {
+        Machine::step(self).map(|_| ())
+    }
+
+    fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    fn regs(&self) -> std::collections::BTreeMap<String, u64> {
+        let r = &self.regs;
+
+        [
+            ("a", r.get_reg_8(RegEnum::A) as u64),
+            ("b", r.get_reg_8(RegEnum::B) as u64),
+            ("x", r.get_reg_16(RegEnum::X) as u64),
+            ("sp", r.get_reg_16(RegEnum::SP) as u64),
+            ("pc", r.get_reg_16(RegEnum::PC) as u64),
+            ("sr", r.get_reg_8(RegEnum::SR) as u64),
+        ]
+        .into_iter()
+        .map(|(n, v)| (n.to_string(), v))
+        .collect()
+    }
+
+    fn mem(&self) -> &dyn MemoryIO {
+        &self.mem
+    }
+
+    fn mem_mut(&mut self) -> &mut dyn MemoryIO {
+        &mut self.mem
+    }
+}
+
+impl<M, R> RunTarget for Machine<M, R>
+where
+    M: MemoryIO,
+    R: RegisterFileTrait + StatusRegTrait,
+{
+    fn pc(&self) -> usize {
+        self.regs.get_reg_16(RegEnum::PC) as usize
+    }
+
+    fn is_illegal_opcode(err: &CpuErrKind) -> bool {
+        matches!(err, CpuErrKind::IllegalInstruction { .. })
+    }
+
+    fn reg_watch_hit(&self) -> bool {
+        self.reg_hit
+    }
+}
+
+impl<M, R> StepTarget for Machine<M, R>
+where
+    M: MemoryIO,
+    R: RegisterFileTrait + StatusRegTrait,
+{
+    fn flow(&self) -> Flow {
+        self.flow
+    }
+
+    fn call_depth(&self) -> usize {
+        self.call_stack.depth()
+    }
+}
+
+impl<M, R> FlagTarget for Machine<M, R>
+where
+    M: MemoryIO,
+    R: RegisterFileTrait + StatusRegTrait,
+{
+    const FLAG_LAYOUT: &'static str = "--HINZVC";
+
+    fn flags(&self) -> u64 {
+        self.regs.get_reg_8(RegEnum::SR) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::RegisterFile;
+    use emucore::byteorder::BigEndian;
+    use emucore::mem::{MemBlock, MemErrorTypes, MemMap, MemMapIO, WaitStates};
+    use emucore::regwatch::RegCondition;
+    use emucore::run::{StopConditions, StopReason};
+
+    /// Ram at $0000-$7fff and $ff00-$ffff, pc at $1000, NMI enters $2000
+    fn machine(code: &[u8]) -> Machine<MemMap, RegisterFile> {
+        let mut mem = MemMap::new();
+        for (name, r) in [("ram", 0..0x8000), ("vectors", 0xff00..0x1_0000)] {
+            mem.add_memory(Box::new(MemBlock::<BigEndian>::new(name, false, &r)));
+        }
+        mem.upload(0x1000, code).unwrap();
+        mem.store_word(NMI_VEC, 0x2000).unwrap();
+        // rti
+        mem.store_byte(0x2000, 0x3b).unwrap();
+
+        let regs = RegisterFile {
+            pc: 0x1000,
+            sp: 0x7fff,
+            ..Default::default()
+        };
+        Machine::new(mem, regs)
+    }
+
+    #[test]
+    fn flow_and_step_over() {
+        // jsr $1010, nop ... $1010: nop, rts
+        let mut m = machine(&[0xbd, 0x10, 0x10, 0x01]);
+        m.mem.upload(0x1010, &[0x01, 0x39]).unwrap();
+
+        m.step().unwrap();
+        assert_eq!(
+            m.flow,
+            Flow::Call {
+                dest: 0x1010,
+                ret: 0x1003
+            }
+        );
+        assert_eq!(m.call_depth(), 1);
+
+        let s = StopConditions::new().step_out(&mut m).unwrap();
+        assert_eq!((s.reason, s.pc), (StopReason::Step, 0x1003));
+        assert_eq!(m.flow, Flow::Return { dest: 0x1003 });
+        assert_eq!(m.call_depth(), 0);
+
+        m.regs.pc = 0x1000;
+        let s = StopConditions::new().step_over(&mut m).unwrap();
+        assert_eq!((s.pc, s.instructions), (0x1003, 6));
+    }
+
+    #[test]
+    fn step_cycles() {
+        // nops
+        let mut m = machine(&[0x01; 8]);
+
+        m.step_cycles(3).unwrap();
+        assert_eq!((m.cycle, m.instructions), (4, 2));
+
+        m.step_cycles(3).unwrap();
+        assert_eq!((m.cycle, m.instructions), (8, 4));
+    }
+
+    #[test]
+    fn nmi() {
+        // nop
+        let mut m = machine(&[0x01]);
+        m.set_pin(Pin::Nmi, true);
+
+        m.step().unwrap();
+        assert_eq!(m.taken_interrupt(), Some(Pin::Nmi));
+        assert_eq!(
+            m.flow,
+            Flow::Interrupt {
+                vector: NMI_VEC,
+                dest: 0x2000,
+                ret: 0x1000
+            }
+        );
+        assert_eq!((m.pc(), m.regs.sp, m.instructions), (0x2000, 0x7ff8, 0));
+
+        m.step().unwrap();
+        assert_eq!(m.flow, Flow::ReturnFromInterrupt { dest: 0x1000 });
+        assert_eq!((m.pc(), m.regs.sp, m.call_depth()), (0x1000, 0x7fff, 0));
+    }
+
+    #[test]
+    fn stack_guard() {
+        // psha x 5
+        let mut m = machine(&[0x36; 5]);
+        m.stack_guard = Some(StackGuard::new("S", 0x7ffc..0x8000));
+
+        for _ in 0..4 {
+            m.step().unwrap();
+        }
+
+        match m.step() {
+            Err(CpuErrKind::Stack(e)) => {
+                assert_eq!((e.kind, e.pc, e.addr), ("overflow", 0x1004, 0x7ffb));
+            }
+            r => panic!("expected a stack overflow, got {:?}", r.map(|_| ())),
+        }
+        assert_eq!(m.stack_guard.unwrap().high_water(), 4);
+    }
+
+    #[test]
+    fn fetch_error() {
+        // jmp $9000, which isn't mapped
+        let mut m = machine(&[0x7e, 0x90, 0x00]);
+        m.step().unwrap();
+
+        match m.step() {
+            Err(CpuErrKind::IllegalFetch { pc, prev_pc, err }) => {
+                assert_eq!((pc, prev_pc), (0x9000, 0x1000));
+                assert_eq!(err, MemErrorTypes::IllegalAddress(0x9000));
+            }
+            r => panic!("expected a fetch error, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reg_watch_stops_run() {
+        // ldaa #1, ldaa #$12, nop
+        let mut m = machine(&[0x86, 0x01, 0x86, 0x12, 0x01]);
+        m.reg_watches.add(RegEnum::A, RegCondition::Equal(0x12));
+
+        let s = StopConditions::new()
+            .instructions(3)
+            .reg_watches()
+            .run_until(&mut m)
+            .unwrap();
+        assert_eq!((s.reason, s.condition, s.pc), (StopReason::RegWatch, Some(1), 0x1004));
+
+        let hits = m.reg_watches.take_hits();
+        assert_eq!((hits.len(), hits[0].pc, hits[0].new), (1, 0x1002, 0x12));
+    }
+
+    #[test]
+    fn wait_states() {
+        // nop, ldaa #$12, staa $10
+        let mut m = machine(&[0x01, 0x86, 0x12, 0x97, 0x10]);
+        m.mem.set_wait_states(0x1000..0x1100, WaitStates::new(1, 0));
+        m.mem.set_wait_states(0x0000..0x0100, WaitStates::new(0, 2));
+
+        let cycles: Vec<_> = (0..3)
+            .map(|_| {
+                let c = m.cycles();
+                m.step().unwrap();
+                m.cycles() - c
+            })
+            .collect();
+
+        assert_eq!(cycles, [2 + 1, 2 + 2, 4 + 2 + 2]);
+
+        // Interrupt entry pushes seven bytes to the stretched stack
+        m.mem.set_wait_states(0x7f00..0x8000, WaitStates::new(0, 1));
+        m.set_pin(Pin::Nmi, true);
+        let c = m.cycles();
+        assert!(matches!(m.step().unwrap(), StepResult::Nmi(0x2000)));
+        assert_eq!(m.cycles() - c, 1 + 7);
+    }
+
+    #[test]
+    fn flag_check() {
+        use crate::cpu::opcodes::load_isa;
+        use emucore::flagcheck::FlagChecker;
+
+        let isa = load_isa().unwrap();
+        let flags = |op| isa.get(op).flags.as_str();
+        // HINZVC
+        assert_eq!(
+            [
+                flags(0x0c),
+                flags(0x0d),
+                flags(0x4f),
+                flags(0x43),
+                flags(0x86)
+            ],
+            ["-----0", "-----1", "--0100", "--++01", "--++0-"]
+        );
+
+        // sev, ldaa #$80, sec, clra, coma, tsta, clc, sei
+        let mut m = machine(&[0x0b, 0x86, 0x80, 0x0d, 0x4f, 0x43, 0x4d, 0x0c, 0x0f]);
+        let mut checker = FlagChecker::new(&isa);
+
+        assert_eq!(
+            checker.run(&mut m, 8).unwrap(),
+            0,
+            "{:?}",
+            checker.violations()
+        );
+        assert_eq!(m.flags(), 0b0001_1000);
+    }
+}
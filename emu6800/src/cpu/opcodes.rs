@@ -1,3 +1,4 @@
+use emucore::flow::Flow;
 use emucore::mem::MemoryIO;
 use emucore::sha1::digest::typenum::operator_aliases;
 
@@ -5,6 +6,8 @@ use super::{Bus, RegisterFileTrait, StatusRegTrait};
 use super::{CpuResult, Machine};
 use crate::cpu::{AccA, AccB};
 use crate::cpu_core::{AddrModeEnum, Isa, IsaDatabase, RegEnum};
+use emucore::isa::{IsaDbase, IsaOp, IsaResult};
+use emucore::traits::InstructionDbaseTrait;
 
 lazy_static::lazy_static! {
     pub static ref ISA_DBASE : IsaDatabase = {
@@ -13,6 +16,84 @@ lazy_static::lazy_static! {
         IsaDatabase::new(&isa)
     };
 }
+
+/// The instruction set as an emucore ISA database
+pub fn load_isa() -> IsaResult<IsaDbase<AddrModeEnum>> {
+    use crate::cpu_core::StatusReg;
+    use AddrModeEnum::*;
+
+    let txt = include_str!("../../resources/opcodes6800.json");
+    let isa: Isa = serde_json::from_str(txt)?;
+
+    let flags = [
+        StatusReg::H,
+        StatusReg::I,
+        StatusReg::N,
+        StatusReg::Z,
+        StatusReg::V,
+        StatusReg::C,
+    ];
+
+    let mut ops = vec![];
+
+    for (m, ins) in isa.instructions.iter() {
+        let name = format!("{m:?}");
+        let fixed = fixed_flags(&name);
+
+        let written: String = flags
+            .iter()
+            .map(|f| match fixed.iter().find(|(ff, _)| ff == f) {
+                Some((_, c)) => *c,
+                None if ins.flags_written.contains(*f) => '+',
+                None => '-',
+            })
+            .collect();
+
+        for (amode, data) in ins.addr_modes.iter() {
+            let op = IsaOp::new(&name, *amode, data.opcode, data.cycles, data.size);
+            ops.push(op.with_flags(&written));
+        }
+    }
+
+    let modes = [
+        (Immediate8, 2),
+        (Immediate16, 3),
+        (Direct, 2),
+        (Extended, 3),
+        (Indexed, 2),
+        (Inherent, 1),
+        (Relative, 2),
+    ];
+
+    let dbase = IsaDbase::from_data(ops, IsaOp::new("unknown", Illegal, 0, 1, 1))
+        .with_flag_order("HINZVC")
+        .with_modes(modes.map(|(m, s)| (m, Some(s))));
+
+    Ok(dbase)
+}
+
+/// Flags an instruction always clears or sets
+/// opcodes6800.json only says which flags are written
+fn fixed_flags(name: &str) -> &'static [(crate::cpu_core::StatusReg, char)] {
+    use crate::cpu_core::StatusReg as F;
+
+    match name {
+        "Clc" => &[(F::C, '0')],
+        "Cli" => &[(F::I, '0')],
+        "Clv" => &[(F::V, '0')],
+        "Sec" => &[(F::C, '1')],
+        "Sei" | "Swi" => &[(F::I, '1')],
+        "Sev" => &[(F::V, '1')],
+        "Clr" | "ClrA" | "ClrB" => &[(F::N, '0'), (F::Z, '1'), (F::V, '0'), (F::C, '0')],
+        "Com" | "ComA" | "ComB" => &[(F::V, '0'), (F::C, '1')],
+        "Tst" | "TstA" | "TstB" => &[(F::V, '0'), (F::C, '0')],
+        "AndA" | "AndB" | "BitA" | "BitB" | "EorA" | "EorB" | "OraA" | "OraB" | "LdaA"
+        | "LdaB" | "Lds" | "Ldx" | "StaA" | "StaB" | "Sts" | "Stx" | "Tab" | "Tba" => {
+            &[(F::V, '0')]
+        }
+        _ => &[],
+    }
+}
 ////////////////////////////////////////////////////////////////////////////////
 // Helpers
 
@@ -306,6 +387,7 @@ where
             .set_x(x)
             .set_pc(pc);
 
+        self.m.flow = Flow::ReturnFromInterrupt { dest: pc as usize };
         Ok(())
     }
 
@@ -322,6 +404,10 @@ where
         let pc = self.m.regs.pc();
         self.m.push_word(pc)?;
         self.m.regs.set_pc(addr);
+        self.m.flow = Flow::Call {
+            dest: addr as usize,
+            ret: pc as usize,
+        };
         Ok(())
     }
 
@@ -343,6 +429,11 @@ where
         let regs = self.regs_mut();
         regs.set_pc(addr);
 
+        self.m.flow = Flow::Interrupt {
+            vector: 0xfffa,
+            dest: addr as usize,
+            ret: pc as usize,
+        };
         Ok(())
     }
 
@@ -352,6 +443,10 @@ where
         let pc = self.m.regs.pc();
         self.m.push_word(pc)?;
         self.m.regs.set_pc(addr);
+        self.m.flow = Flow::Call {
+            dest: addr as usize,
+            ret: pc as usize,
+        };
         Ok(())
     }
 
@@ -359,6 +454,9 @@ where
     pub fn rts(&mut self) -> CpuResult<()> {
         let addr = self.m.pop_word()?;
         self.m.regs.set_pc(addr);
+        self.m.flow = Flow::Return {
+            dest: addr as usize,
+        };
         Ok(())
     }
 }
@@ -381,7 +479,7 @@ where
 
     #[inline]
     pub fn post_com(&mut self, new: u8) -> CpuResult<()> {
-        self.set_nz_from_u8(new).clc().clv();
+        self.set_nz_from_u8(new).sec().clv();
         Ok(())
     }
 
@@ -891,13 +989,13 @@ where
 
     fn fetch_operand_8_fl(&mut self) -> CpuResult<u8> {
         let val = self.fetch_operand()?;
-        self.set_nz_from_u8(val).clc();
+        self.set_nz_from_u8(val).clv();
         Ok(val)
     }
 
     fn fetch_operand_16_fl(&mut self) -> CpuResult<u16> {
         let val = self.fetch_operand_16()?;
-        self.set_nz_from_u16(val).clc();
+        self.set_nz_from_u16(val).clv();
         Ok(val)
     }
 
@@ -3,10 +3,11 @@ use std::{fmt::Debug, hash::Hash, str::FromStr};
 use serde::{Deserialize, Serialize};
 use crate::cpu_core::{ StatusReg, RegEnum };
 use super::StatusRegTrait;
+use emucore::traits::RegEnumTrait;
 
 
 ////////////////////////////////////////////////////////////////////////////////
-pub trait RegisterFileTrait : std::fmt::Display {
+pub trait RegisterFileTrait : std::fmt::Display + emucore::traits::RegisterFileTrait<RegEnum> {
 
     fn set_reg_8(&mut self, r: RegEnum, val: u8) -> &mut Self;
     fn set_reg_16(&mut self, r: RegEnum, val: u16) -> &mut Self;
@@ -191,6 +192,32 @@ impl RegisterFileTrait for RegisterFile {
 
 }
 
+impl RegEnumTrait for RegEnum {
+    fn get_size_bytes(&self) -> usize {
+        use RegEnum::*;
+        match self {
+            A | B | SR => 1,
+            X | PC | SP => 2,
+        }
+    }
+}
+
+impl emucore::traits::RegisterFileTrait<RegEnum> for RegisterFile {
+    fn get(&self, r: &RegEnum) -> u64 {
+        match r.get_size_bytes() {
+            1 => self.get_reg_8(*r) as u64,
+            _ => self.get_reg_16(*r) as u64,
+        }
+    }
+
+    fn set(&mut self, r: &RegEnum, v: u64) {
+        match r.get_size_bytes() {
+            1 => self.set_reg_8(*r, v as u8),
+            _ => self.set_reg_16(*r, v as u16),
+        };
+    }
+}
+
 #[derive(Clone,Debug,PartialEq, Default, Copy)]
 pub struct RegisterFile {
     pub a: u8,
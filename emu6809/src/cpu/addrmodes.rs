@@ -82,7 +82,7 @@ impl AddressLines for Direct {
         regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u16, CpuErr> {
-        let index = u16::from(ins.fetch_byte(mem));
+        let index = u16::from(ins.fetch_byte(mem)?);
         Ok(regs.get_dp_ptr().wrapping_add(index))
     }
 
@@ -211,7 +211,7 @@ impl AddressLines for Immediate8 {
         _regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u8, CpuErr> {
-        Ok(ins.fetch_byte(mem))
+        ins.fetch_byte(mem)
     }
 
     fn diss(mem: &dyn MemoryIO, ins: &mut InstructionDecoder) -> String {
@@ -254,7 +254,7 @@ impl AddressLines for Inherent {
         _regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u8, CpuErr> {
-        Ok(ins.fetch_byte(mem))
+        ins.fetch_byte(mem)
     }
 
     fn diss(_mem: &dyn MemoryIO, _ins: &mut InstructionDecoder) -> String {
@@ -274,7 +274,7 @@ impl AddressLines for RegisterSet {
         _regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u8, CpuErr> {
-        Ok(ins.fetch_byte(mem))
+        ins.fetch_byte(mem)
     }
 
     fn diss(_mem: &dyn MemoryIO, _ins: &mut InstructionDecoder) -> String {
@@ -294,7 +294,7 @@ impl AddressLines for RegisterPair {
         _regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u8, CpuErr> {
-        Ok(ins.fetch_byte(mem))
+        ins.fetch_byte(mem)
     }
 
     fn diss(_mem: &dyn MemoryIO, _ins: &mut InstructionDecoder) -> String {
@@ -333,7 +333,7 @@ impl Indexed {
         regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<(u16, IndexedFlags), CpuErr> {
-        let index_mode_id = ins.fetch_byte(mem);
+        let index_mode_id = ins.fetch_byte(mem)?;
 
         let index_mode = IndexedFlags::new(index_mode_id);
 
@@ -383,7 +383,7 @@ impl Indexed {
 
             IndexModes::RAddi8(r) => {
                 // format!("{},{:?}",self.fetch_byte_as_i16(mem) as i8, r)
-                let v = ins.fetch_byte_as_i16(mem) as u16;
+                let v = ins.fetch_byte_as_i16(mem)? as u16;
                 Ok((regs.get(&r).wrapping_add(v), index_mode))
             }
 
@@ -401,7 +401,7 @@ impl Indexed {
 
             IndexModes::PCAddi8 => {
                 // format!("PC,{:?}",diss.fetch_byte(mem) as i8)
-                let offset = ins.fetch_byte_as_i16(mem) as u16;
+                let offset = ins.fetch_byte_as_i16(mem)? as u16;
                 Ok((regs.pc.wrapping_add(offset), index_mode))
             }
 
@@ -559,7 +559,7 @@ impl AddressLines for Relative {
         _regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u8, CpuErr> {
-        Ok(ins.fetch_byte(mem))
+        ins.fetch_byte(mem)
     }
 
     fn diss(mem: &dyn MemoryIO, ins: &mut InstructionDecoder) -> String {
@@ -580,7 +580,7 @@ impl AddressLines for Relative16 {
         _regs: &mut Regs,
         ins: &mut InstructionDecoder,
     ) -> Result<u8, CpuErr> {
-        Ok(ins.fetch_byte(mem))
+        ins.fetch_byte(mem)
     }
 
     fn fetch_word(
@@ -39,13 +39,21 @@ use super::{
     InstructionDecoder, RegEnum, RegisterPair, RegisterSet, Regs, Relative, Relative16,
 };
 
+use emucore::callstack::CallStack;
+use emucore::flagcheck::FlagTarget;
+use emucore::flow::Flow;
+use emucore::golden::GoldenTarget;
+use emucore::intsched::InterruptTarget;
+use emucore::irqline::InterruptLines;
 use emucore::mem::{MemErrorTypes, MemoryIO};
+use emucore::regwatch::RegWatches;
+use emucore::replay::{hash_state, Pin, ReplayTarget};
+use emucore::run::{RunTarget, StepTarget};
+use emucore::stackguard::{StackErr, StackGuard};
 
 
 
-// use std::cell::RefCell;
-// use std::rc::Rc;
-
+use std::ops::{Deref, DerefMut};
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone, PartialEq)]
@@ -56,8 +64,28 @@ pub enum CpuErr {
     Unimplemented(usize),
     #[error("Illegal addressing mode")]
     IllegalAddressingMode,
+    #[error("Bad instruction tables: {0}")]
+    Isa(String),
     #[error(transparent)]
     Memory(#[from] MemErrorTypes),
+    #[error(transparent)]
+    Stack(#[from] StackErr),
+    #[error("Instruction fetch from ${pc:04x}, previous pc ${prev_pc:04x}: {err}")]
+    IllegalFetch {
+        pc: usize,
+        prev_pc: usize,
+        err: MemErrorTypes,
+    },
+}
+
+impl CpuErr {
+    /// Memory errors while decoding are reported as bad fetches
+    fn into_fetch_err(self, pc: usize, prev_pc: usize) -> Self {
+        match self {
+            CpuErr::Memory(err) => CpuErr::IllegalFetch { pc, prev_pc, err },
+            _ => self,
+        }
+    }
 }
 
 // use cpu::alu;
@@ -94,16 +122,77 @@ pub struct Pins {
     pub nmi: bool,
     pub reset: bool,
     pub waiting_for_irq: bool,
+    /// Devices wired to IRQ, FIRQ and NMI, sensed along with the pins above
+    pub lines: InterruptLines,
+}
+
+impl Pins {
+    /// Latch an NMI edge from a device the same as one from the pin
+    fn sense(&mut self) {
+        if self.lines.take_edge(Pin::Nmi) {
+            self.nmi = true
+        }
+    }
+
+    fn irq_asserted(&self) -> bool {
+        self.irq || self.lines.level(Pin::Irq)
+    }
+
+    fn firq_asserted(&self) -> bool {
+        self.firq || self.lines.level(Pin::Firq)
+    }
+}
+
+/// Part of the machine the cpu either borrows or owns
+pub enum Slot<'a, T: ?Sized> {
+    Borrowed(&'a mut T),
+    Owned(Box<T>),
+}
+
+impl<T: ?Sized> Deref for Slot<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Slot::Borrowed(r) => r,
+            Slot::Owned(b) => b,
+        }
+    }
 }
 
+impl<T: ?Sized> DerefMut for Slot<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        match self {
+            Slot::Borrowed(r) => r,
+            Slot::Owned(b) => b,
+        }
+    }
+}
+
+/// A 6809 that owns its memory, registers and pins
+/// It's Send and 'static so it can live on a runner thread
+pub type Machine = Context<'static>;
+
 pub struct Context<'a> {
-    pub regs: &'a mut Regs,
-    pub pins: &'a mut Pins,
+    pub regs: Slot<'a, Regs>,
+    pub pins: Slot<'a, Pins>,
     pub ins: InstructionDecoder,
     pub cycles: usize,
+    /// Opcodes executed, interrupt entries and reset aren't counted
     pub instructions: usize,
+    /// Flow of control recorded by the last step
+    pub flow: Flow,
+    /// Shadow call stack built from flow
+    pub call_stack: CallStack,
+    /// Optional bounds checks for the S and U stacks
+    pub s_guard: Option<StackGuard>,
+    pub u_guard: Option<StackGuard>,
+    /// Register watchpoints, hits are left here after each step
+    pub reg_watches: RegWatches<RegEnum>,
+    /// A register watch triggered on the last step
+    pub reg_hit: bool,
     // TODO This should generic with compile time dispatch
-    pub mem: &'a mut dyn MemoryIO,
+    pub mem: Slot<'a, dyn MemoryIO + Send + 'a>,
 }
 
 // use serde::Deserializer;
@@ -123,11 +212,11 @@ impl<'a> Context<'a> {
     }
 
     fn store_byte<A: AddressLines>(&mut self, v: u8) -> CpuResult<u16> {
-        A::store_byte(self.mem, self.regs, &mut self.ins, v)
+        A::store_byte(&mut *self.mem, &mut self.regs, &mut self.ins, v)
     }
 
     fn store_word<A: AddressLines>(&mut self, v: u16) -> CpuResult<u16> {
-        A::store_word(self.mem, self.regs, &mut self.ins, v)
+        A::store_word(&mut *self.mem, &mut self.regs, &mut self.ins, v)
     }
 
     fn fetch_word_as_i16<A: AddressLines>(&mut self) -> CpuResult<i16> {
@@ -136,15 +225,15 @@ impl<'a> Context<'a> {
     }
 
     fn fetch_byte<A: AddressLines>(&mut self) -> CpuResult<u8> {
-        A::fetch_byte(self.mem, self.regs, &mut self.ins)
+        A::fetch_byte(&mut *self.mem, &mut self.regs, &mut self.ins)
     }
 
     fn fetch_word<A: AddressLines>(&mut self) -> CpuResult<u16> {
-        A::fetch_word(self.mem, self.regs, &mut self.ins)
+        A::fetch_word(&mut *self.mem, &mut self.regs, &mut self.ins)
     }
 
     fn ea<A: AddressLines>(&mut self) -> CpuResult<u16> {
-        A::ea(self.mem, self.regs, &mut self.ins)
+        A::ea(&mut *self.mem, &mut self.regs, &mut self.ins)
     }
 
     fn op16_2<A: AddressLines>(
@@ -327,9 +416,37 @@ impl<'a> Context<'a> {
         }
     }
 
+    fn guard_stack(
+        &mut self,
+        is_system: bool,
+        addr: u16,
+        val: u16,
+        word: bool,
+        push: bool,
+    ) -> CpuResult<()> {
+        let pc = self.regs.pc as usize;
+        let addr = addr as usize;
+
+        let guard = if is_system {
+            &mut self.s_guard
+        } else {
+            &mut self.u_guard
+        };
+
+        if let Some(g) = guard {
+            if push {
+                g.check_push(pc, addr, val, word)?
+            } else {
+                g.check_pop(pc, addr, val, word)?
+            }
+        }
+        Ok(())
+    }
+
     fn push_byte(&mut self, v: u8, is_system: bool) -> CpuResult<()> {
         let sp = self.get_stack(is_system);
         let sp = sp.wrapping_sub(1);
+        self.guard_stack(is_system, sp, v.into(), false, true)?;
         self.mem.store_byte(sp.into(), v)?;
         self.set_stack(sp, is_system);
         Ok(())
@@ -338,6 +455,7 @@ impl<'a> Context<'a> {
     fn push_word(&mut self, v: u16, is_system: bool) -> CpuResult<()> {
         let sp = self.get_stack(is_system);
         let sp = sp.wrapping_sub(2);
+        self.guard_stack(is_system, sp, v, true, true)?;
         self.mem.store_word(sp.into(), v)?;
         self.set_stack(sp, is_system);
         Ok(())
@@ -346,6 +464,7 @@ impl<'a> Context<'a> {
     fn pop_byte(&mut self, is_system: bool) -> CpuResult<u8> {
         let sp = self.get_stack(is_system);
         let r = self.mem.load_byte(sp as usize)?;
+        self.guard_stack(is_system, sp, r.into(), false, false)?;
         self.set_stack(sp.wrapping_add(1), is_system);
         Ok(r)
     }
@@ -354,6 +473,7 @@ impl<'a> Context<'a> {
         let sp = self.get_stack(is_system);
 
         let r = self.mem.load_word(sp as usize)?;
+        self.guard_stack(is_system, sp, r, true, false)?;
 
         self.set_stack(sp.wrapping_add(2), is_system);
 
@@ -504,14 +624,19 @@ impl<'a> Context<'a> {
     fn rts<A: AddressLines>(&mut self) -> CpuResult<()> {
         let pc = self.pop_word(true)?;
         self.set_next_pc(pc as usize);
+        self.flow = Flow::Return { dest: pc as usize };
         Ok(())
     }
 
     fn bsr<A: AddressLines>(&mut self) -> CpuResult<()> {
         let offset = self.fetch_byte_as_i16::<A>()?;
         let next_op = self.get_pc();
-        self.push_word((next_op & 0xfff) as u16, true)?;
+        self.push_word(next_op as u16, true)?;
         self.set_next_pc_rel(offset);
+        self.flow = Flow::Call {
+            dest: self.get_pc(),
+            ret: next_op,
+        };
         Ok(())
     }
 
@@ -805,6 +930,10 @@ impl<'a> Context<'a> {
         let next_op = (self.get_pc() & 0xffff) as u16;
         self.push_word(next_op, true)?;
         self.set_next_pc(dest as usize);
+        self.flow = Flow::Call {
+            dest: dest as usize,
+            ret: next_op as usize,
+        };
         Ok(())
     }
 
@@ -815,6 +944,10 @@ impl<'a> Context<'a> {
         let next_op = (self.get_pc() & 0xffff) as u16;
         self.push_word(next_op, true)?;
         self.set_next_pc_rel(offset);
+        self.flow = Flow::Call {
+            dest: self.get_pc(),
+            ret: next_op as usize,
+        };
         Ok(())
     }
 
@@ -999,6 +1132,7 @@ impl<'a> Context<'a> {
         if sf.contains(StackFlags::PC) {
             let i0 = self.pop_word(is_system)?;
             self.set_next_pc(i0 as usize);
+            self.flow = Flow::Return { dest: i0 as usize };
             self.cycles += 2;
         }
 
@@ -1256,7 +1390,8 @@ impl<'a> Context<'a> {
 
         self.regs.flags |= flags;
 
-        push16!(self.get_pc());
+        let ret = self.get_pc();
+        push16!(ret);
         push16!(self.regs.u);
         push16!(self.regs.y);
         push16!(self.regs.x);
@@ -1269,6 +1404,11 @@ impl<'a> Context<'a> {
 
         let pc = self.mem.load_word(vec)?;
         self.set_next_pc(pc as usize);
+        self.flow = Flow::Interrupt {
+            vector: vec,
+            dest: pc as usize,
+            ret,
+        };
         Ok(())
     }
 
@@ -1314,6 +1454,7 @@ impl<'a> Context<'a> {
         let pc = self.pop_word(true)? as usize;
 
         self.set_next_pc(pc);
+        self.flow = Flow::ReturnFromInterrupt { dest: pc };
 
         Ok(())
     }
@@ -1371,6 +1512,11 @@ impl<'a> Context<'a> {
             let pc = self.mem.load_word(vector)? as usize;
             // set the PC
             self.set_next_pc(pc);
+            self.flow = Flow::Interrupt {
+                vector,
+                dest: pc,
+                ret: self.regs.pc as usize,
+            };
         }
 
         Ok(())
@@ -1378,13 +1524,13 @@ impl<'a> Context<'a> {
 
     fn irq(&mut self) -> CpuResult<()> {
         let r = self.gen_irq(Flags::I, true, 0xfff8);
-        self.ins.cycles += 19;
+        self.ins.cycles = 19;
         r
     }
 
     fn firq(&mut self) -> CpuResult<()> {
         let r = self.gen_irq(Flags::F, false, 0xfff6);
-        self.ins.cycles += 10;
+        self.ins.cycles = 10;
         r
     }
 
@@ -1397,7 +1543,15 @@ impl<'a> Context<'a> {
         self.regs.flags.set(Flags::E, true);
         self.push_regs(StackFlags::CC, true)?;
         self.regs.flags.set(Flags::I | Flags::F, true);
-        self.ins.cycles += 19;
+
+        let pc = self.mem.load_word(VEC_NMI)? as usize;
+        self.set_next_pc(pc);
+        self.flow = Flow::Interrupt {
+            vector: VEC_NMI,
+            dest: pc,
+            ret: self.regs.pc as usize,
+        };
+        self.ins.cycles = 19;
         Ok(())
     }
 }
@@ -1417,12 +1571,35 @@ impl<'a> Context<'a> {
         self.ins.next_addr & 0xffff
     }
 
+    /// High water marks for any guarded stacks
+    pub fn stack_report(&self) -> String {
+        [&self.s_guard, &self.u_guard]
+            .into_iter()
+            .flatten()
+            .map(|g| g.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     pub fn new(
-        mem: &'a mut dyn MemoryIO,
+        mem: &'a mut (dyn MemoryIO + Send),
         regs: &'a mut Regs,
         pins: &'a mut Pins,
     ) -> CpuResult<Context<'a>> {
-        let ins = InstructionDecoder::new_from_read_mem(regs.pc as usize, mem)?;
+        Self::from_slots(Slot::Borrowed(mem), Slot::Borrowed(regs), Slot::Borrowed(pins))
+    }
+
+    fn from_slots(
+        mut mem: Slot<'a, dyn MemoryIO + Send + 'a>,
+        regs: Slot<'a, Regs>,
+        pins: Slot<'a, Pins>,
+    ) -> CpuResult<Context<'a>> {
+        let mut ins = InstructionDecoder::new_from_read_mem(regs.pc as usize, &mut *mem)?;
+        // Nothing has run yet, an NMI taken first pushes this pc
+        // and the decode isn't charged to the first step
+        ins.next_addr = regs.pc as usize;
+        mem.take_wait_cycles();
+
         let ret = Context {
             regs,
             mem,
@@ -1430,10 +1607,25 @@ impl<'a> Context<'a> {
             pins,
             cycles: 0,
             instructions: 0,
+            flow: Flow::Normal,
+            call_stack: CallStack::new(),
+            s_guard: None,
+            u_guard: None,
+            reg_watches: RegWatches::new(),
+            reg_hit: false,
         };
         Ok(ret)
     }
 
+    /// Take ownership of everything, see Machine
+    pub fn owned<M: MemoryIO + Send + 'static>(mem: M, regs: Regs) -> CpuResult<Machine> {
+        Context::from_slots(
+            Slot::Owned(Box::new(mem)),
+            Slot::Owned(Box::new(regs)),
+            Slot::Owned(Box::default()),
+        )
+    }
+
     fn clear_pending_irq(&mut self) {
         self.pins.waiting_for_irq = false;
     }
@@ -1443,17 +1635,32 @@ impl<'a> Context<'a> {
     }
 
     pub fn step(&mut self) -> CpuResult<()> {
-        if self.pins.irq {
-            self.irq()?;
+        self.flow = Flow::Normal;
+        let pc = self.regs.pc as usize;
+        self.mem.set_bus_cycle(self.cycles);
+        self.pins.sense();
+
+        // Interrupt entries set ins.cycles themselves, ins still holds
+        // the last instruction decoded
+        if self.pins.reset {
+            self.pins.reset = false;
+            self.reset()?;
+            self.set_next_pc(self.regs.pc as usize);
+        } else if self.pins.nmi {
+            // Edge triggered, the pin latches until taken
+            self.pins.nmi = false;
+            self.nmi()?;
             self.clear_pending_irq();
-        } else if self.pins.firq {
+        } else if self.pins.firq_asserted() && !self.regs.flags.contains(Flags::F) {
             self.firq()?;
             self.clear_pending_irq();
-        } else if self.pins.nmi {
-            self.nmi()?;
+        } else if self.pins.irq_asserted() && !self.regs.flags.contains(Flags::I) {
+            self.irq()?;
             self.clear_pending_irq();
         } else {
-            self.ins = InstructionDecoder::new_from_read_mem(self.regs.pc as usize, self.mem)?;
+            let prev_pc = self.ins.addr;
+            self.ins = InstructionDecoder::new_from_read_mem(pc, &mut *self.mem)
+                .map_err(|e| e.into_fetch_err(pc, prev_pc))?;
 
             macro_rules! handle_op {
                 ($addr:ident, $action:ident, $opcode:expr, $cycles:expr, $size:expr) => {{
@@ -1464,11 +1671,17 @@ impl<'a> Context<'a> {
             let opcode = self.ins.instruction_info.opcode;
 
             op_table!(opcode, { self.unimplemented() })?;
+            self.instructions += 1;
         }
 
         self.regs.pc = self.ins.next_addr as u16;
+        // Stretched bus accesses
+        self.ins.cycles += self.mem.take_wait_cycles();
         self.cycles += self.ins.cycles;
-        self.instructions += 1;
+
+        self.call_stack.update(pc, self.regs.s as usize, self.flow);
+
+        self.reg_hit = !self.reg_watches.is_empty() && self.reg_watches.check(&*self.regs, pc);
 
         Ok(())
     }
@@ -1484,9 +1697,458 @@ impl<'a> Context<'a> {
             flags: Flags::I | Flags::F,
             ..Default::default()
         };
+        self.call_stack.reset();
         Ok(())
     }
 }
 
+impl<'a> ReplayTarget for Context<'a> {
+    fn set_pin(&mut self, pin: Pin, level: bool) {
+        match pin {
+            Pin::Irq => self.pins.irq = level,
+            Pin::Firq => self.pins.firq = level,
+            Pin::Nmi => self.pins.nmi = level,
+            Pin::Reset => self.pins.reset = level,
+        }
+    }
+
+    fn state_hash(&self) -> String {
+        let r = &self.regs;
+        let p = &self.pins;
+
+        let mut bytes = vec![r.a, r.b, r.dp, r.flags.bits()];
+
+        for w in [r.x, r.y, r.u, r.s, r.pc] {
+            bytes.extend(w.to_be_bytes())
+        }
+
+        bytes.extend(
+            [p.irq_asserted(), p.firq_asserted(), p.nmi, p.reset, p.waiting_for_irq].map(u8::from),
+        );
+
+        hash_state(&bytes, &*self.mem)
+    }
+}
+
+impl<'a> InterruptTarget for Context<'a> {
+    fn is_masked(&self, pin: Pin) -> bool {
+        match pin {
+            Pin::Irq => self.regs.flags.contains(Flags::I),
+            Pin::Firq => self.regs.flags.contains(Flags::F),
+            Pin::Nmi | Pin::Reset => false,
+        }
+    }
+
+    fn taken_interrupt(&self) -> Option<Pin> {
+        match self.flow {
+            Flow::Interrupt {
+                vector: VEC_IRQ, ..
+            } => Some(Pin::Irq),
+            Flow::Interrupt {
+                vector: VEC_FIRQ, ..
+            } => Some(Pin::Firq),
+            Flow::Interrupt {
+                vector: VEC_NMI, ..
+            } => Some(Pin::Nmi),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> GoldenTarget for Context<'a> {
+    type Err = CpuErr;
+
+    fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    fn step(&mut self) -> CpuResult<()> {
+        Context::step(self)
+    }
+
+    fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    fn regs(&self) -> std::collections::BTreeMap<String, u64> {
+        use RegEnum::*;
+
+        [A, B, X, Y, U, S, DP, CC, PC]
+            .iter()
+            .map(|r| (r.to_string().to_lowercase(), self.regs.get(r) as u64))
+            .collect()
+    }
+
+    fn mem(&self) -> &dyn MemoryIO {
+        &*self.mem
+    }
+
+    fn mem_mut(&mut self) -> &mut dyn MemoryIO {
+        &mut *self.mem
+    }
+}
+
+impl<'a> RunTarget for Context<'a> {
+    fn pc(&self) -> usize {
+        self.regs.pc as usize
+    }
+
+    fn is_illegal_opcode(err: &CpuErr) -> bool {
+        matches!(
+            err,
+            CpuErr::UnknownInstruction | CpuErr::Unimplemented(_) | CpuErr::IllegalAddressingMode
+        )
+    }
+
+    fn reg_watch_hit(&self) -> bool {
+        self.reg_hit
+    }
+}
+
+impl<'a> StepTarget for Context<'a> {
+    fn flow(&self) -> Flow {
+        self.flow
+    }
+
+    fn call_depth(&self) -> usize {
+        self.call_stack.depth()
+    }
+}
+
+impl<'a> FlagTarget for Context<'a> {
+    const FLAG_LAYOUT: &'static str = "EFHINZVC";
+
+    fn flags(&self) -> u64 {
+        self.regs.flags.bits() as u64
+    }
+}
+
 //
 // }}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emucore::byteorder::BigEndian;
+    use emucore::mem::{MemBlock, MemMap, MemMapIO, WaitStates};
+    use emucore::regwatch::RegCondition;
+    use emucore::run::{RunTarget, StepTarget, StopConditions, StopReason};
+    use emucore::smc::SmcMonitor;
+    use std::sync::{Arc, Mutex};
+
+    /// Ram at $0000-$7fff and $ff00-$ffff, pc at $1000, NMI enters $2000
+    struct Parts {
+        mem: MemMap,
+        regs: Regs,
+        pins: Pins,
+    }
+
+    impl Parts {
+        fn new(code: &[u8]) -> Self {
+            let mut mem = MemMap::new();
+            for (name, r) in [("ram", 0..0x8000), ("vectors", 0xff00..0x1_0000)] {
+                mem.add_memory(Box::new(MemBlock::<BigEndian>::new(name, false, &r)));
+            }
+            mem.upload(0x1000, code).unwrap();
+            mem.store_word(VEC_NMI, 0x2000).unwrap();
+            // rti
+            mem.store_byte(0x2000, 0x3b).unwrap();
+
+            let regs = Regs {
+                pc: 0x1000,
+                s: 0x8000,
+                ..Default::default()
+            };
+
+            Self {
+                mem,
+                regs,
+                pins: Pins::default(),
+            }
+        }
+
+        fn ctx(&mut self) -> Context<'_> {
+            Context::new(&mut self.mem, &mut self.regs, &mut self.pins).unwrap()
+        }
+    }
+
+    #[test]
+    fn flow_and_step_out() {
+        // jsr $1010, nop ... $1010: nop, rts
+        let mut p = Parts::new(&[0xbd, 0x10, 0x10, 0x12]);
+        p.mem.upload(0x1010, &[0x12, 0x39]).unwrap();
+        let mut c = p.ctx();
+
+        c.step().unwrap();
+        assert_eq!(
+            c.flow,
+            Flow::Call {
+                dest: 0x1010,
+                ret: 0x1003
+            }
+        );
+        assert_eq!(c.call_depth(), 1);
+
+        let s = StopConditions::new().step_out(&mut c).unwrap();
+        assert_eq!((s.reason, s.pc), (StopReason::Step, 0x1003));
+        assert_eq!(c.flow, Flow::Return { dest: 0x1003 });
+        assert_eq!(c.call_depth(), 0);
+    }
+
+    #[test]
+    fn nmi() {
+        // nop
+        let mut p = Parts::new(&[0x12]);
+        let mut c = p.ctx();
+        c.set_pin(Pin::Nmi, true);
+
+        c.step().unwrap();
+        assert_eq!(c.taken_interrupt(), Some(Pin::Nmi));
+        assert_eq!(
+            c.flow,
+            Flow::Interrupt {
+                vector: VEC_NMI,
+                dest: 0x2000,
+                ret: 0x1000
+            }
+        );
+        assert_eq!((c.pc(), c.regs.s), (0x2000, 0x8000 - 12));
+
+        c.step().unwrap();
+        assert_eq!(c.flow, Flow::ReturnFromInterrupt { dest: 0x1000 });
+        assert_eq!((c.pc(), c.regs.s, c.call_depth()), (0x1000, 0x8000, 0));
+    }
+
+    #[test]
+    fn interrupts_arent_instructions() {
+        // nop
+        let mut p = Parts::new(&[0x12]);
+        p.pins.nmi = true;
+        let mut c = p.ctx();
+
+        c.step().unwrap();
+        assert_eq!((c.taken_interrupt(), c.pc(), c.instructions), (Some(Pin::Nmi), 0x2000, 0));
+
+        // rti, nop
+        c.step().unwrap();
+        c.step().unwrap();
+        assert_eq!((c.pc(), c.instructions), (0x1001, 2));
+    }
+
+    #[test]
+    fn extended_indirect() {
+        // lda [$0010], nop
+        let mut p = Parts::new(&[0xa6, 0x9f, 0x00, 0x10, 0x12]);
+        p.mem.store_word(0x10, 0x20).unwrap();
+        p.mem.store_byte(0x20, 0x42).unwrap();
+        let mut c = p.ctx();
+
+        c.step().unwrap();
+        assert_eq!((c.regs.a, c.pc()), (0x42, 0x1004));
+    }
+
+    #[test]
+    fn device_lines() {
+        // nop, nop
+        let mut p = Parts::new(&[0x12, 0x12]);
+        p.mem.store_word(VEC_IRQ, 0x2000).unwrap();
+        let via = p.pins.lines.add_source(Pin::Irq, "via").unwrap();
+        let nmi = p.pins.lines.add_source(Pin::Nmi, "button").unwrap();
+        let mut c = p.ctx();
+
+        c.pins.lines.set(via, true);
+        c.step().unwrap();
+        assert_eq!((c.taken_interrupt(), c.pc()), (Some(Pin::Irq), 0x2000));
+        c.step().unwrap();
+
+        // Held high, still one edge
+        c.pins.lines.set(via, false);
+        c.pins.lines.set(nmi, true);
+        c.step().unwrap();
+        assert_eq!(c.taken_interrupt(), Some(Pin::Nmi));
+        c.step().unwrap();
+        c.step().unwrap();
+        assert_eq!((c.taken_interrupt(), c.pc()), (None, 0x1001));
+    }
+
+    #[test]
+    fn interrupt_cycles() {
+        // nop, lda $1000
+        let mut p = Parts::new(&[0x12, 0xb6, 0x10, 0x00]);
+        p.mem.store_word(VEC_IRQ, 0x2000).unwrap();
+        let mut c = p.ctx();
+
+        let irq_after = |c: &mut Context| {
+            c.step().unwrap();
+            c.set_pin(Pin::Irq, true);
+            let n = c.cycles;
+            c.step().unwrap();
+            c.set_pin(Pin::Irq, false);
+            // rti
+            c.step().unwrap();
+            c.cycles - n
+        };
+
+        let after_nop = irq_after(&mut c);
+        let after_lda = irq_after(&mut c);
+        assert_eq!(after_nop, after_lda);
+    }
+
+    #[test]
+    fn stack_guard() {
+        // pshs a x 5
+        let mut p = Parts::new(&[0x34, 0x02].repeat(5));
+        let mut c = p.ctx();
+        c.s_guard = Some(StackGuard::new("S", 0x7ffc..0x8000));
+
+        for _ in 0..4 {
+            c.step().unwrap();
+        }
+
+        match c.step() {
+            Err(CpuErr::Stack(e)) => {
+                assert_eq!((e.kind, e.addr), ("overflow", 0x7ffb));
+            }
+            r => panic!("expected a stack overflow, got {r:?}"),
+        }
+        assert_eq!(c.s_guard.as_ref().unwrap().high_water(), 4);
+    }
+
+    #[test]
+    fn fetch_error() {
+        // jmp $9000, which isn't mapped
+        let mut p = Parts::new(&[0x7e, 0x90, 0x00]);
+        let mut c = p.ctx();
+        c.step().unwrap();
+
+        match c.step() {
+            Err(CpuErr::IllegalFetch { pc, prev_pc, err }) => {
+                assert_eq!((pc, prev_pc), (0x9000, 0x1000));
+                assert_eq!(err, MemErrorTypes::IllegalAddress(0x9000));
+            }
+            r => panic!("expected a fetch error, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn smc_blames_prefixed_opcode() {
+        // sty $1000, over itself
+        let mut p = Parts::new(&[0x10, 0xbf, 0x10, 0x00]);
+        let smc = Arc::new(Mutex::new(SmcMonitor::new()));
+        p.mem.subscribe(SmcMonitor::filter(), Box::new(smc.clone()));
+        p.ctx().step().unwrap();
+
+        let hits = smc.lock().unwrap().take_hits();
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|h| h.pc == 0x1000));
+    }
+
+    #[test]
+    fn operand_fetch_error() {
+        // jmp $7fff, lda # with its operand past the end of ram
+        let mut p = Parts::new(&[0x7e, 0x7f, 0xff]);
+        p.mem.store_byte(0x7fff, 0x86).unwrap();
+        let mut c = p.ctx();
+        c.step().unwrap();
+
+        match c.step() {
+            Err(CpuErr::IllegalFetch { pc, prev_pc, err }) => {
+                assert_eq!((pc, prev_pc), (0x7fff, 0x1000));
+                assert_eq!(err, MemErrorTypes::IllegalAddress(0x8000));
+            }
+            r => panic!("expected a fetch error, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn reg_watch_stops_run() {
+        // lda #1, lda #$12, nop
+        let mut p = Parts::new(&[0x86, 0x01, 0x86, 0x12, 0x12]);
+        let mut c = p.ctx();
+        c.reg_watches.add(RegEnum::A, RegCondition::Changed);
+
+        let s = StopConditions::new()
+            .reg_watches()
+            .run_until(&mut c)
+            .unwrap();
+        assert_eq!((s.reason, s.pc), (StopReason::RegWatch, 0x1004));
+        assert_eq!(c.reg_watches.hits()[0].old, Some(1));
+    }
+
+    #[test]
+    fn wait_states() {
+        // nop, lda #$12, sta <$10
+        let mut p = Parts::new(&[0x12, 0x86, 0x12, 0x97, 0x10]);
+        p.mem.set_wait_states(0x1000..0x1100, WaitStates::new(1, 0));
+        p.mem.set_wait_states(0x0000..0x0100, WaitStates::new(0, 2));
+        let mut c = p.ctx();
+
+        let cycles: Vec<_> = (0..3)
+            .map(|_| {
+                let n = c.cycles;
+                c.step().unwrap();
+                c.cycles - n
+            })
+            .collect();
+
+        assert_eq!(cycles, [2 + 1, 2 + 2, 4 + 2 + 2]);
+    }
+
+    #[test]
+    fn owned_machine_on_a_runner() {
+        use emucore::runner::{Command, Event, PauseReason, Runner, Snapshot};
+        use std::time::Duration;
+
+        fn is_send<T: Send>() {}
+        is_send::<Machine>();
+
+        let stopped = |r: &Runner<Machine>| -> (PauseReason, Snapshot) {
+            loop {
+                match r.events().recv_timeout(Duration::from_secs(5)).unwrap() {
+                    Event::Stopped { reason, snapshot } => return (reason, snapshot),
+                    Event::Snapshot(_) => (),
+                }
+            }
+        };
+
+        // inca, bra to the inca
+        let r = Runner::spawn(|| {
+            let p = Parts::new(&[0x4c, 0x20, 0xfd]);
+            Machine::owned(p.mem, p.regs).unwrap()
+        });
+
+        r.send(Command::AddBreakpoint(0x1001.into()));
+        r.run();
+        let (reason, s) = stopped(&r);
+        assert_eq!(reason, PauseReason::Breakpoint { id: 0, addr: 0x1001 });
+        assert_eq!((s.regs["a"], s.instructions), (1, 1));
+
+        r.send(Command::RemoveBreakpoint(0x1001.into()));
+        r.step(4);
+        let (reason, s) = stopped(&r);
+        assert_eq!(reason, PauseReason::Stepped);
+        assert_eq!((s.pc, s.regs["a"], s.cycles), (0x1001, 3, 3 * 2 + 2 * 3));
+
+        assert!(r.quit());
+    }
+
+    #[test]
+    fn flag_check() {
+        use crate::cpu::decoder::load_isa;
+        use emucore::flagcheck::FlagChecker;
+
+        let isa = load_isa().unwrap();
+
+        // lda #$80, clra, coma, tsta, ldd #$1234, inca, deca, lsra, rora, mul, sex,
+        // leax 1,x, adda #$7f, orcc #1, andcc #$fe, nop
+        let p = Parts::new(&[
+            0x86, 0x80, 0x4f, 0x43, 0x4d, 0xcc, 0x12, 0x34, 0x4c, 0x4a, 0x44, 0x46, 0x3d, 0x1d,
+            0x30, 0x01, 0x8b, 0x7f, 0x1a, 0x01, 0x1c, 0xfe, 0x12,
+        ]);
+        let mut m = Machine::owned(p.mem, p.regs).unwrap();
+        let mut checker = FlagChecker::new(&isa);
+
+        assert_eq!(checker.run(&mut m, 16).unwrap(), 0, "{:?}", checker.violations());
+        assert_eq!(m.pc(), 0x1017);
+    }
+}
@@ -1,15 +1,84 @@
 #![deny(unused_imports)]
 use super::{CpuErr, CpuResult};
-use crate::isa::{Dbase, Instruction};
-use emucore::mem::{ MemReader, MemoryIO };
+use crate::isa::{split_opcodes, AddrModeEnum, Dbase, Instruction};
+use emucore::isa::{IsaDbase, IsaOp, IsaResult};
+use emucore::mem::{ MemErrorTypes, MemReader, MemoryIO };
+use emucore::traits::InstructionDbaseTrait;
+use std::sync::OnceLock;
 
 const RBYTE: &[u8] = include_bytes!("../../resources/opcodes6809.json");
 
-lazy_static::lazy_static! {
-    static ref DBASE: Dbase = {
-        let bytes_str = std::str::from_utf8(RBYTE).unwrap();
-        Dbase::from_text(bytes_str)
+static DBASE: OnceLock<IsaResult<Dbase>> = OnceLock::new();
+
+/// The tables decoding uses, loaded on first use
+/// A table that fails to load fails every decode
+fn dbase() -> CpuResult<&'static Dbase> {
+    DBASE
+        .get_or_init(load_dbase)
+        .as_ref()
+        .map_err(|e| CpuErr::Isa(e.to_string()))
+}
+
+/// The decoder's tables
+/// Dbase keeps the first of any duplicates so they're checked for here
+pub fn load_dbase() -> IsaResult<Dbase> {
+    checked_dbase(std::str::from_utf8(RBYTE)?)
+}
+
+fn checked_dbase(json: &str) -> IsaResult<Dbase> {
+    let dbase = Dbase::from_json(json)?;
+    to_isa(&dbase).checked()?;
+    Ok(dbase)
+}
+
+/// The instruction set as an emucore ISA database
+pub fn load_isa() -> IsaResult<IsaDbase<AddrModeEnum>> {
+    Ok(to_isa(&load_dbase()?))
+}
+
+fn to_isa(dbase: &Dbase) -> IsaDbase<AddrModeEnum> {
+    use AddrModeEnum::*;
+
+    let to_op = |i: &Instruction, name: &str| {
+        IsaOp::new(name, i.addr_mode, i.opcode, i.cycles, i.size).with_flags(flag_mods(name))
     };
+
+    let mut aliases = vec![];
+
+    let ops = dbase
+        .all_instructions()
+        .iter()
+        .map(|i| match split_opcodes(&i.action) {
+            Some((a, b)) => {
+                aliases.push((b, a));
+                to_op(i, a)
+            }
+            None => to_op(i, &i.action),
+        })
+        .collect();
+
+    let modes = [
+        (Indexed, 2),
+        (Direct, 2),
+        (Extended, 3),
+        (Relative, 2),
+        (Relative16, 3),
+        (Inherent, 1),
+        (Immediate8, 2),
+        (Immediate16, 3),
+        (RegisterSet, 2),
+        (RegisterPair, 2),
+    ];
+
+    let mut isa = IsaDbase::from_data(ops, to_op(dbase.unknown(), "unknown"))
+        .with_flag_order("EFHINZVC")
+        .with_modes(modes.map(|(m, s)| (m, Some(s))));
+
+    for (alias, name) in aliases {
+        isa = isa.with_alias(alias, name);
+    }
+
+    isa
 }
 
 #[derive(Debug, Clone)]
@@ -40,11 +109,11 @@ fn decode_op(reader: &mut MemReader) -> CpuResult<InstructionDecoder> {
 
     use crate::isa::AddrModeEnum;
 
-    let a = reader.next_byte()? as u16;
+    let a = reader.fetch_byte()? as u16;
 
     // Fetch the next byte if it's an extended opcode
     let op_code = match a {
-        0x10 | 0x11 => (a << 8) + reader.next_byte()? as u16,
+        0x10 | 0x11 => (a << 8) + reader.fetch_byte()? as u16,
         _ => a,
     };
 
@@ -52,7 +121,7 @@ fn decode_op(reader: &mut MemReader) -> CpuResult<InstructionDecoder> {
 
     // Fetch a reference to the extended infomation about this
     // opcode
-    let instruction_info = DBASE.get(op_code);
+    let instruction_info = dbase()?.get(op_code);
 
     if instruction_info.addr_mode == AddrModeEnum::Indexed {
         let index_mode_id = reader.peek_byte()?;
@@ -66,7 +135,7 @@ fn decode_op(reader: &mut MemReader) -> CpuResult<InstructionDecoder> {
     reader.skip_bytes(size);
 
     let range = reader.get_taken_range();
-    let data = reader.get_taken_bytes();
+    let data = reader.get_taken_bytes()?;
 
     // Create the decoded instruction
     let ret = InstructionDecoder {
@@ -118,23 +187,118 @@ impl InstructionDecoder {
         decode_op_mem(addr, _mem)
     }
 
-    pub fn fetch_byte(&mut self, mem: &mut dyn MemoryIO) -> u8 {
-        let b = mem.load_byte(self.operand_addr).unwrap();
+    /// Operands that can't be read are bad fetches, prev_pc is the
+    /// instruction they belong to
+    fn fetch_err(&self, addr: usize, err: MemErrorTypes) -> CpuErr {
+        CpuErr::IllegalFetch {
+            pc: addr,
+            prev_pc: self.addr,
+            err,
+        }
+    }
+
+    pub fn fetch_byte(&mut self, mem: &mut dyn MemoryIO) -> CpuResult<u8> {
+        let addr = self.operand_addr;
+        let b = mem
+            .fetch_operand_byte(addr)
+            .map_err(|e| self.fetch_err(addr, e))?;
         self.operand_addr += 1;
-        b
+        Ok(b)
     }
 
     pub fn fetch_word(&mut self, mem: &mut dyn MemoryIO) -> Result<u16, CpuErr> {
-        let w = mem.load_word(self.operand_addr)?;
+        let addr = self.operand_addr;
+        let w = mem
+            .fetch_operand_word(addr)
+            .map_err(|e| self.fetch_err(addr, e))?;
         self.operand_addr += 1;
         Ok(w)
     }
 
-    pub fn fetch_byte_as_i8(&mut self, mem: &mut dyn MemoryIO) -> i8 {
-        self.fetch_byte(mem) as i8
+    pub fn fetch_byte_as_i8(&mut self, mem: &mut dyn MemoryIO) -> CpuResult<i8> {
+        self.fetch_byte(mem).map(|b| b as i8)
+    }
+
+    pub fn fetch_byte_as_i16(&mut self, mem: &mut dyn MemoryIO) -> CpuResult<i16> {
+        self.fetch_byte_as_i8(mem).map(i16::from)
+    }
+}
+
+/// How each instruction changes EFHINZVC
+/// opcodes6809.json doesn't carry flags
+fn flag_mods(mnemonic: &str) -> &'static str {
+    match mnemonic {
+        "adca" | "adcb" | "adda" | "addb" => "--+-++++",
+        "addd" | "subd" | "cmpd" | "cmps" | "cmpu" | "cmpx" | "cmpy" | "daa" | "rol" | "rola"
+        | "rolb" => "----++++",
+        "sbca" | "sbcb" | "suba" | "subb" | "cmpa" | "cmpb" | "neg" | "nega" | "negb" | "lsl"
+        | "lsla" | "lslb" => "--+-++++",
+        "asr" | "asra" | "asrb" => "--+-++-+",
+        "ror" | "rora" | "rorb" => "----++-+",
+        "lsr" | "lsra" | "lsrb" => "----0+-+",
+        "anda" | "andb" | "bita" | "bitb" | "eora" | "eorb" | "ora" | "orb" | "tst" | "tsta"
+        | "tstb" | "lda" | "ldb" | "ldd" | "lds" | "ldu" | "ldx" | "ldy" | "sta" | "stb"
+        | "std" | "sts" | "stu" | "stx" | "sty" => "----++0-",
+        "dec" | "deca" | "decb" | "inc" | "inca" | "incb" | "sex" => "----+++-",
+        "clr" | "clra" | "clrb" => "----0100",
+        "com" | "coma" | "comb" => "----++01",
+        "leax" | "leay" => "-----+--",
+        "mul" => "-----+-+",
+        "swi" => "11-1----",
+        "swi2" | "swi3" => "1-------",
+        // Anything that can load CC
+        "andcc" | "orcc" | "cwai" | "exg" | "tfr" | "puls" | "pulu" | "rti" => "++++++++",
+        "unknown" => "",
+        _ => "--------",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emucore::isa::{IsaErr, IsaIssue};
+    use emucore::mem::MemBlock;
+
+    #[test]
+    fn duplicates_fail_to_load() {
+        let json = r#"{
+            "unknown": { "addr_mode": "Inherent", "cycles": 1, "action": "unknown", "opcode": "0", "size": 1 },
+            "instructions": [
+                { "addr_mode": "Inherent", "cycles": 2, "action": "nop", "opcode": "12", "size": 1 },
+                { "addr_mode": "Inherent", "cycles": 2, "action": "nop", "opcode": "13", "size": 1 }
+            ]
+        }"#;
+
+        let Err(IsaErr::Invalid(issues)) = checked_dbase(json) else {
+            panic!("duplicate nop loaded")
+        };
+
+        assert_eq!(
+            issues,
+            vec![IsaIssue::DuplicateMode {
+                mnemonic: "nop".into(),
+                mode: "Inherent".into()
+            }]
+        );
+        assert!(load_dbase().is_ok());
     }
 
-    pub fn fetch_byte_as_i16(&mut self, mem: &mut dyn MemoryIO) -> i16 {
-        i16::from(self.fetch_byte_as_i8(mem))
+    #[test]
+    fn decodes_fixed_opcodes() {
+        // cwai #$ef, lbrn $0010
+        let mut mem = MemBlock::<emucore::byteorder::BigEndian>::new("ram", false, &(0..0x100));
+        mem.upload(0, &[0x3c, 0xef, 0x10, 0x21, 0x00, 0x10])
+            .unwrap();
+
+        let cwai = InstructionDecoder::new_from_read_mem(0, &mut mem).unwrap();
+        let lbrn = InstructionDecoder::new_from_read_mem(2, &mut mem).unwrap();
+
+        let info = |i: &InstructionDecoder| {
+            let ins = i.instruction_info;
+            (ins.action.clone(), ins.addr_mode, i.size, i.next_addr)
+        };
+
+        assert_eq!(info(&cwai), ("cwai".into(), AddrModeEnum::Immediate8, 2, 2));
+        assert_eq!(info(&lbrn), ("lbrn".into(), AddrModeEnum::Relative16, 4, 6));
     }
 }
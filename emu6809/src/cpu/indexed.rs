@@ -41,7 +41,7 @@ impl IndexModes {
             Self::PCAddi8 => 1,
             Self::PCAddi16 => 2,
             Self::Illegal => 0,
-            Self::Ea => 0,
+            Self::Ea => 2,
         }
     }
 }
@@ -1,10 +1,13 @@
 #![deny(unused_imports)]
 use std::{fmt::Display, str::FromStr};
 
+use emucore::traits::{RegEnumTrait, RegisterFileTrait};
+
 use super::Flags;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy, PartialOrd, Ord, Default)]
 pub enum RegEnum {
+    #[default]
     A,
     B,
     D,
@@ -58,6 +61,16 @@ impl FromStr for RegEnum {
     }
 }
 
+impl RegEnumTrait for RegEnum {
+    fn get_size_bytes(&self) -> usize {
+        use RegEnum::*;
+        match self {
+            A | B | DP | CC => 1,
+            _ => 2,
+        }
+    }
+}
+
 impl RegEnum {
     /// Is this register okay to use as an index?
     pub fn valid_for_index(&self) -> bool {
@@ -168,3 +181,13 @@ impl Regs {
         }
     }
 }
+
+impl RegisterFileTrait<RegEnum> for Regs {
+    fn get(&self, r: &RegEnum) -> u64 {
+        Regs::get(self, r) as u64
+    }
+
+    fn set(&mut self, r: &RegEnum, v: u64) {
+        Regs::set(self, r, v as u16)
+    }
+}
@@ -1,5 +1,5 @@
-use super::cpu::{IndexModes, IndexedFlags, InstructionDecoder};
-use super::isa::Dbase;
+use super::cpu::{IndexModes, IndexedFlags, InstructionDecoder, RegEnum};
+use super::isa::{AddrModeEnum, Dbase};
 use super::byteorder;
 
 pub struct Disassembly {
@@ -8,6 +8,8 @@ pub struct Disassembly {
     pub decoded: InstructionDecoder,
 }
 
+use emucore::diss::{Disassembler, OpFlow, Operand, OperandFormat};
+use emucore::isa::{IsaOp, IsaResult};
 use emucore::mem::{ MemBlock, MemoryIO, MemReader };
 
 pub struct DissCtx {
@@ -201,3 +203,217 @@ impl Diss {
         }
     }
 }
+
+/// Instruction text at addr, for reports
+/// Works on a copy so the read doesn't touch the bus
+pub fn describe(mem: &dyn MemoryIO, addr: usize) -> String {
+    let end = (addr + 5).min(0x1_0000);
+    let data: Vec<u8> = (addr..end).map(|a| mem.inspect_byte(a).unwrap_or(0)).collect();
+    let mut block = MemBlock::<byteorder::BigEndian>::from_data(addr, "diss", &data, true);
+
+    let mut reader = MemReader::new(&mut block);
+    reader.set_addr(addr);
+
+    match InstructionDecoder::new_from_reader(&mut reader) {
+        Ok(_) => Diss::new().diss(&mut block, addr).text,
+        Err(e) => format!("{e:?}"),
+    }
+}
+
+fn tfr_name(r: u8) -> &'static str {
+    match r {
+        0 => "d",
+        1 => "x",
+        2 => "y",
+        3 => "u",
+        4 => "s",
+        5 => "pc",
+        8 => "a",
+        9 => "b",
+        10 => "cc",
+        11 => "dp",
+        _ => "?",
+    }
+}
+
+/// 6809 operand syntax for the emucore disassembler
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Syntax;
+
+impl Syntax {
+    fn indexed(&self, o: &Operand) -> String {
+        let flags = IndexedFlags::new(o.byte(0));
+        let r = |r: RegEnum| r.to_string().to_lowercase();
+
+        let text = match flags.get_index_type() {
+            IndexModes::ROff(reg, off) => format!("{},{}", off as i16, r(reg)),
+            IndexModes::RPlus(reg) => format!(",{}+", r(reg)),
+            IndexModes::RPlusPlus(reg) => format!(",{}++", r(reg)),
+            IndexModes::RSub(reg) => format!(",-{}", r(reg)),
+            IndexModes::RSubSub(reg) => format!(",--{}", r(reg)),
+            IndexModes::RZero(reg) => format!(",{}", r(reg)),
+            IndexModes::RAddB(reg) => format!("b,{}", r(reg)),
+            IndexModes::RAddA(reg) => format!("a,{}", r(reg)),
+            IndexModes::RAddD(reg) => format!("d,{}", r(reg)),
+            IndexModes::RAddi8(reg) => format!("{},{}", o.byte(1) as i8, r(reg)),
+            IndexModes::RAddi16(reg) => format!("{},{}", o.word_be(1) as i16, r(reg)),
+            IndexModes::PCAddi8 => format!("{},pcr", o.name(o.rel8(1), 4, "$")),
+            IndexModes::PCAddi16 => format!("{},pcr", o.name(o.rel16_be(1), 4, "$")),
+            IndexModes::Ea => o.name(o.word_be(1) as usize, 4, "$"),
+            IndexModes::Illegal => return "????".to_owned(),
+        };
+
+        if flags.is_indirect() {
+            format!("[{text}]")
+        } else {
+            text
+        }
+    }
+
+    fn register_set(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> String {
+        let other = if op.mnemonic.ends_with('u') { "s" } else { "u" };
+        let names = ["cc", "a", "b", "dp", "x", "y", other, "pc"];
+
+        names
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| o.byte(0) & (1 << i) != 0)
+            .map(|(_, n)| *n)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn rel(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> usize {
+        match op.addr_mode {
+            AddrModeEnum::Relative16 => o.rel16_be(0),
+            _ => o.rel8(0),
+        }
+    }
+}
+
+impl OperandFormat<AddrModeEnum> for Syntax {
+    fn operand(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> String {
+        use AddrModeEnum::*;
+
+        match op.addr_mode {
+            Immediate8 => format!("#${:02x}", o.byte(0)),
+            Immediate16 => format!("#${:04x}", o.word_be(0)),
+            Direct => format!("<${:02x}", o.byte(0)),
+            Extended => {
+                let w = o.word_be(0) as usize;
+                // Force extended where an assembler would pick direct
+                let force = if w < 0x100 { ">" } else { "" };
+                format!("{force}{}", o.name(w, 4, "$"))
+            }
+            Indexed => self.indexed(o),
+            Inherent => "".to_owned(),
+            Relative | Relative16 => o.name(self.rel(op, o), 4, "$"),
+            RegisterSet => self.register_set(op, o),
+            RegisterPair => {
+                let b = o.byte(0);
+                format!("{},{}", tfr_name(b >> 4), tfr_name(b & 0xf))
+            }
+        }
+    }
+
+    /// Indexed ops are as long as their postbyte says
+    fn size(&self, op: &IsaOp<AddrModeEnum>, mem: &dyn MemoryIO, addr: usize) -> usize {
+        if op.addr_mode != AddrModeEnum::Indexed {
+            return op.size;
+        }
+
+        let extra = mem
+            .inspect_byte(addr + op.opcode_bytes())
+            .map(|b| IndexedFlags::new(b).get_index_type().get_size())
+            .unwrap_or(0);
+
+        op.size + extra
+    }
+
+    fn flow(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> OpFlow {
+        use AddrModeEnum::*;
+
+        let b = o.byte(0);
+
+        match (op.mnemonic.as_str(), op.addr_mode) {
+            ("jsr", Extended) => OpFlow::Call(o.word_be(0) as usize),
+            ("jmp", Extended) => OpFlow::Jump(o.word_be(0) as usize),
+            ("jmp", _) | ("rts", _) | ("rti", _) => OpFlow::Stop,
+            ("puls", _) | ("pulu", _) if b & 0x80 != 0 => OpFlow::Stop,
+            ("tfr", _) if b & 0xf == 5 => OpFlow::Stop,
+            ("exg", _) if b & 0xf == 5 || b >> 4 == 5 => OpFlow::Stop,
+            ("bsr", _) | ("lbsr", _) => OpFlow::Call(self.rel(op, o)),
+            ("bra", _) | ("lbra", _) => OpFlow::Jump(self.rel(op, o)),
+            ("brn", _) | ("lbrn", _) => OpFlow::Next,
+            (_, Relative) | (_, Relative16) => OpFlow::Branch(self.rel(op, o)),
+            _ => OpFlow::Next,
+        }
+    }
+
+    fn data_ref(&self, op: &IsaOp<AddrModeEnum>, o: &Operand) -> Option<usize> {
+        use AddrModeEnum::*;
+
+        match op.addr_mode {
+            _ if op.mnemonic.starts_with('j') => None,
+            Extended => Some(o.word_be(0) as usize),
+            Indexed if IndexedFlags::new(o.byte(0)).is_ea() => Some(o.word_be(1) as usize),
+            _ => None,
+        }
+    }
+}
+
+/// A table driven disassembler built from the ISA json
+pub fn disassembler() -> IsaResult<Disassembler<AddrModeEnum, Syntax>> {
+    Ok(Disassembler::new(super::cpu::load_isa()?, Syntax))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax() {
+        let code = [
+            0x86, 0x12, // lda #$12
+            0xfc, 0x00, 0x10, // ldd >$0010
+            0x97, 0x20, // sta <$20
+            0x30, 0x1d, // leax -3,x
+            0xa6, 0x9f, 0x10, 0x00, // lda [$1000]
+            0xe6, 0x8c, 0x04, // ldb $1014,pcr
+            0x34, 0x16, // pshs a,b,x
+            0x1f, 0x12, // tfr x,y
+            0x10, 0x27, 0xff, 0xea, // lbeq $1002
+            0x20, 0xfe, // bra $1018
+            0x35, 0x80, // puls pc
+        ];
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0x1000, "rom", &code, true);
+        let diss = disassembler().unwrap();
+
+        let lines: Vec<_> = diss.iter(&mem, 0x1000..0x1000 + code.len()).collect();
+        let text: Vec<_> = lines.iter().map(|l| l.to_string()).collect();
+
+        assert_eq!(
+            text,
+            [
+                "1000 86 12        lda #$12",
+                "1002 fc 00 10     ldd >$0010",
+                "1005 97 20        sta <$20",
+                "1007 30 1d        leax -3,x",
+                "1009 a6 9f 10 00  lda [$1000]",
+                "100d e6 8c 04     ldb $1014,pcr",
+                "1010 34 16        pshs a,b,x",
+                "1012 1f 12        tfr x,y",
+                "1014 10 27 ff ea  lbeq $1002",
+                "1018 20 fe        bra $1018",
+                "101a 35 80        puls pc",
+            ]
+        );
+
+        let flows: Vec<_> = lines[8..].iter().map(|l| diss.flow(l)).collect();
+        assert_eq!(
+            flows,
+            [OpFlow::Branch(0x1002), OpFlow::Jump(0x1018), OpFlow::Stop]
+        );
+        assert_eq!(diss.data_ref(&lines[4]), Some(0x1000));
+    }
+}
@@ -100,8 +100,9 @@ impl InstructionInfo {
     }
 
     pub fn add(&mut self, ins: &Instruction) {
+        // First one wins, load_dbase() rejects tables with duplicates
         if self.addressing_modes.contains_key(&ins.addr_mode) {
-            panic!("can't contain same addressing mode twice")
+            return;
         }
 
         self.addressing_modes.insert(ins.addr_mode, ins.clone());
@@ -121,7 +122,8 @@ pub struct Dbase {
     opcode_to_ins: HashMap<usize, InstructionInfo>,
 }
 
-fn split_opcodes(_input: &str) -> Option<(&str, &str)> {
+/// Actions with two names like lsl_asl
+pub fn split_opcodes(_input: &str) -> Option<(&str, &str)> {
     let split: Vec<&str> = _input.split('_').collect();
 
     if split.len() != 2 {
@@ -138,6 +140,11 @@ impl Dbase {
         Self::from_data(loaded.instructions, loaded.unknown)
     }
 
+    pub fn from_json(json_str: &str) -> Result<Self, serde_json::Error> {
+        let loaded: Dbase = serde_json::from_str(json_str)?;
+        Ok(Self::from_data(loaded.instructions, loaded.unknown))
+    }
+
     pub fn from_filename(file_name: &str) -> Self {
         let json_str = std::fs::read_to_string(file_name).unwrap();
         Self::from_text(&json_str)
@@ -216,6 +223,10 @@ impl Dbase {
     pub fn all_instructions(&self) -> &Vec<Instruction> {
         &self.instructions
     }
+
+    pub fn unknown(&self) -> &Instruction {
+        &self.unknown
+    }
 }
 
 impl Default for Dbase {
@@ -1,13 +1,14 @@
 use serde::Deserialize;
 use std::fmt;
 
-#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Hash, Eq)]
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Hash, Eq, Default)]
 pub enum AddrModeEnum {
     Indexed,
     Direct,
     Extended,
     Relative,
     Relative16,
+    #[default]
     Inherent,
     Immediate8,
     Immediate16,
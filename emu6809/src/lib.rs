@@ -3,5 +3,6 @@
 pub mod cpu;
 pub mod isa;
 pub mod diss;
+pub mod m6522;
 pub use byteorder;
 pub use emucore;
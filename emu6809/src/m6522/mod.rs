@@ -1,11 +1,10 @@
-use super::mem::{ MemoryIO };
-use super::cpu::Clock;
+use emucore::device::{DeviceInspect, DeviceReg};
+use emucore::irqline::{InterruptLines, SourceId};
+use emucore::mem::{MemErrorTypes, MemResult, MemoryIO};
+use emucore::replay::Pin;
 
 use sha1::Sha1;
 
-use std::cell::RefCell;
-use std::rc::Rc;
-
 ////////////////////////////////////////////////////////////////////////////////
 trait Bits {
     fn get_bit(&self, bit : usize) -> bool;
@@ -82,7 +81,7 @@ impl Bits for u8 {
 // *       7 IER set/clear control
 // VIA_port_a_nohs EQU     $D00F   ;VIA port A data I/O register (no handshaking)
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum Reg {
 
@@ -156,12 +155,14 @@ struct Timer {
     pub free_run : bool,
     pub int_flag : bool,
     pub timer_1 : bool,
+    /// A one shot interrupts once per write of the counter's high byte
+    pub armed : bool,
 }
 
 impl Timer {
 
     pub fn new(timer_1 : bool) -> Timer {
-        Timer {counter : 0, latch : 0, free_run : false, int_flag : false, timer_1}
+        Timer {counter : 0, latch : 0, free_run : false, int_flag : false, timer_1, armed : false}
     }
 
     pub fn write_lo(&mut self, val : u8) {
@@ -169,9 +170,10 @@ impl Timer {
     }
 
     pub fn write_hi(&mut self, val : u8) {
-        let data = ( self.counter & 0xff ) | u16::from(val) << 8;
+        let data = ( self.latch & 0xff ) | u16::from(val) << 8;
         self.latch = data;
         self.counter = data;
+        self.armed = true;
         self.reset_int_flag()
     }
 
@@ -180,10 +182,27 @@ impl Timer {
     }
 
     pub fn write_latch_hi(&mut self, val : u8) {
-        self.latch = ( self.latch & 0xff00 ) | u16::from(val );
+        self.latch = ( self.latch & 0xff ) | u16::from(val ) << 8;
         self.reset_int_flag()
     }
 
+    /// Count down, flags an interrupt when the counter passes zero
+    /// A one shot keeps counting but only flags the first time
+    pub fn tick(&mut self, cycles : u16) {
+        let (counter, under) = self.counter.overflowing_sub(cycles);
+
+        self.counter = if under && self.free_run {
+            self.latch
+        } else {
+            counter
+        };
+
+        if under && (self.free_run || self.armed) {
+            self.int_flag = true;
+            self.armed = self.free_run;
+        }
+    }
+
     pub fn reset_int_flag(&mut self ) {
         self.int_flag = false;
     }
@@ -206,13 +225,12 @@ impl Timer {
 }
 
 ////////////////////////////////////////////////////////////////////////////////
+/// Clocked by the host calling tick with the cycles each step took
 #[derive(Debug, Clone, Default)]
-pub struct M6522<C : Clock> {
-    start : u16,
-    size :u16,
-    last_byte : u16,
+pub struct M6522 {
+    start : usize,
+    size : usize,
     name : String,
-    rc_clock : Rc<RefCell<C>>,
     dirty_flag : bool,
 
     timer_1 : Timer,
@@ -225,13 +243,33 @@ pub struct M6522<C : Clock> {
     cntl : u8,
 
     shift_reg : u8,
+    int_enable : u8,
+
+    /// Where the IRQ output is wired
+    irq : Option<SourceId>,
 }
 
-#[derive(Debug, Clone)]
+/// What PB4 (BDIR) and PB3 (BC1) tell the AY-3-8912
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SoundReg {
-    TBD,
+    Inactive,
+    Read,
+    Write,
+    LatchAddress,
+}
+
+impl SoundReg {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SoundReg::Inactive => "PSG inactive",
+            SoundReg::Read => "PSG read",
+            SoundReg::Write => "PSG write",
+            SoundReg::LatchAddress => "PSG latch address",
+        }
+    }
 }
-#[derive(Debug, Clone)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MuxDest {
     Disabled,
     XAxis,
@@ -241,16 +279,32 @@ pub enum MuxDest {
     SoundChip,
 }
 
-impl <C : Clock> M6522 <C> {
+impl MuxDest {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            MuxDest::Disabled => "mux disabled",
+            MuxDest::XAxis => "DAC to X axis only",
+            MuxDest::YAxis => "DAC to Y axis",
+            MuxDest::XYAxisIntegrator => "DAC to XY integrator offset",
+            MuxDest::ZAxis => "DAC to Z axis (brightness)",
+            MuxDest::SoundChip => "DAC to sound",
+        }
+    }
+}
+
+impl M6522 {
 
 
     pub fn ramp(&self) -> bool { self.port_b.bits.get_bit(7) }
     pub fn comparator(&self) -> bool { self.port_b.bits.get_bit(5) }
-    pub fn sample_hold(&self) -> bool { self.port_b.bits.get_bit(1)  }
+    pub fn sample_hold(&self) -> bool { self.port_b.bits.get_bit(0)  }
 
     pub fn sound(&self) -> SoundReg {
         match (self.port_b.bits >> 3) & 3 {
-            _ => SoundReg::TBD,
+            0 => SoundReg::Inactive,
+            1 => SoundReg::Read,
+            2 => SoundReg::Write,
+            _ => SoundReg::LatchAddress,
         }
     }
 
@@ -268,26 +322,9 @@ impl <C : Clock> M6522 <C> {
             }
         }
     }
-
-
-    pub fn port_b_report(&self) {
-
-        let rtext = if self.ramp() {
-            "true : gun on" 
-        } 
-        else { 
-            "false : gun off"
-        };
-
-        println!("PortB setup");
-        println!("MuxDest    : {:?}", self.get_mux_dest());
-        println!("SOUND      : {:?}", self.sound());
-        println!("COMPARATOR : {}", self.comparator());
-        println!("RAMP       : {} ", rtext);
-    }
 }
 
-impl <C : Clock> M6522 <C> {
+impl M6522 {
 
     pub fn clear_dirty(&mut self) {
         self.dirty_flag = false;
@@ -301,19 +338,15 @@ impl <C : Clock> M6522 <C> {
         self.dirty_flag = true;
     }
 
-    pub fn new(start : u16, size : u16, rc_clock : &Rc<RefCell<C>>) -> Self {
-
-        let last_byte = (u32::from(size) + u32::from(start)) - 1;
+    pub fn new(start : usize, size : usize) -> Self {
 
-        assert!(last_byte < 0x1_0000);
+        assert!(start + size <= 0x1_0000);
 
         Self {
             start,
             size,
-            last_byte : last_byte as u16,
             name : format!("6522 : {:04x} {:04x}", start, size),
             dirty_flag : false,
-            rc_clock : rc_clock.clone(),
             port_b : Port::new(0,0),
             port_a : Port::new(0,0),
             timer_1 : Timer::new(true),
@@ -321,12 +354,68 @@ impl <C : Clock> M6522 <C> {
             aux_cntl : 0,
             cntl : 0,
             shift_reg : 0,
+            int_enable : 0,
+            irq : None,
         }
     }
 
-    pub fn get_reg(&self, addr : u16) -> (Reg, usize) {
+    /// Wire the IRQ output to pin, usually Pin::Irq
+    pub fn connect_irq(&mut self, lines : &mut InterruptLines, pin : Pin) {
+        self.irq = lines.add_source(pin, &self.name);
+    }
+
+    /// Run the timers on
+    pub fn tick(&mut self, cycles : usize) {
+        let cycles = cycles.min(0xffff) as u16;
+        self.timer_1.tick(cycles);
+        self.timer_2.tick(cycles);
+    }
+
+    /// Interrupt flags, bit 7 is set when an enabled flag is
+    pub fn int_flags(&self) -> u8 {
+        let ifr = u8::from(self.timer_1.int_flag) << 6 | u8::from(self.timer_2.int_flag) << 5;
+
+        if ifr & self.int_enable != 0 {
+            ifr | 0x80
+        } else {
+            ifr
+        }
+    }
+
+    /// Level of the open collector IRQ output
+    pub fn irq(&self) -> bool {
+        self.int_flags() & 0x80 != 0
+    }
+
+    /// Put the IRQ output on the line it's connected to
+    pub fn drive_irq(&self, lines : &mut InterruptLines) {
+        if let Some(id) = self.irq {
+            lines.set(id, self.irq())
+        }
+    }
 
-        let reg_num = (addr - self.start) & 0xf;
+    fn write_int_flags(&mut self, val : u8) {
+        // Writing a 1 clears the flag
+        if val.get_bit(6) {
+            self.timer_1.reset_int_flag()
+        }
+        if val.get_bit(5) {
+            self.timer_2.reset_int_flag()
+        }
+    }
+
+    fn write_int_enable(&mut self, val : u8) {
+        // Bit 7 says whether the other 1s set or clear
+        if val.get_bit(7) {
+            self.int_enable |= val & 0x7f
+        } else {
+            self.int_enable &= !val
+        }
+    }
+
+    pub fn get_reg(&self, addr : usize) -> (Reg, usize) {
+
+        let reg_num = addr.wrapping_sub(self.start) & 0xf;
 
         use self::Reg::*;
 
@@ -358,7 +447,7 @@ impl <C : Clock> M6522 <C> {
 
         };
 
-        (r, reg_num as usize)
+        (r, reg_num)
     }
 
     ////////////////////////////////////////////////////////////////////////////////
@@ -387,34 +476,11 @@ impl <C : Clock> M6522 <C> {
     fn get_cb2_cntl(&self)  -> u8 {
         (self.cntl >> 5) & 3
     }
-
-    fn cntl_report(&self) {
-        println!("cntl");
-        println!("ca1 irq hi   : {}", self.get_ca1_irq_on_high());
-        println!("ca2 cntl     : {}", self.get_ca2_cntl());
-        println!("cb1 irq hi   : {}", self.get_cb1_irq_on_high());
-        println!("cb2 cntl     : {}", self.get_cb2_cntl());
-        println!("bits         : %{:08b}", self.cntl);
-    }
     ////////////////////////////////////////////////////////////////////////////////
 
     fn write_aux_cntl(&mut self, data : u8) {
         self.aux_cntl = data;
-        let t1_free_run = data.get_bit(5);
-        let t2_free_run = data.get_bit(6);
-        self.timer_1.free_run = t1_free_run;
-        self.timer_2.free_run = t2_free_run;
-    }
-
-    fn aux_cntl_report(&self) {
-        println!("auxcntl");
-        println!("PA latch     : {}", self.get_port_a_latch());
-        println!("PB latch     : {}", self.get_port_b_latch());
-        println!("SR control   : {}", self.get_sr_control());
-        println!("T1 free run  : {}", self.timer_1.free_run);
-        println!("T2 free run  : {}", self.timer_2.free_run);
-        println!("p7 enable    : {}", self.get_t1_p7_enable());
-        println!("bits         : %{:08b}", self.aux_cntl);
+        self.timer_1.free_run = data.get_bit(6);
     }
 
     pub fn get_t1_p7_enable(&self) -> bool {
@@ -437,33 +503,67 @@ impl <C : Clock> M6522 <C> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+fn ctl_line(name: &'static str) -> impl Fn(u64) -> Option<String> {
+    move |v| Some(format!("{} mode {:03b}", name, v))
+}
 
-impl<C : Clock> MemoryIO for M6522<C> {
-
-    fn get_range(&self) -> (u16, u16) {
-        (self.start, self.last_byte)
+// Reads the latched state directly so nothing here clears a flag
+impl DeviceInspect for M6522 {
+    fn device_name(&self) -> String {
+        self.name.clone()
     }
 
-    fn update_sha1(&self, _digest : &mut Sha1) {
-        panic!();
-    }
+    fn registers(&self) -> Vec<DeviceReg> {
+        use self::Reg::*;
 
-    fn upload(&mut self, _addr : u16, _data : &[u8]) {
-        panic!("tbd")
+        let mux = self.get_mux_dest().describe();
+        let sound = self.sound().describe();
+
+        vec![
+            DeviceReg::new("ORB", PortB as usize, self.port_b.bits)
+                .decode("ramp", 7, 1, |v| Some(if v != 0 { "gun on" } else { "gun off" }.into()))
+                .flag("comparator", 5, "comparator high")
+                .decode("sound", 3, 2, move |_| Some(sound.into()))
+                .decode("mux", 1, 2, move |_| Some(mux.into()))
+                .flag("sample/hold", 0, "mux disabled"),
+            DeviceReg::new("ORA", PortA as usize, self.port_a.bits),
+            DeviceReg::new("DDRB", DdrB as usize, self.port_b.ddr),
+            DeviceReg::new("DDRA", DdrA as usize, self.port_a.ddr),
+            DeviceReg::word("T1C", T1CntL as usize, self.timer_1.counter),
+            DeviceReg::word("T1L", T1LatchLo as usize, self.timer_1.latch),
+            DeviceReg::word("T2C", T2Lo as usize, self.timer_2.counter),
+            DeviceReg::new("SR", ShiftReg as usize, self.shift_reg),
+            DeviceReg::new("ACR", AuxCntl as usize, self.aux_cntl)
+                .decode("T1 control", 6, 2, |v| {
+                    let mode = if v & 1 != 0 { "T1 free running" } else { "T1 one shot" };
+                    Some(if v & 2 != 0 { format!("{}, PB7 out", mode) } else { mode.into() })
+                })
+                .flag("T2 control", 5, "T2 counts PB6")
+                .decode("SR control", 2, 3, |v| (v != 0).then(|| format!("SR mode {:03b}", v)))
+                .flag("PB latch", 1, "PB latched")
+                .flag("PA latch", 0, "PA latched"),
+            DeviceReg::new("PCR", Cntl as usize, self.cntl)
+                .decode("CB2 control", 5, 3, ctl_line("CB2"))
+                .flag("CB1 control", 4, "CB1 IRQ on high")
+                .decode("CA2 control", 1, 3, ctl_line("CA2"))
+                .flag("CA1 control", 0, "CA1 IRQ on high"),
+            DeviceReg::new("IFR", IntFlags as usize, self.int_flags())
+                .flag("IRQ", 7, "IRQ")
+                .flag("T1", 6, "T1 timed out")
+                .flag("T2", 5, "T2 timed out"),
+            DeviceReg::new("IER", IntEnable as usize, self.int_enable | 0x80)
+                .flag("T1", 6, "T1 enabled")
+                .flag("T2", 5, "T2 enabled"),
+        ]
     }
+}
 
-    fn get_name(&self) -> String {
-        "via".to_string()
-    }
-
-
-    // http://archive.6502.org/datasheets/synertek_sy6522.pdf
+////////////////////////////////////////////////////////////////////////////////
 
-    fn load_byte(&mut self, addr:u16) -> u8 {
-        self.set_dirty();
-        let (reg, _) = self.get_reg(addr);
-        println!("R  0x{:04X} {:?}",addr, reg);
 
+impl M6522 {
+    /// A register's value with no side effects
+    fn peek(&self, reg : Reg) -> u8 {
         use self::Reg::*;
 
         match reg {
@@ -473,34 +573,80 @@ impl<C : Clock> MemoryIO for M6522<C> {
             PortB       => self.port_b.read_port(),
             AuxCntl     => self.aux_cntl ,
             Cntl        => self.cntl,
-            T1CntL      => self.timer_1.read_lo() ,
+            T1CntL      => self.timer_1.counter as u8 ,
             T1CntH      => self.timer_1.read_hi() ,
             T1LatchLo   => self.timer_1.read_latch_lo(),
             T1LatchHi   => self.timer_1.read_latch_hi(),
-            T2Lo        => self.timer_2.read_lo(),
+            T2Lo        => self.timer_2.counter as u8,
             T2Hi        => self.timer_2.read_hi(),
+            ShiftReg    => self.shift_reg,
+            IntFlags    => self.int_flags(),
+            IntEnable   => self.int_enable | 0x80,
+            PortANhs    => self.port_a.read_port(),
+        }
+    }
+}
 
-            _ => panic!("Unhandled read for {:?}", reg),
+impl MemoryIO for M6522 {
 
+    fn get_range(&self) -> std::ops::Range<usize> {
+        self.start..self.start + self.size
+    }
 
-            // ShiftReg    => {10} ,
-            // IntFlags    => {13} ,
-            // IntEnable   => {14} ,
-            // PortANhs    => {15} ,
-        }
+    fn update_sha1(&self, digest : &mut Sha1) {
+        use sha1::Digest;
+        let regs : Vec<u8> = (0..16).filter_map(|r| self.inspect_byte(self.start + r).ok()).collect();
+        digest.update(regs);
+    }
+
+    fn upload(&mut self, addr : usize, _data : &[u8]) -> MemResult<()> {
+        Err(MemErrorTypes::IllegalWrite(addr))
+    }
+
+    fn get_name(&self) -> String {
+        "via".to_string()
+    }
+
+    fn inspect_byte(&self, addr : usize) -> MemResult<u8> {
+        let (reg, _) = self.get_reg(addr);
+        Ok(self.peek(reg))
     }
 
-    fn store_word(&mut self, addr:u16, val:u16) {
-        self.store_byte(addr, (val >> 8) as u8);
-        self.store_byte(addr.wrapping_add(1), val as u8);
+    fn inspect_word(&self, addr : usize) -> MemResult<u16> {
+        let hi = self.inspect_byte(addr)?;
+        let lo = self.inspect_byte(addr.wrapping_add(1))?;
+        Ok(u16::from_be_bytes([hi, lo]))
     }
 
-    fn store_byte(&mut self, addr:u16, val:u8) {
+    // http://archive.6502.org/datasheets/synertek_sy6522.pdf
+
+    fn load_byte(&mut self, addr : usize) -> MemResult<u8> {
         self.set_dirty();
         let (reg, _) = self.get_reg(addr);
 
-        let reg_str = format!("{:?}", reg);
-        println!("W  0x{:04X} {:10} : 0x{:02x} 0b{:08b}",addr, reg_str, val, val);
+        use self::Reg::*;
+
+        match reg {
+            T1CntL => Ok(self.timer_1.read_lo()),
+            T2Lo   => Ok(self.timer_2.read_lo()),
+            _      => Ok(self.peek(reg)),
+        }
+    }
+
+    fn load_word(&mut self, addr : usize) -> MemResult<u16> {
+        let hi = self.load_byte(addr)?;
+        let lo = self.load_byte(addr.wrapping_add(1))?;
+        Ok(u16::from_be_bytes([hi, lo]))
+    }
+
+    fn store_word(&mut self, addr : usize, val : u16) -> MemResult<()> {
+        self.store_byte(addr, (val >> 8) as u8)?;
+        self.store_byte(addr.wrapping_add(1), val as u8)
+    }
+
+    fn store_byte(&mut self, addr : usize, val : u8) -> MemResult<()> {
+        self.set_dirty();
+        let (reg, _) = self.get_reg(addr);
 
         use self::Reg::*;
 
@@ -512,23 +658,14 @@ impl<C : Clock> MemoryIO for M6522<C> {
 
             DdrB         => self.port_b.set_ddr(val),
 
-            PortB        => {
-                self.port_b.write_port(val);
-                self.port_b_report()
-            }
+            PortB        => self.port_b.write_port(val),
 
-            AuxCntl      => {
-                self.write_aux_cntl(val);
-                // self.aux_cntl_report();
-            },
+            AuxCntl      => self.write_aux_cntl(val),
 
             T1CntL       => self.timer_1.write_lo(val),
             T1CntH       => self.timer_1.write_hi(val),
 
-            Cntl             => {
-                self.write_cntl(val);
-                // self.cntl_report()
-            },
+            Cntl         => self.write_cntl(val),
 
             ShiftReg     => self.shift_reg = val,
 
@@ -537,21 +674,88 @@ impl<C : Clock> MemoryIO for M6522<C> {
             T2Lo         => self.timer_2.write_lo(val),
             T2Hi         => self.timer_2.write_hi(val),
 
-            _ => panic!("Unhandled write of %{:08b} to {:?}", val, reg)
+            IntFlags     => self.write_int_flags(val),
+            IntEnable    => self.write_int_enable(val),
 
-                // PortA        => () ,
+            PortANhs     => self.port_a.write_port(val),
+        };
 
-                // T2CntL       => () ,
-                // T2CntH       => () ,
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_irq() {
+        let mut via = M6522::new(0xd000, 0x800);
+        let mut lines = InterruptLines::new();
+        via.connect_irq(&mut lines, Pin::Irq);
+
+        // T1 one shot from $0010
+        via.store_byte(0xd004, 0x10).unwrap();
+        via.store_byte(0xd005, 0x00).unwrap();
+        via.tick(0x11);
+        via.drive_irq(&mut lines);
+        // T2 wasn't loaded so it isn't armed
+        assert_eq!(via.inspect_byte(0xd00d), Ok(0x40));
+        assert!(!lines.level(Pin::Irq));
+
+        // Enable T1
+        via.store_byte(0xd00e, 0xc0).unwrap();
+        via.drive_irq(&mut lines);
+        assert_eq!(via.inspect_byte(0xd00d), Ok(0xc0));
+        assert_eq!(lines.asserting(Pin::Irq), vec![via.device_name()]);
+
+        // Reading T1 low acknowledges it
+        via.load_byte(0xd004).unwrap();
+        via.drive_irq(&mut lines);
+        assert_eq!(via.inspect_byte(0xd00d), Ok(0x00));
+        assert!(!lines.level(Pin::Irq));
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut via = M6522::new(0xd000, 0x800);
+
+        // T2 one shot from $0010
+        via.store_byte(0xd008, 0x10).unwrap();
+        via.store_byte(0xd009, 0x00).unwrap();
+        via.tick(0x11);
+        assert_eq!(via.inspect_byte(0xd00d), Ok(0x20));
+
+        // Acknowledge, then wrap twice more
+        via.load_byte(0xd008).unwrap();
+        for _ in 0..3 {
+            via.tick(0xffff);
+        }
+        assert_eq!(via.inspect_byte(0xd00d), Ok(0x00));
 
-                // T2Lo         => () ,
-                // T2Hi         => () ,
+        // Reloading rearms it
+        via.store_byte(0xd009, 0x00).unwrap();
+        via.tick(0x11);
+        assert_eq!(via.inspect_byte(0xd00d), Ok(0x20));
+    }
 
+    #[test]
+    fn port_b_decode() {
+        let mut via = M6522::new(0xd000, 0x800);
 
+        // Latch a PSG address, DAC to the Z axis
+        via.store_byte(0xd002, 0xff).unwrap();
+        via.store_byte(0xd000, 0b1001_1100).unwrap();
 
-                // IntFlags     => () ,
-                // PortANhs     => () ,
-        };
+        let orb = via.register("orb").unwrap();
+        assert_eq!(
+            orb.summary(),
+            "gun on, PSG latch address, DAC to Z axis (brightness)"
+        );
 
+        via.store_byte(0xd000, 0b0000_0001).unwrap();
+        let orb = via.register("orb").unwrap();
+        assert_eq!(orb.field("mux").unwrap().meaning.as_deref(), Some("DAC to X axis only"));
+        assert_eq!(orb.summary(), "gun off, PSG inactive, DAC to X axis only, mux disabled");
     }
 }
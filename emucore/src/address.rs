@@ -0,0 +1,165 @@
+use grl_sources::{SourceDatabase, SourceLine};
+use std::fmt;
+use std::ops::Range;
+
+/// A cpu address, optionally tied to the physical address it must map to
+/// An address with no physical part matches whatever bank is mapped in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address {
+    pub logical: usize,
+    pub physical: Option<usize>,
+}
+
+impl Address {
+    pub fn new(logical: usize) -> Self {
+        Self {
+            logical,
+            physical: None,
+        }
+    }
+
+    pub fn banked(logical: usize, physical: usize) -> Self {
+        Self {
+            logical,
+            physical: Some(physical),
+        }
+    }
+
+    /// This address with its physical part filled in from the current mapping
+    pub fn resolve(logical: usize, map: &dyn BankMap) -> Self {
+        Self::banked(logical, map.to_physical(logical))
+    }
+
+    /// Does a cpu access to logical hit this address as currently mapped
+    pub fn matches(&self, logical: usize, map: &dyn BankMap) -> bool {
+        self.logical == logical && self.physical.is_none_or(|p| map.to_physical(logical) == p)
+    }
+}
+
+impl From<usize> for Address {
+    fn from(logical: usize) -> Self {
+        Self::new(logical)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.physical {
+            Some(p) if p != self.logical => write!(f, "${:04x}@${p:05x}", self.logical),
+            _ => write!(f, "${:04x}", self.logical),
+        }
+    }
+}
+
+/// Maps cpu addresses to physical ones
+pub trait BankMap {
+    fn to_physical(&self, logical: usize) -> usize;
+}
+
+/// No banking, physical is logical
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Flat;
+
+impl BankMap for Flat {
+    fn to_physical(&self, logical: usize) -> usize {
+        logical
+    }
+}
+
+/// A window in the cpu's address space that can show one of several banks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankWindow {
+    pub name: String,
+    pub range: Range<usize>,
+    /// Physical address of bank 0
+    pub base: usize,
+    pub bank: usize,
+}
+
+impl BankWindow {
+    fn to_physical(&self, logical: usize) -> usize {
+        self.base + self.bank * self.range.len() + (logical - self.range.start)
+    }
+}
+
+/// Paged windows, addresses outside any window are unbanked
+#[derive(Debug, Clone, Default)]
+pub struct Banks {
+    windows: Vec<BankWindow>,
+}
+
+impl Banks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_window(&mut self, name: &str, range: Range<usize>, base: usize) {
+        self.windows.push(BankWindow {
+            name: name.to_string(),
+            range,
+            base,
+            bank: 0,
+        })
+    }
+
+    /// Map a bank into a window, false if there's no such window
+    pub fn select(&mut self, name: &str, bank: usize) -> bool {
+        match self.windows.iter_mut().find(|w| w.name == name) {
+            Some(w) => {
+                w.bank = bank;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn windows(&self) -> &[BankWindow] {
+        &self.windows
+    }
+}
+
+impl BankMap for Banks {
+    fn to_physical(&self, logical: usize) -> usize {
+        self.windows
+            .iter()
+            .find(|w| w.range.contains(&logical))
+            .map_or(logical, |w| w.to_physical(logical))
+    }
+}
+
+/// Source for a cpu address in the current mapping
+/// Falls back to the logical address for sources with no physical mapping
+pub fn lookup_source<'a>(
+    sources: &'a SourceDatabase,
+    logical: usize,
+    map: &dyn BankMap,
+) -> Option<SourceLine<'a>> {
+    sources
+        .get_source_info_from_physical_address(map.to_physical(logical))
+        .or_else(|| sources.get_source_info_from_address(logical))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banked_match() {
+        let mut banks = Banks::new();
+        banks.add_window("page", 0x8000..0xc000, 0x10000);
+
+        let bp = Address::banked(0x8100, 0x14100);
+        let any = Address::new(0x8100);
+
+        assert!(!bp.matches(0x8100, &banks));
+        assert!(any.matches(0x8100, &banks));
+
+        assert!(banks.select("page", 1));
+        assert!(bp.matches(0x8100, &banks));
+        assert_eq!(
+            Address::resolve(0x1234, &banks),
+            Address::banked(0x1234, 0x1234)
+        );
+        assert_eq!(bp.to_string(), "$8100@$14100");
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use crate::address::{Address, BankMap};
 use crate::mem::{Region, RegionErr};
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Copy)]
@@ -10,7 +11,7 @@ pub enum BreakPointTypes {
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Copy)]
 pub struct BreakPoint {
-    addr: usize,
+    addr: Address,
     bp_type: BreakPointTypes,
     active: bool,
     id: usize,
@@ -18,10 +19,10 @@ pub struct BreakPoint {
 
 impl BreakPoint {
     /// Describes a breakpoint constructs defaulted to active
-    pub fn new(bp_type: BreakPointTypes, addr: usize, id: usize) -> BreakPoint {
+    pub fn new<A: Into<Address>>(bp_type: BreakPointTypes, addr: A, id: usize) -> BreakPoint {
         BreakPoint {
             bp_type,
-            addr,
+            addr: addr.into(),
             active: true,
             id,
         }
@@ -34,6 +35,14 @@ impl BreakPoint {
     pub fn toggle_active(&mut self) {
         self.active = !self.active;
     }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn addr(&self) -> Address {
+        self.addr
+    }
 }
 
 // usize def as zero
@@ -59,20 +68,30 @@ impl BreakPoints {
 
     pub fn has_any_breakpoint(&self, addr: usize) -> bool {
         let bp = self.get_breakpoints(addr, 1);
-        bp.iter().filter(|b| b.addr == addr).count() > 0
+        bp.iter().filter(|b| b.addr.logical == addr).count() > 0
     }
 
-    pub fn has_breakpoint(&self, addr: usize, bp_type: BreakPointTypes) -> bool {
-        self.find_breakpoint(addr, bp_type).is_some()
+    pub fn has_breakpoint<A: Into<Address>>(&self, addr: A, bp_type: BreakPointTypes) -> bool {
+        self.find_breakpoint(addr.into(), bp_type).is_some()
     }
 
-    fn find_breakpoint(&self, addr: usize, bp_type: BreakPointTypes) -> Option<&BreakPoint> {
+    fn find_breakpoint(&self, addr: Address, bp_type: BreakPointTypes) -> Option<&BreakPoint> {
         self.break_points
             .values()
             .find(|bp| bp.addr == addr && bp.bp_type == bp_type)
     }
 
-    pub fn add(&mut self, addr: usize, bp_type: BreakPointTypes) -> Option<usize> {
+    /// Active breakpoints an access to a cpu address hits with the current bank mapping
+    pub fn hits(&self, addr: usize, bp_type: BreakPointTypes, map: &dyn BankMap) -> Vec<&BreakPoint> {
+        self.break_points
+            .values()
+            .filter(|bp| bp.active && bp.bp_type == bp_type && bp.addr.matches(addr, map))
+            .collect()
+    }
+
+    /// Pass an Address with a physical part for a breakpoint on banked code
+    pub fn add<A: Into<Address>>(&mut self, addr: A, bp_type: BreakPointTypes) -> Option<usize> {
+        let addr = addr.into();
         if !self.has_breakpoint(addr, bp_type) {
             let ret = self.id;
             let bp = BreakPoint::new(bp_type, addr, ret);
@@ -83,8 +102,8 @@ impl BreakPoints {
             None
         }
     }
-    pub fn find_breakpoint_id(&self, addr: usize, bp_type: BreakPointTypes) -> Option<usize> {
-        self.find_breakpoint(addr, bp_type)
+    pub fn find_breakpoint_id<A: Into<Address>>(&self, addr: A, bp_type: BreakPointTypes) -> Option<usize> {
+        self.find_breakpoint(addr.into(), bp_type)
             .map(|bp| bp.id)
     }
 
@@ -92,7 +111,7 @@ impl BreakPoints {
         self.break_points.remove(&id);
     }
 
-    pub fn remove(&mut self, addr: usize, bp_type: BreakPointTypes) {
+    pub fn remove<A: Into<Address>>(&mut self, addr: A, bp_type: BreakPointTypes) {
         if let Some(id) = self.find_breakpoint_id(addr, bp_type) {
             self.break_points.remove(&id);
         }
@@ -106,7 +125,7 @@ impl BreakPoints {
         if let Ok(r) = Self::get_range(addr, range) {
             self.break_points
                 .values()
-                .filter(|bp| r.is_in_region(bp.addr))
+                .filter(|bp| r.is_in_region(bp.addr.logical))
                 .collect()
         } else {
             vec![]
@@ -117,7 +136,7 @@ impl BreakPoints {
         if let Ok(r) = Self::get_range(addr, range) {
             self.break_points
                 .values_mut()
-                .filter(|bp| r.is_in_region(bp.addr))
+                .filter(|bp| r.is_in_region(bp.addr.logical))
                 .collect()
         } else {
             vec![]
@@ -162,4 +181,22 @@ mod tests {
         bp.remove(addr, BreakPointTypes::READ);
         assert_eq!(bp.len(), 1);
     }
+
+    #[test]
+    fn banked() {
+        use crate::address::Banks;
+
+        let mut banks = Banks::new();
+        banks.add_window("rom", 0xc000..0xe000, 0x20000);
+
+        let mut bp = BreakPoints::new();
+        bp.add(Address::banked(0xc010, 0x22010), BreakPointTypes::EXEC);
+        bp.add(0xc010, BreakPointTypes::EXEC);
+        assert_eq!(bp.len(), 2);
+
+        assert_eq!(bp.hits(0xc010, BreakPointTypes::EXEC, &banks).len(), 1);
+        banks.select("rom", 1);
+        assert_eq!(bp.hits(0xc010, BreakPointTypes::EXEC, &banks).len(), 2);
+        assert!(bp.has_any_breakpoint(0xc010));
+    }
 }
@@ -0,0 +1,276 @@
+use crate::flow::Flow;
+use crate::symbols::SymbolMap;
+use grl_sources::SourceDatabase;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    Interrupt { vector: usize },
+}
+
+/// A call or interrupt that has not returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Address of the instruction that made the call
+    pub call_site: usize,
+    /// Where the call went
+    pub dest: usize,
+    /// Address pushed as the return address
+    pub ret: usize,
+    /// Stack pointer after the return address was pushed
+    pub sp: usize,
+}
+
+/// Returns that didn't match the shadow stack
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CallStackErr {
+    #[error("Return at ${pc:04x} to ${dest:04x} with nothing on the call stack")]
+    Underflow { pc: usize, dest: usize },
+    #[error("Return at ${pc:04x} to ${actual:04x}, expected ${expected:04x}")]
+    ReturnAddress {
+        pc: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Return at ${pc:04x} to ${dest:04x} unwound {frames} frames")]
+    Unwound {
+        pc: usize,
+        dest: usize,
+        frames: usize,
+    },
+    #[error("Return at ${pc:04x} does not match the kind of frame it returned from")]
+    WrongReturnKind { pc: usize },
+}
+
+/// Shadow of the call stack built from the flow recorded by a core
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    mismatches: Vec<CallStackErr>,
+    max_depth: usize,
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self {
+            frames: vec![],
+            mismatches: vec![],
+            max_depth: 1024,
+        }
+    }
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Oldest frames are dropped past this depth
+    pub fn with_max_depth(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.mismatches.clear();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn mismatches(&self) -> &[CallStackErr] {
+        &self.mismatches
+    }
+
+    pub fn take_mismatches(&mut self) -> Vec<CallStackErr> {
+        std::mem::take(&mut self.mismatches)
+    }
+
+    /// Update with a step
+    /// pc is the address of the instruction executed, sp the stack pointer after it
+    pub fn update(&mut self, pc: usize, sp: usize, flow: Flow) {
+        match flow {
+            Flow::Normal => (),
+
+            Flow::Call { dest, ret } => self.push(Frame {
+                kind: FrameKind::Call,
+                call_site: pc,
+                dest,
+                ret,
+                sp,
+            }),
+
+            Flow::Interrupt { vector, dest, ret } => self.push(Frame {
+                kind: FrameKind::Interrupt { vector },
+                call_site: pc,
+                dest,
+                ret,
+                sp,
+            }),
+
+            Flow::Return { dest } => self.pop(pc, dest, false),
+            Flow::ReturnFromInterrupt { dest } => self.pop(pc, dest, true),
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() >= self.max_depth {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    fn pop(&mut self, pc: usize, dest: usize, is_rti: bool) {
+        let Some(top) = self.frames.last().cloned() else {
+            self.mismatches.push(CallStackErr::Underflow { pc, dest });
+            return;
+        };
+
+        if top.ret == dest {
+            self.frames.pop();
+
+            if is_rti != matches!(top.kind, FrameKind::Interrupt { .. }) {
+                self.mismatches.push(CallStackErr::WrongReturnKind { pc });
+            }
+            return;
+        }
+
+        // Returned further up the stack, eg after discarding a return address
+        if let Some(pos) = self.frames.iter().rposition(|f| f.ret == dest) {
+            let frames = self.frames.len() - pos;
+            self.frames.truncate(pos);
+            self.mismatches
+                .push(CallStackErr::Unwound { pc, dest, frames });
+        } else {
+            // Return address was altered, leave the stack as it is
+            self.mismatches.push(CallStackErr::ReturnAddress {
+                pc,
+                expected: top.ret,
+                actual: dest,
+            });
+        }
+    }
+
+    /// Frames innermost first with names and source resolved where possible
+    pub fn backtrace(
+        &self,
+        syms: &SymbolMap,
+        sources: Option<&SourceDatabase>,
+    ) -> Vec<BacktraceFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| BacktraceFrame {
+                frame: *frame,
+                routine: syms.describe(frame.dest),
+                call_site: syms.describe(frame.call_site),
+                source: sources.and_then(|s| {
+                    s.get_source_info_from_address(frame.call_site)
+                        .map(|l| format!("{}:{}", l.file.to_string_lossy(), l.line_number))
+                }),
+            })
+            .collect()
+    }
+}
+
+/// A frame resolved for display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    pub frame: Frame,
+    pub routine: String,
+    pub call_site: String,
+    /// file:line of the call site if known
+    pub source: Option<String>,
+}
+
+impl std::fmt::Display for BacktraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.frame.kind {
+            FrameKind::Call => "".to_string(),
+            FrameKind::Interrupt { vector } => format!(" [int ${vector:04x}]"),
+        };
+
+        write!(
+            f,
+            "{}{} from {} (${:04x}) ret ${:04x} sp ${:04x}",
+            self.routine, kind, self.call_site, self.frame.call_site, self.frame.ret, self.frame.sp
+        )?;
+
+        if let Some(src) = &self.source {
+            write!(f, " at {src}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(dest: usize, ret: usize) -> Flow {
+        Flow::Call { dest, ret }
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut cs = CallStack::new();
+
+        cs.update(0x1000, 0x7ffe, call(0x2000, 0x1003));
+        cs.update(0x2000, 0x7ffc, call(0x3000, 0x2003));
+        assert_eq!(cs.depth(), 2);
+
+        let bt = cs.backtrace(&SymbolMap::new(), None);
+        assert_eq!(bt[0].frame.dest, 0x3000);
+        assert_eq!(bt[1].frame.call_site, 0x1000);
+
+        cs.update(0x3000, 0x7ffe, Flow::Return { dest: 0x2003 });
+        cs.update(0x2003, 0x8000, Flow::Return { dest: 0x1003 });
+        assert_eq!(cs.depth(), 0);
+        assert!(cs.mismatches().is_empty());
+    }
+
+    #[test]
+    fn mismatches() {
+        let mut cs = CallStack::new();
+
+        cs.update(0x1000, 0x7ffe, call(0x2000, 0x1003));
+        cs.update(0x2000, 0x7ffe, Flow::Return { dest: 0x4000 });
+        assert_eq!(cs.depth(), 1);
+
+        cs.update(0x2000, 0x7ffc, call(0x3000, 0x2003));
+        cs.update(0x3000, 0x8000, Flow::Return { dest: 0x1003 });
+        assert_eq!(cs.depth(), 0);
+
+        cs.update(0x1003, 0x8002, Flow::Return { dest: 0x5000 });
+
+        assert_eq!(
+            cs.take_mismatches(),
+            vec![
+                CallStackErr::ReturnAddress {
+                    pc: 0x2000,
+                    expected: 0x1003,
+                    actual: 0x4000
+                },
+                CallStackErr::Unwound {
+                    pc: 0x3000,
+                    dest: 0x1003,
+                    frames: 2
+                },
+                CallStackErr::Underflow {
+                    pc: 0x1003,
+                    dest: 0x5000
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use crate::address::{Address, BankMap};
+use std::collections::BTreeMap;
+
+/// Execution counts keyed by physical address
+/// so code paged into the same window is counted separately
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    hits: BTreeMap<usize, usize>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an instruction executed at a cpu address
+    pub fn add(&mut self, pc: usize, map: &dyn BankMap) {
+        *self.hits.entry(map.to_physical(pc)).or_default() += 1
+    }
+
+    /// Times the address was executed
+    /// An address with no physical part is looked up as currently mapped
+    pub fn count(&self, addr: Address, map: &dyn BankMap) -> usize {
+        let phys = addr
+            .physical
+            .unwrap_or_else(|| map.to_physical(addr.logical));
+        self.hits.get(&phys).copied().unwrap_or(0)
+    }
+
+    pub fn is_covered(&self, addr: Address, map: &dyn BankMap) -> bool {
+        self.count(addr, map) != 0
+    }
+
+    /// Physical addresses executed and their counts
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.hits.iter().map(|(a, c)| (*a, *c))
+    }
+
+    pub fn clear(&mut self) {
+        self.hits.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Banks;
+
+    #[test]
+    fn per_bank() {
+        let mut banks = Banks::new();
+        banks.add_window("page", 0x4000..0x8000, 0x10000);
+
+        let mut cov = Coverage::new();
+        cov.add(0x4000, &banks);
+        banks.select("page", 2);
+        cov.add(0x4000, &banks);
+        cov.add(0x4000, &banks);
+        cov.add(0x100, &banks);
+
+        assert_eq!(cov.count(Address::new(0x4000), &banks), 2);
+        assert_eq!(cov.count(Address::banked(0x4000, 0x10000), &banks), 1);
+        assert!(cov.is_covered(Address::new(0x100), &banks));
+        assert_eq!(cov.iter().count(), 3);
+    }
+}
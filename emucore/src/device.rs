@@ -0,0 +1,202 @@
+use std::fmt;
+
+/// A run of bits in a device register
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitField {
+    pub name: String,
+    pub lsb: u32,
+    pub width: u32,
+    pub value: u64,
+    /// What the value means, eg "T1 free running"
+    pub meaning: Option<String>,
+}
+
+impl fmt::Display for BitField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits = if self.width == 1 {
+            format!("{}", self.lsb)
+        } else {
+            format!("{}..{}", self.lsb + self.width - 1, self.lsb)
+        };
+
+        write!(
+            f,
+            "{:<6} {:<14} %{:0w$b}",
+            bits,
+            self.name,
+            self.value,
+            w = self.width as usize
+        )?;
+
+        if let Some(m) = &self.meaning {
+            write!(f, "  {m}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A device register as the device sees it, with its bits decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceReg {
+    pub name: String,
+    /// Offset from the device's base address
+    pub offset: usize,
+    /// Size in bytes
+    pub width: usize,
+    pub value: u64,
+    pub fields: Vec<BitField>,
+}
+
+impl DeviceReg {
+    pub fn new(name: &str, offset: usize, value: u8) -> Self {
+        Self {
+            name: name.to_string(),
+            offset,
+            width: 1,
+            value: value as u64,
+            fields: vec![],
+        }
+    }
+
+    pub fn word(name: &str, offset: usize, value: u16) -> Self {
+        Self {
+            width: 2,
+            value: value as u64,
+            ..Self::new(name, offset, 0)
+        }
+    }
+
+    fn get(&self, lsb: u32, width: u32) -> u64 {
+        (self.value >> lsb) & ((1 << width) - 1)
+    }
+
+    /// A field with no decoding
+    pub fn bits(self, name: &str, lsb: u32, width: u32) -> Self {
+        self.decode(name, lsb, width, |_| None)
+    }
+
+    /// A one bit field, meaning is shown when it's set
+    pub fn flag(self, name: &str, bit: u32, meaning: &str) -> Self {
+        self.decode(name, bit, 1, |v| (v != 0).then(|| meaning.to_string()))
+    }
+
+    /// A field whose value is explained by f
+    pub fn decode<F: Fn(u64) -> Option<String>>(
+        mut self,
+        name: &str,
+        lsb: u32,
+        width: u32,
+        f: F,
+    ) -> Self {
+        let value = self.get(lsb, width);
+
+        self.fields.push(BitField {
+            name: name.to_string(),
+            lsb,
+            width,
+            value,
+            meaning: f(value),
+        });
+        self
+    }
+
+    pub fn field(&self, name: &str) -> Option<&BitField> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Meanings of the fields in one line
+    pub fn summary(&self) -> String {
+        self.fields
+            .iter()
+            .filter_map(|f| f.meaning.clone())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for DeviceReg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8} +{:x} = ${:0w$x}",
+            self.name,
+            self.offset,
+            self.value,
+            w = self.width * 2
+        )?;
+
+        let summary = self.summary();
+        if !summary.is_empty() {
+            write!(f, "  {summary}")?;
+        }
+
+        for field in &self.fields {
+            write!(f, "\n    {field}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A device that can show its registers without side effects
+/// so reading a status register here doesn't clear its flags
+pub trait DeviceInspect {
+    fn device_name(&self) -> String;
+
+    fn registers(&self) -> Vec<DeviceReg>;
+
+    fn register(&self, name: &str) -> Option<DeviceReg> {
+        self.registers()
+            .into_iter()
+            .find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Timer {
+        ctrl: u8,
+        count: u16,
+    }
+
+    impl DeviceInspect for Timer {
+        fn device_name(&self) -> String {
+            "timer".into()
+        }
+
+        fn registers(&self) -> Vec<DeviceReg> {
+            vec![
+                DeviceReg::new("CTRL", 0, self.ctrl)
+                    .flag("run", 7, "running")
+                    .decode("mode", 4, 3, |v| Some(format!("mode {v:03b}")))
+                    .bits("prescale", 0, 4),
+                DeviceReg::word("COUNT", 1, self.count),
+            ]
+        }
+    }
+
+    #[test]
+    fn decode() {
+        let t = Timer {
+            ctrl: 0b1110_0011,
+            count: 0x1234,
+        };
+
+        let r = t.register("ctrl").unwrap();
+        assert_eq!(r.field("mode").unwrap().value, 0b110);
+        assert_eq!(r.field("prescale").unwrap().value, 3);
+        assert_eq!(r.summary(), "running, mode 110");
+        assert_eq!(
+            r.to_string(),
+            "CTRL     +0 = $e3  running, mode 110
+    7      run            %1  running
+    6..4   mode           %110  mode 110
+    3..0   prescale       %0011"
+        );
+        assert_eq!(
+            t.register("COUNT").unwrap().to_string(),
+            "COUNT    +1 = $1234"
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use crate::isa::{IsaDbase, IsaMode, IsaOp};
+use crate::mem::{MemResult, MemoryIO};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+/// Names for addresses in operands
+pub trait Labels {
+    fn label(&self, addr: usize) -> Option<String>;
+}
+
+impl Labels for () {
+    fn label(&self, _addr: usize) -> Option<String> {
+        None
+    }
+}
+
+impl Labels for HashMap<usize, String> {
+    fn label(&self, addr: usize) -> Option<String> {
+        self.get(&addr).cloned()
+    }
+}
+
+impl Labels for BTreeMap<usize, String> {
+    fn label(&self, addr: usize) -> Option<String> {
+        self.get(&addr).cloned()
+    }
+}
+
+/// The operand bytes of an instruction, handed to the cpu's formatter
+pub struct Operand<'a> {
+    /// Address of the instruction
+    pub addr: usize,
+    /// Address of the instruction after
+    pub next: usize,
+    /// Bytes after the opcode
+    pub bytes: &'a [u8],
+    labels: &'a dyn Labels,
+}
+
+impl<'a> Operand<'a> {
+    pub fn byte(&self, i: usize) -> u8 {
+        self.bytes.get(i).copied().unwrap_or(0)
+    }
+
+    pub fn word_be(&self, i: usize) -> u16 {
+        u16::from_be_bytes([self.byte(i), self.byte(i + 1)])
+    }
+
+    pub fn word_le(&self, i: usize) -> u16 {
+        u16::from_le_bytes([self.byte(i), self.byte(i + 1)])
+    }
+
+    /// Target of an 8 bit branch whose offset is byte i
+    pub fn rel8(&self, i: usize) -> usize {
+        self.next.wrapping_add(self.byte(i) as i8 as usize) & 0xffff
+    }
+
+    /// Target of a 16 bit big endian branch whose offset is at byte i
+    pub fn rel16_be(&self, i: usize) -> usize {
+        self.next.wrapping_add(self.word_be(i) as i16 as usize) & 0xffff
+    }
+
+    /// The label for addr, or addr in hex with this many digits and prefix
+    pub fn name(&self, addr: usize, digits: usize, prefix: &str) -> String {
+        self.labels
+            .label(addr)
+            .unwrap_or_else(|| format!("{prefix}{addr:0digits$x}"))
+    }
+}
+
+/// Where control can go after an instruction, for flow following disassembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpFlow {
+    /// On to the next instruction
+    Next,
+    /// Conditional, either the target or the next instruction
+    Branch(usize),
+    /// Only the target
+    Jump(usize),
+    /// The target, then back to the next instruction
+    Call(usize),
+    /// Return, or a jump to somewhere that can't be known statically
+    Stop,
+}
+
+/// The cpu specific part of disassembly
+pub trait OperandFormat<A: IsaMode> {
+    /// Operand text, empty for none
+    fn operand(&self, op: &IsaOp<A>, o: &Operand) -> String;
+
+    /// Size of the instruction at addr, for ops whose size depends on their operand
+    fn size(&self, op: &IsaOp<A>, _mem: &dyn MemoryIO, _addr: usize) -> usize {
+        op.size
+    }
+
+    fn flow(&self, _op: &IsaOp<A>, _o: &Operand) -> OpFlow {
+        OpFlow::Next
+    }
+
+    /// Memory the operand reads or writes, if it names an address
+    fn data_ref(&self, _op: &IsaOp<A>, _o: &Operand) -> Option<usize> {
+        None
+    }
+
+    /// Byte order of words in tables
+    fn big_endian(&self) -> bool {
+        true
+    }
+}
+
+impl<A: IsaMode, F: Fn(&IsaOp<A>, &Operand) -> String> OperandFormat<A> for F {
+    fn operand(&self, op: &IsaOp<A>, o: &Operand) -> String {
+        self(op, o)
+    }
+}
+
+/// One disassembled instruction, or a byte that isn't one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DissLine<A> {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    /// None for a byte that isn't an opcode
+    pub op: Option<IsaOp<A>>,
+    pub mnemonic: String,
+    pub operand: String,
+}
+
+impl<A> DissLine<A> {
+    pub fn next(&self) -> usize {
+        self.addr + self.bytes.len()
+    }
+
+    /// Mnemonic and operand
+    pub fn text(&self) -> String {
+        if self.operand.is_empty() {
+            self.mnemonic.clone()
+        } else {
+            format!("{} {}", self.mnemonic, self.operand)
+        }
+    }
+
+    pub fn bytes_text(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl<A> fmt::Display for DissLine<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x} {:<12} {}",
+            self.addr,
+            self.bytes_text(),
+            self.text()
+        )
+    }
+}
+
+/// Disassembles with an ISA table and a cpu's operand formatter
+pub struct Disassembler<A: IsaMode, F: OperandFormat<A>> {
+    isa: IsaDbase<A>,
+    format: F,
+}
+
+impl<A: IsaMode, F: OperandFormat<A>> Disassembler<A, F> {
+    pub fn new(isa: IsaDbase<A>, format: F) -> Self {
+        Self { isa, format }
+    }
+
+    pub fn isa(&self) -> &IsaDbase<A> {
+        &self.isa
+    }
+
+    pub fn format(&self) -> &F {
+        &self.format
+    }
+
+    pub fn diss(&self, mem: &dyn MemoryIO, addr: usize) -> MemResult<DissLine<A>> {
+        self.diss_with(mem, addr, &())
+    }
+
+    /// Disassemble with names for addresses
+    pub fn diss_with(
+        &self,
+        mem: &dyn MemoryIO,
+        addr: usize,
+        labels: &dyn Labels,
+    ) -> MemResult<DissLine<A>> {
+        let Some(op) = self.isa.decode(mem, addr) else {
+            let b = mem.inspect_byte(addr)?;
+            return Ok(DissLine {
+                addr,
+                bytes: vec![b],
+                op: None,
+                mnemonic: "fcb".into(),
+                operand: format!("${b:02x}"),
+            });
+        };
+
+        let size = self.format.size(op, mem, addr).max(op.opcode_bytes());
+        let bytes = (addr..addr + size)
+            .map(|a| mem.inspect_byte(a))
+            .collect::<MemResult<Vec<_>>>()?;
+
+        let operand = Operand {
+            addr,
+            next: addr + size,
+            bytes: &bytes[op.opcode_bytes()..],
+            labels,
+        };
+
+        let operand = self.format.operand(op, &operand);
+
+        Ok(DissLine {
+            addr,
+            mnemonic: op.mnemonic.to_lowercase(),
+            op: Some(op.clone()),
+            bytes,
+            operand,
+        })
+    }
+
+    fn with_operand<R, G: FnOnce(&IsaOp<A>, &Operand) -> R>(
+        &self,
+        line: &DissLine<A>,
+        f: G,
+    ) -> Option<R> {
+        let op = line.op.as_ref()?;
+
+        let o = Operand {
+            addr: line.addr,
+            next: line.next(),
+            bytes: &line.bytes[op.opcode_bytes()..],
+            labels: &(),
+        };
+
+        Some(f(op, &o))
+    }
+
+    /// Where control goes after line, Stop for a byte that isn't an opcode
+    pub fn flow(&self, line: &DissLine<A>) -> OpFlow {
+        self.with_operand(line, |op, o| self.format.flow(op, o))
+            .unwrap_or(OpFlow::Stop)
+    }
+
+    pub fn data_ref(&self, line: &DissLine<A>) -> Option<usize> {
+        self.with_operand(line, |op, o| self.format.data_ref(op, o))
+            .flatten()
+    }
+
+    /// Disassemble instructions starting in range
+    /// Stops early at memory that can't be read
+    pub fn iter<'a>(&'a self, mem: &'a dyn MemoryIO, range: Range<usize>) -> DissIter<'a, A, F> {
+        DissIter {
+            diss: self,
+            mem,
+            labels: &(),
+            addr: range.start,
+            end: range.end,
+        }
+    }
+
+    pub fn iter_with<'a>(
+        &'a self,
+        mem: &'a dyn MemoryIO,
+        range: Range<usize>,
+        labels: &'a dyn Labels,
+    ) -> DissIter<'a, A, F> {
+        DissIter {
+            labels,
+            ..self.iter(mem, range)
+        }
+    }
+}
+
+pub struct DissIter<'a, A: IsaMode, F: OperandFormat<A>> {
+    diss: &'a Disassembler<A, F>,
+    mem: &'a dyn MemoryIO,
+    labels: &'a dyn Labels,
+    addr: usize,
+    end: usize,
+}
+
+impl<'a, A: IsaMode, F: OperandFormat<A>> Iterator for DissIter<'a, A, F> {
+    type Item = DissLine<A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.addr >= self.end {
+            return None;
+        }
+
+        match self.diss.diss_with(self.mem, self.addr, self.labels) {
+            Ok(line) => {
+                self.addr = line.next();
+                Some(line)
+            }
+            Err(_) => {
+                self.addr = self.end;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemBlock;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+    enum Mode {
+        #[default]
+        Inherent,
+        Immediate,
+        Extended,
+        Relative,
+    }
+
+    fn operand(op: &IsaOp<Mode>, o: &Operand) -> String {
+        match op.addr_mode {
+            Mode::Inherent => String::new(),
+            Mode::Immediate => format!("#${:02x}", o.byte(0)),
+            Mode::Extended => o.name(o.word_be(0) as usize, 4, "$"),
+            Mode::Relative => o.name(o.rel8(0), 4, "$"),
+        }
+    }
+
+    #[test]
+    fn iterate() {
+        let isa = IsaDbase::<Mode>::parse(
+            r#"{ "instructions": {
+                "Nop": { "addr_modes": { "Inherent": { "opcode": "12", "cycles": 2, "size": 1 } } },
+                "Lda": { "addr_modes": { "Immediate": { "opcode": "86", "cycles": 2, "size": 2 } } },
+                "Jmp": { "addr_modes": { "Extended": { "opcode": "7e", "cycles": 3, "size": 3 } } },
+                "Bra": { "addr_modes": { "Relative": { "opcode": "20", "cycles": 3, "size": 2 } } },
+                "Ldy": { "addr_modes": { "Immediate": { "opcode": "108e", "cycles": 4, "size": 4 } } }
+            }}"#,
+        )
+        .unwrap();
+
+        let code = [
+            0x12, 0x86, 0x41, 0x7e, 0x10, 0x00, 0x20, 0xf8, 0x01, 0x10, 0x8e, 0x12, 0x34,
+        ];
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0x1000, "rom", &code, true);
+        let diss = Disassembler::new(isa, operand);
+        let labels: HashMap<usize, String> = [(0x1000, "start".to_string())].into();
+
+        let lines: Vec<_> = diss
+            .iter_with(&mem, 0x1000..0x1000 + code.len(), &labels)
+            .map(|l| l.to_string())
+            .collect();
+
+        assert_eq!(
+            lines,
+            [
+                "1000 12           nop",
+                "1001 86 41        lda #$41",
+                "1003 7e 10 00     jmp start",
+                "1006 20 f8        bra start",
+                "1008 01           fcb $01",
+                "1009 10 8e 12 34  ldy #$12",
+            ]
+        );
+
+        // Stops where memory ends
+        assert_eq!(diss.iter(&mem, 0x100b..0x1010).count(), 2);
+    }
+}
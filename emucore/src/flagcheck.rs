@@ -0,0 +1,198 @@
+use crate::flagmods::FlagMods;
+use crate::intsched::InterruptTarget;
+use crate::isa::{IsaDbase, IsaMode};
+use crate::run::RunTarget;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A machine whose status register can be checked against its ISA
+pub trait FlagTarget: RunTarget + InterruptTarget {
+    /// Flag names most significant first, - for bits with no flag
+    const FLAG_LAYOUT: &'static str;
+
+    fn flags(&self) -> u64;
+}
+
+/// An instruction that changed flags in a way its ISA says it doesn't
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlagViolation {
+    pub pc: usize,
+    pub opcode: usize,
+    pub mnemonic: String,
+    pub before: u64,
+    pub after: u64,
+    /// What the ISA declares, most significant first
+    pub expected: String,
+    /// Bits that broke the declaration
+    pub bits: u64,
+    pub layout: &'static str,
+}
+
+impl FlagViolation {
+    /// Names of the offending flags
+    pub fn flag_names(&self) -> String {
+        let width = self.layout.chars().count();
+
+        self.layout
+            .chars()
+            .enumerate()
+            .filter(|(i, _)| self.bits & (1 << (width - 1 - i)) != 0)
+            .map(|(_, c)| c)
+            .collect()
+    }
+}
+
+impl fmt::Display for FlagViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let w = self.layout.chars().count();
+        write!(
+            f,
+            "${:04x} {} (${:02x}): {} %{:0w$b} -> %{:0w$b}, expected {}, bad {}",
+            self.pc,
+            self.mnemonic,
+            self.opcode,
+            self.layout,
+            self.before,
+            self.after,
+            self.expected,
+            self.flag_names(),
+        )
+    }
+}
+
+/// Steps a machine and checks every instruction's effect on the flags
+/// Ops the ISA has no flags for aren't checked
+pub struct FlagChecker<'a, A: IsaMode> {
+    isa: &'a IsaDbase<A>,
+    /// Per opcode, None if there's nothing to check
+    mods: HashMap<usize, Option<FlagMods>>,
+    violations: Vec<FlagViolation>,
+}
+
+impl<'a, A: IsaMode> FlagChecker<'a, A> {
+    pub fn new(isa: &'a IsaDbase<A>) -> Self {
+        Self {
+            isa,
+            mods: HashMap::new(),
+            violations: vec![],
+        }
+    }
+
+    /// Step once, returning a violation if there was one
+    pub fn step<T: FlagTarget>(&mut self, target: &mut T) -> Result<Option<FlagViolation>, T::Err> {
+        let isa = self.isa;
+        let pc = target.pc();
+        let op = isa.decode(target.mem(), pc);
+        let before = target.flags();
+
+        target.step()?;
+
+        // Interrupt entry is a step of its own
+        if target.taken_interrupt().is_some() {
+            return Ok(None);
+        }
+
+        let Some(op) = op else {
+            return Ok(None);
+        };
+
+        let mods = *self.mods.entry(op.opcode).or_insert_with(|| {
+            isa.flag_mods(op, T::FLAG_LAYOUT).unwrap_or_else(|e| {
+                log::warn!("Can't check flags of {}: {e}", op.mnemonic);
+                None
+            })
+        });
+
+        let Some(mods) = mods else {
+            return Ok(None);
+        };
+
+        let after = target.flags();
+        let bits = mods.violations(before, after);
+
+        if bits == 0 {
+            return Ok(None);
+        }
+
+        let v = FlagViolation {
+            pc,
+            opcode: op.opcode,
+            mnemonic: op.mnemonic.clone(),
+            before,
+            after,
+            expected: mods.into(),
+            bits,
+            layout: T::FLAG_LAYOUT,
+        };
+
+        self.violations.push(v.clone());
+        Ok(Some(v))
+    }
+
+    /// Run for this many instructions, returning how many violated their flags
+    pub fn run<T: FlagTarget>(
+        &mut self,
+        target: &mut T,
+        instructions: usize,
+    ) -> Result<usize, T::Err> {
+        let mut found = 0;
+
+        for _ in 0..instructions {
+            if self.step(target)?.is_some() {
+                found += 1
+            }
+        }
+
+        Ok(found)
+    }
+
+    pub fn violations(&self) -> &[FlagViolation] {
+        &self.violations
+    }
+
+    pub fn take(&mut self) -> Vec<FlagViolation> {
+        std::mem::take(&mut self.violations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmachine::Toy;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+    enum Mode {
+        #[default]
+        Inherent,
+    }
+
+    #[test]
+    fn check() {
+        let isa = IsaDbase::<Mode>::parse(
+            r#"{
+            "flag_order": "CZ",
+            "instructions": {
+                "Nop": { "addr_modes": { "Inherent": { "opcode": 0, "cycles": 2, "size": 1 } } },
+                "Sec": { "flags": "1-", "addr_modes": { "Inherent": { "opcode": 6, "cycles": 2, "size": 1 } } },
+                "Clc": { "flags": "0-", "addr_modes": { "Inherent": { "opcode": 7, "cycles": 2, "size": 1 } } }
+            }
+        }"#,
+        )
+        .unwrap();
+
+        // nop, sec, clc that also clears Z by mistake
+        let mut m = Toy::new(&[(0, &[0x00, 0x06, 0x07])]).buggy();
+        m.sr = 0b010;
+
+        let mut checker = FlagChecker::new(&isa);
+        assert_eq!(checker.run(&mut m, 4).unwrap(), 1);
+
+        let v = &checker.violations()[0];
+        assert_eq!((v.pc, v.opcode, v.before, v.after), (2, 7, 0b011, 0b000));
+        assert_eq!(v.flag_names(), "Z");
+        assert_eq!(
+            v.to_string(),
+            "$0002 Clc ($07): -----IZC %00000011 -> %00000000, expected -------0, bad Z"
+        );
+    }
+}
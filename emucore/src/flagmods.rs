@@ -1,9 +1,23 @@
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
 
-// TODO: Maybe extend this to N bits and always represent internally as a u64?
-// would allow to work on procflags up to 64 bits
+/// Widest status register FlagMods can describe
+pub const MAX_FLAGS: usize = 64;
 
-/// Represents how a set of bits of 8 bits can be modified
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FlagModErr {
+    #[error("{0} isn't one of -01+")]
+    BadChar(char),
+    #[error("{0} flags is more than {MAX_FLAGS}")]
+    TooWide(usize),
+    #[error("No flag called {0} in {1}")]
+    UnknownFlag(char, String),
+    #[error("{0} flags for {1} names")]
+    Length(usize, usize),
+}
+
+/// Represents how a bit of a status register can be modified
 /// used for debugging to check emulator against desired results
 #[derive(Debug, Copy, Clone, PartialEq, Hash, Eq, Deserialize, Serialize, Default)]
 pub enum FlagMod {
@@ -18,6 +32,27 @@ pub enum FlagMod {
     Altered,
 }
 
+impl FlagMod {
+    pub fn from_char(val: char) -> Option<Self> {
+        match val {
+            '-' => Some(FlagMod::Unaltered),
+            '1' => Some(FlagMod::One),
+            '0' => Some(FlagMod::Zero),
+            '+' => Some(FlagMod::Altered),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            FlagMod::Unaltered => '-',
+            FlagMod::One => '1',
+            FlagMod::Zero => '0',
+            FlagMod::Altered => '+',
+        }
+    }
+}
+
 /// Converts a char into a FlagMod
 /// - => unaltered
 /// + => altered
@@ -25,82 +60,133 @@ pub enum FlagMod {
 /// 1 => one
 impl From<char> for FlagMod {
     fn from(val: char) -> Self {
-        match val {
-            '-' => FlagMod::Unaltered,
-            '1' => FlagMod::One,
-            '0' => FlagMod::Zero,
-            '+' => FlagMod::Altered,
-            _ => panic!("What the hell is this {val}"),
-        }
+        FlagMod::from_char(val).unwrap_or_else(|| panic!("What the hell is this {val}"))
     }
 }
 
-/// A collection of 8 FlagMods
+/// A FlagMod for each bit of a status register up to 64 bits wide
 /// and masks representing each mod type
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct FlagMods {
-    mods: [FlagMod; 8],
-    pub alter_mask: u8,
-    pub one_mask: u8,
-    pub zero_mask: u8,
-    pub unaltered_mask: u8,
+    mods: [FlagMod; MAX_FLAGS],
+    width: usize,
+    pub alter_mask: u64,
+    pub one_mask: u64,
+    pub zero_mask: u64,
+    pub unaltered_mask: u64,
+}
+
+impl Default for FlagMods {
+    fn default() -> Self {
+        Self::from_mods([FlagMod::Unaltered; 8])
+    }
 }
 
 impl FlagMods {
-    pub fn mods(&self) -> &[FlagMod; 8] {
-        &self.mods
+    /// Bit 0 first
+    pub fn mods(&self) -> &[FlagMod] {
+        &self.mods[..self.width]
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
     }
 
-    pub fn from_mods(mods: [FlagMod; 8]) -> Self {
+    /// Bit 0 first
+    pub fn from_mods<M: AsRef<[FlagMod]>>(mods: M) -> Self {
+        let mods = mods.as_ref();
+        assert!(mods.len() <= MAX_FLAGS);
+
+        let mut all = [FlagMod::Unaltered; MAX_FLAGS];
+        all[..mods.len()].copy_from_slice(mods);
+
         use FlagMod::*;
         FlagMods {
-            mods,
-            alter_mask: create_mask(&mods, Altered),
-            one_mask: create_mask(&mods, One),
-            unaltered_mask: create_mask(&mods, Unaltered),
-            zero_mask: create_mask(&mods, Zero),
+            mods: all,
+            width: mods.len(),
+            alter_mask: create_mask(mods, Altered),
+            one_mask: create_mask(mods, One),
+            unaltered_mask: create_mask(mods, Unaltered),
+            zero_mask: create_mask(mods, Zero),
         }
     }
 
     pub fn set_mod(self, idx: usize, val: FlagMod) -> Self {
-        assert!(idx < 8);
+        assert!(idx < self.width);
         let mut mods = self.mods;
         mods[idx] = val;
-        Self::from_mods(mods)
+        Self::from_mods(&mods[..self.width])
+    }
+
+    /// Mods written in one order laid out in another
+    /// eg order "NZC" with mods "+0-" onto layout "NV-BDIZC"
+    /// layout is most significant first with - for unused bits
+    pub fn from_named(order: &str, mods: &str, layout: &str) -> Result<Self, FlagModErr> {
+        let (order, mods): (Vec<_>, Vec<_>) = (order.chars().collect(), mods.chars().collect());
+
+        if order.len() != mods.len() {
+            return Err(FlagModErr::Length(mods.len(), order.len()));
+        }
+
+        let width = layout.chars().count();
+        if width > MAX_FLAGS {
+            return Err(FlagModErr::TooWide(width));
+        }
+
+        let mut ret = [FlagMod::Unaltered; MAX_FLAGS];
+
+        for (name, m) in order.iter().zip(mods) {
+            let m = FlagMod::from_char(m).ok_or(FlagModErr::BadChar(m))?;
+            let pos = layout
+                .chars()
+                .position(|c| c == *name && c != '-')
+                .ok_or_else(|| FlagModErr::UnknownFlag(*name, layout.to_string()))?;
+            ret[width - 1 - pos] = m;
+        }
+
+        Ok(Self::from_mods(&ret[..width]))
+    }
+
+    /// Bits that changed in a way these mods don't allow
+    pub fn violations(&self, before: u64, after: u64) -> u64 {
+        ((before ^ after) & self.unaltered_mask)
+            | (!after & self.one_mask)
+            | (after & self.zero_mask)
     }
 }
 
-impl From<String> for FlagMods {
-    fn from(txt: String) -> Self {
-        assert_eq!(txt.len(), 8);
+/// Most significant first
+impl FromStr for FlagMods {
+    type Err = FlagModErr;
+
+    fn from_str(txt: &str) -> Result<Self, Self::Err> {
         let mods = txt
             .chars()
-            .map(|x| FlagMod::from(x as char))
             .rev()
-            .collect::<Vec<_>>();
+            .map(|c| FlagMod::from_char(c).ok_or(FlagModErr::BadChar(c)))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let mods = mods.try_into().unwrap();
-        Self::from_mods(mods)
+        if mods.len() > MAX_FLAGS {
+            return Err(FlagModErr::TooWide(mods.len()));
+        }
+
+        Ok(Self::from_mods(mods))
+    }
+}
+
+impl From<String> for FlagMods {
+    fn from(txt: String) -> Self {
+        txt.parse().unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
 impl Into<String> for FlagMods {
     fn into(self) -> String {
-        use FlagMod::*;
-        self.mods
-            .iter()
-            .rev()
-            .map(|x| match x {
-                Unaltered => '-',
-                One => '1',
-                Zero => '0',
-                Altered => '+',
-            })
-            .collect()
+        self.mods().iter().rev().map(|x| x.to_char()).collect()
     }
 }
 
-fn create_mask(flags: &[FlagMod; 8], flag_mod: FlagMod) -> u8 {
+fn create_mask(flags: &[FlagMod], flag_mod: FlagMod) -> u64 {
     let mut ret = 0;
     for (i, flag) in flags.iter().enumerate() {
         if flag == &flag_mod {
@@ -109,3 +195,42 @@ fn create_mask(flags: &[FlagMod; 8], flag_mod: FlagMod) -> u8 {
     }
     ret
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide() {
+        let txt = "1-+0".repeat(4);
+        let mods: FlagMods = txt.parse().unwrap();
+        assert_eq!(mods.width(), 16);
+        assert_eq!(mods.zero_mask, 0x1111);
+        assert_eq!(mods.one_mask, 0x8888);
+        let back: String = mods.into();
+        assert_eq!(back, txt);
+
+        assert_eq!("--x".parse::<FlagMods>(), Err(FlagModErr::BadChar('x')));
+    }
+
+    #[test]
+    fn named() {
+        let mods = FlagMods::from_named("NZCIDV", "++0-1+", "NV-BDIZC").unwrap();
+        let txt: String = mods.into();
+        assert_eq!(txt, "++--1-+0");
+
+        assert_eq!(
+            FlagMods::from_named("NQ", "++", "NV-BDIZC"),
+            Err(FlagModErr::UnknownFlag('Q', "NV-BDIZC".into()))
+        );
+    }
+
+    #[test]
+    fn violations() {
+        let mods: FlagMods = "-+10".parse().unwrap();
+        assert_eq!(mods.violations(0b0110, 0b0010), 0);
+        assert_eq!(mods.violations(0b0010, 0b0110), 0);
+        // bit 3 changed, bit 1 cleared, bit 0 set
+        assert_eq!(mods.violations(0b0010, 0b1001), 0b1011);
+    }
+}
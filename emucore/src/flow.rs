@@ -0,0 +1,31 @@
+/// What a single step of a cpu did to the flow of control
+/// Cores record this so diagnostics can follow calls and returns
+/// without having to decode instructions themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Flow {
+    #[default]
+    /// Fell through or branched
+    Normal,
+    /// Subroutine call
+    Call { dest: usize, ret: usize },
+    /// Software or hardware interrupt taken
+    Interrupt {
+        vector: usize,
+        dest: usize,
+        ret: usize,
+    },
+    /// Return from subroutine
+    Return { dest: usize },
+    /// Return from interrupt
+    ReturnFromInterrupt { dest: usize },
+}
+
+impl Flow {
+    pub fn is_call(&self) -> bool {
+        matches!(self, Flow::Call { .. } | Flow::Interrupt { .. })
+    }
+
+    pub fn is_return(&self) -> bool {
+        matches!(self, Flow::Return { .. } | Flow::ReturnFromInterrupt { .. })
+    }
+}
@@ -0,0 +1,597 @@
+use crate::diss::{Disassembler, DissLine, OpFlow, OperandFormat};
+use crate::isa::IsaMode;
+use crate::mem::{MemResult, MemoryIO};
+use grl_symbols::{ScopeIdTraits, SymIdTraits, SymbolTree};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
+/// Assembler directive names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directives {
+    pub byte: String,
+    pub word: String,
+    pub text: String,
+    pub org: String,
+    pub equ: String,
+}
+
+impl Default for Directives {
+    fn default() -> Self {
+        Self {
+            byte: "fcb".into(),
+            word: "fdb".into(),
+            text: "fcc".into(),
+            org: "org".into(),
+            equ: "equ".into(),
+        }
+    }
+}
+
+/// A piece of the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chunk<A> {
+    Code(DissLine<A>),
+    /// A word holding a code address, from a jump table or vector
+    Word {
+        addr: usize,
+        bytes: Vec<u8>,
+        target: usize,
+    },
+    /// Bytes no code reached
+    Data {
+        addr: usize,
+        bytes: Vec<u8>,
+    },
+}
+
+impl<A> Chunk<A> {
+    pub fn addr(&self) -> usize {
+        match self {
+            Chunk::Code(l) => l.addr,
+            Chunk::Word { addr, .. } | Chunk::Data { addr, .. } => *addr,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Chunk::Code(l) => &l.bytes,
+            Chunk::Word { bytes, .. } | Chunk::Data { bytes, .. } => bytes,
+        }
+    }
+}
+
+/// Why an address needs a label, picks the generated name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Loc,
+    Sub,
+}
+
+impl LabelKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            LabelKind::Data => "dat_",
+            LabelKind::Loc => "loc_",
+            LabelKind::Sub => "sub_",
+        }
+    }
+}
+
+/// Code and data found by following the flow from the entry points
+#[derive(Debug, Clone)]
+pub struct Listing<A> {
+    pub org: usize,
+    /// In address order, covering the image with no gaps
+    pub chunks: Vec<Chunk<A>>,
+    pub labels: BTreeMap<usize, String>,
+    pub directives: Directives,
+}
+
+impl<A> Listing<A> {
+    /// The image the source assembles to
+    pub fn bytes(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .flat_map(|c| c.bytes().to_vec())
+            .collect()
+    }
+
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.chunks
+            .iter()
+            .any(|c| matches!(c, Chunk::Code(l) if (l.addr..l.next()).contains(&addr)))
+    }
+
+    /// Labels that don't start a line, these become equates
+    pub fn equates(&self) -> Vec<(usize, &str)> {
+        self.labels
+            .iter()
+            .filter(|(a, _)| !self.chunks.iter().any(|c| c.addr() == **a))
+            .map(|(a, n)| (*a, n.as_str()))
+            .collect()
+    }
+
+    fn name(&self, addr: usize) -> String {
+        self.labels
+            .get(&addr)
+            .cloned()
+            .unwrap_or_else(|| format!("${addr:04x}"))
+    }
+
+    fn data_lines(&self, bytes: &[u8]) -> Vec<String> {
+        let d = &self.directives;
+        let printable = |b: &u8| (0x20..0x7f).contains(b) && *b != b'"' && *b != b'\\';
+
+        let mut ret = vec![];
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let text = bytes[i..].iter().take_while(|b| printable(b)).count();
+
+            if text >= 4 {
+                let s: String = bytes[i..i + text].iter().map(|b| *b as char).collect();
+                ret.push(format!("{:<6}\"{s}\"", d.text));
+                i += text;
+                continue;
+            }
+
+            // Bytes up to the next run of text
+            let mut n = 0;
+            while i + n < bytes.len() && n < 8 {
+                let ahead = bytes[i + n..].iter().take_while(|b| printable(b)).count();
+                if ahead >= 4 {
+                    break;
+                }
+                n += 1;
+            }
+
+            let fcb: Vec<_> = bytes[i..i + n]
+                .iter()
+                .map(|b| format!("${b:02x}"))
+                .collect();
+            ret.push(format!("{:<6}{}", d.byte, fcb.join(",")));
+            i += n;
+        }
+
+        ret
+    }
+}
+
+const LABEL_WIDTH: usize = 16;
+
+fn line(f: &mut fmt::Formatter<'_>, label: Option<&String>, body: &str) -> fmt::Result {
+    match label {
+        Some(l) if l.len() >= LABEL_WIDTH => {
+            writeln!(f, "{l}")?;
+            writeln!(f, "{:LABEL_WIDTH$}{body}", "")
+        }
+        Some(l) => writeln!(f, "{l:LABEL_WIDTH$}{body}"),
+        None => writeln!(f, "{:LABEL_WIDTH$}{body}", ""),
+    }
+}
+
+/// Assembler source
+impl<A> fmt::Display for Listing<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = &self.directives;
+        let equates = self.equates();
+
+        for (addr, name) in &equates {
+            line(
+                f,
+                Some(&name.to_string()),
+                &format!("{:<6}${addr:04x}", d.equ),
+            )?;
+        }
+
+        if !equates.is_empty() {
+            writeln!(f)?;
+        }
+
+        line(f, None, &format!("{:<6}${:04x}", d.org, self.org))?;
+
+        for c in &self.chunks {
+            let label = self.labels.get(&c.addr());
+
+            match c {
+                Chunk::Code(l) => {
+                    let body = format!("{:<6}{}", l.mnemonic, l.operand);
+                    line(f, label, body.trim_end())?
+                }
+
+                Chunk::Word { target, .. } => {
+                    line(f, label, &format!("{:<6}{}", d.word, self.name(*target)))?
+                }
+
+                Chunk::Data { bytes, .. } => {
+                    for (i, body) in self.data_lines(bytes).iter().enumerate() {
+                        line(f, if i == 0 { label } else { None }, body)?
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Recursive traversal disassembly of an image
+/// Follows flow from entry points, vectors and jump tables, anything not
+/// reached is data
+pub struct FlowDiss<'a, A: IsaMode, F: OperandFormat<A>> {
+    diss: &'a Disassembler<A, F>,
+    mem: &'a dyn MemoryIO,
+    range: Range<usize>,
+    entries: Vec<usize>,
+    tables: Vec<(usize, usize)>,
+    names: BTreeMap<usize, String>,
+    directives: Directives,
+}
+
+impl<'a, A: IsaMode, F: OperandFormat<A>> FlowDiss<'a, A, F> {
+    pub fn new(diss: &'a Disassembler<A, F>, mem: &'a dyn MemoryIO, range: Range<usize>) -> Self {
+        Self {
+            diss,
+            mem,
+            range,
+            entries: vec![],
+            tables: vec![],
+            names: BTreeMap::new(),
+            directives: Directives::default(),
+        }
+    }
+
+    /// Code starts here
+    pub fn entry(mut self, addr: usize) -> Self {
+        self.entries.push(addr);
+        self
+    }
+
+    /// count words of code addresses
+    pub fn jump_table(mut self, addr: usize, count: usize) -> Self {
+        self.tables.push((addr, count));
+        self
+    }
+
+    /// A vector holding a code address, name is for where it points
+    pub fn vector(mut self, addr: usize, name: &str) -> Self {
+        if let Some(target) = self.word(addr) {
+            self.names.entry(target).or_insert_with(|| name.to_string());
+        }
+        self.jump_table(addr, 1)
+    }
+
+    /// The vectors that are in the image
+    pub fn vectors(self, vectors: &[(usize, &str)]) -> Self {
+        vectors.iter().fold(self, |s, (addr, name)| {
+            if s.range.contains(addr) {
+                s.vector(*addr, name)
+            } else {
+                s
+            }
+        })
+    }
+
+    pub fn with_directives(mut self, directives: Directives) -> Self {
+        self.directives = directives;
+        self
+    }
+
+    /// Name for an address, the first name for an address wins
+    pub fn add_symbol(&mut self, name: &str, addr: usize) {
+        self.names
+            .entry(addr)
+            .or_insert_with(|| name.replace("::", "_"));
+    }
+
+    pub fn with_symbol_tree<SCOPEID, SYMID>(
+        mut self,
+        syms: &SymbolTree<SCOPEID, SYMID, i64>,
+    ) -> Self
+    where
+        SCOPEID: ScopeIdTraits,
+        SYMID: SymIdTraits,
+    {
+        for si in syms.symbols() {
+            if let Some(v) = si.value.filter(|v| *v >= 0) {
+                self.add_symbol(si.scoped_name().trim_start_matches("::"), v as usize)
+            }
+        }
+        self
+    }
+
+    fn word(&self, addr: usize) -> Option<usize> {
+        let hi = self.mem.inspect_byte(addr).ok()?;
+        let lo = self.mem.inspect_byte(addr + 1).ok()?;
+
+        let w = if self.diss.format().big_endian() {
+            u16::from_be_bytes([hi, lo])
+        } else {
+            u16::from_le_bytes([hi, lo])
+        };
+
+        Some(w as usize)
+    }
+
+    fn bytes(&self, r: Range<usize>) -> MemResult<Vec<u8>> {
+        r.map(|a| self.mem.inspect_byte(a)).collect()
+    }
+
+    /// Fails if any of the image can't be read
+    pub fn trace(&self) -> MemResult<Listing<A>> {
+        let start = self.range.start;
+        let inside = |a: usize| self.range.contains(&a);
+
+        // Start of the chunk owning each byte
+        let mut owner: Vec<Option<usize>> = vec![None; self.range.len()];
+        let mut code = BTreeSet::new();
+        let mut words = BTreeMap::new();
+        let mut kinds: BTreeMap<usize, LabelKind> = BTreeMap::new();
+
+        let mut label = |addr: usize, kind: LabelKind| {
+            let k = kinds.entry(addr).or_insert(kind);
+            *k = (*k).max(kind);
+        };
+
+        let mut work = self.entries.clone();
+
+        for e in &self.entries {
+            label(*e, LabelKind::Loc)
+        }
+
+        for (addr, count) in &self.tables {
+            for a in (0..*count).map(|i| addr + i * 2) {
+                if !inside(a) || !inside(a + 1) || owner[a - start].is_some() {
+                    continue;
+                }
+
+                if let Some(t) = self.word(a) {
+                    owner[a - start] = Some(a);
+                    owner[a + 1 - start] = Some(a);
+                    words.insert(a, t);
+                    label(t, LabelKind::Loc);
+                    work.push(t);
+                }
+            }
+        }
+
+        while let Some(addr) = work.pop() {
+            if !inside(addr) || owner[addr - start].is_some() {
+                continue;
+            }
+
+            let Ok(line) = self.diss.diss(self.mem, addr) else {
+                continue;
+            };
+
+            let fits = (addr..line.next()).all(|a| inside(a) && owner[a - start].is_none());
+
+            if line.op.is_none() || !fits {
+                continue;
+            }
+
+            for a in addr..line.next() {
+                owner[a - start] = Some(addr)
+            }
+
+            let next = line.next();
+
+            match self.diss.flow(&line) {
+                OpFlow::Next => work.push(next),
+                OpFlow::Branch(t) => {
+                    label(t, LabelKind::Loc);
+                    work.extend([next, t])
+                }
+                OpFlow::Jump(t) => {
+                    label(t, LabelKind::Loc);
+                    work.push(t)
+                }
+                OpFlow::Call(t) => {
+                    label(t, LabelKind::Sub);
+                    work.extend([next, t])
+                }
+                OpFlow::Stop => (),
+            }
+
+            if let Some(d) = self.diss.data_ref(&line) {
+                label(d, LabelKind::Data)
+            }
+
+            code.insert(addr);
+        }
+
+        // Name everything that needs it, outside the image only flow targets
+        // and data with a known name get one
+        let mut labels = BTreeMap::new();
+
+        for (addr, kind) in kinds {
+            let named = self.names.get(&addr);
+
+            if inside(addr) || kind != LabelKind::Data || named.is_some() {
+                let name = named
+                    .cloned()
+                    .unwrap_or_else(|| format!("{}{addr:04x}", kind.prefix()));
+                labels.insert(addr, name);
+            }
+        }
+
+        for (addr, name) in self.names.range(self.range.clone()) {
+            labels.entry(*addr).or_insert_with(|| name.clone());
+        }
+
+        // Lay out the chunks, data is split at labels
+        let mut chunks = vec![];
+        let mut addr = start;
+
+        while addr < self.range.end {
+            if code.contains(&addr) {
+                let line = self.diss.diss_with(self.mem, addr, &labels)?;
+                addr = line.next();
+                chunks.push(Chunk::Code(line));
+            } else if let Some(target) = words.get(&addr) {
+                chunks.push(Chunk::Word {
+                    addr,
+                    bytes: self.bytes(addr..addr + 2)?,
+                    target: *target,
+                });
+                addr += 2;
+            } else {
+                let end = (addr + 1..self.range.end)
+                    .find(|a| owner[a - start].is_some() || labels.contains_key(a))
+                    .unwrap_or(self.range.end);
+
+                chunks.push(Chunk::Data {
+                    addr,
+                    bytes: self.bytes(addr..end)?,
+                });
+                addr = end;
+            }
+        }
+
+        Ok(Listing {
+            org: start,
+            chunks,
+            labels,
+            directives: self.directives.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diss::Operand;
+    use crate::isa::{IsaDbase, IsaOp};
+    use crate::mem::MemBlock;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+    enum Mode {
+        #[default]
+        Inherent,
+        Extended,
+        Indexed,
+        Relative,
+    }
+
+    struct Syntax;
+
+    impl OperandFormat<Mode> for Syntax {
+        fn operand(&self, op: &IsaOp<Mode>, o: &Operand) -> String {
+            match op.addr_mode {
+                Mode::Inherent => String::new(),
+                Mode::Extended => o.name(o.word_be(0) as usize, 4, "$"),
+                Mode::Indexed => format!("{},x", o.byte(0)),
+                Mode::Relative => o.name(o.rel8(0), 4, "$"),
+            }
+        }
+
+        fn flow(&self, op: &IsaOp<Mode>, o: &Operand) -> OpFlow {
+            let abs = o.word_be(0) as usize;
+            match (op.mnemonic.as_str(), op.addr_mode) {
+                ("Jsr", _) => OpFlow::Call(abs),
+                ("Jmp", Mode::Extended) => OpFlow::Jump(abs),
+                ("Jmp", _) | ("Rts", _) => OpFlow::Stop,
+                ("Bra", _) => OpFlow::Jump(o.rel8(0)),
+                (_, Mode::Relative) => OpFlow::Branch(o.rel8(0)),
+                _ => OpFlow::Next,
+            }
+        }
+
+        fn data_ref(&self, op: &IsaOp<Mode>, o: &Operand) -> Option<usize> {
+            (op.mnemonic == "Lda" && op.addr_mode == Mode::Extended).then(|| o.word_be(0) as usize)
+        }
+    }
+
+    const ISA: &str = r#"{ "instructions": {
+        "Jsr": { "addr_modes": { "Extended": { "opcode": "bd", "cycles": 9, "size": 3 } } },
+        "Jmp": { "addr_modes": {
+            "Extended": { "opcode": "7e", "cycles": 3, "size": 3 },
+            "Indexed": { "opcode": "6e", "cycles": 4, "size": 2 }
+        }},
+        "Lda": { "addr_modes": { "Extended": { "opcode": "b6", "cycles": 4, "size": 3 } } },
+        "Rts": { "addr_modes": { "Inherent": { "opcode": "39", "cycles": 5, "size": 1 } } },
+        "Bra": { "addr_modes": { "Relative": { "opcode": "20", "cycles": 3, "size": 2 } } },
+        "Beq": { "addr_modes": { "Relative": { "opcode": "27", "cycles": 3, "size": 2 } } }
+    }}"#;
+
+    #[test]
+    fn reassemblable() {
+        let image = [
+            0xbd, 0x10, 0x0a, // jsr sub
+            0x27, 0x01, // beq
+            0x39, // rts
+            0x7e, 0x10, 0x00, // jmp start
+            0x00, // never reached
+            0xb6, 0x10, 0x10, // sub: lda msg
+            0x39, // rts
+            0x01, 0x02, // data
+            b'H', b'E', b'L', b'L', b'O', 0x00, // msg
+            0x10, 0x00, // reset vector
+        ];
+
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0x1000, "rom", &image, true);
+        let diss = Disassembler::new(IsaDbase::<Mode>::parse(ISA).unwrap(), Syntax);
+
+        let mut flow = FlowDiss::new(&diss, &mem, 0x1000..0x1018).vectors(&[(0x1016, "reset")]);
+        flow.add_symbol("msg", 0x1010);
+        flow.add_symbol("io::port", 0xc000);
+
+        let listing = flow.trace().unwrap();
+
+        assert_eq!(listing.bytes(), image);
+        assert!(listing.is_code(0x100b));
+        assert!(!listing.is_code(0x1009));
+
+        assert_eq!(
+            listing.to_string(),
+            "                org   $1000
+reset           jsr   sub_100a
+                beq   loc_1006
+                rts
+loc_1006        jmp   reset
+                fcb   $00
+sub_100a        lda   msg
+                rts
+                fcb   $01,$02
+msg             fcc   \"HELLO\"
+                fcb   $00
+                fdb   reset
+"
+        );
+    }
+
+    #[test]
+    fn equates() {
+        // Branch into the middle of an instruction and a call out of the image
+        let image = [0xbd, 0xf0, 0x00, 0x27, 0xfc, 0x39];
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0x2000, "rom", &image, true);
+        let diss = Disassembler::new(IsaDbase::<Mode>::parse(ISA).unwrap(), Syntax);
+
+        let listing = FlowDiss::new(&diss, &mem, 0x2000..0x2006)
+            .entry(0x2000)
+            .trace()
+            .unwrap();
+
+        assert_eq!(listing.bytes(), image);
+        assert_eq!(
+            listing.equates(),
+            [(0x2001, "loc_2001"), (0xf000, "sub_f000")]
+        );
+        assert!(listing
+            .to_string()
+            .starts_with("loc_2001        equ   $2001\nsub_f000        equ   $f000\n\n"));
+    }
+
+    #[test]
+    fn unreadable() {
+        // rts, with the range running past the end of memory
+        let mem = MemBlock::<byteorder::BigEndian>::from_data(0x2000, "rom", &[0x39], true);
+        let diss = Disassembler::new(IsaDbase::<Mode>::parse(ISA).unwrap(), Syntax);
+
+        let flow = FlowDiss::new(&diss, &mem, 0x2000..0x2004).entry(0x2000);
+        assert!(flow.trace().is_err());
+    }
+}
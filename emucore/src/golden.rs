@@ -0,0 +1,322 @@
+use crate::mem::MemoryIO;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Env var that turns on bless mode for every golden test
+pub const BLESS_ENV: &str = "GOLDEN_BLESS";
+
+/// Maximum number of differing memory runs shown in a report
+const MAX_DIFF_RUNS: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum GoldenErr {
+    #[error("{0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Yaml(PathBuf, serde_yaml::Error),
+    #[error("Step failed at cycle {cycle}: {msg}")]
+    Step { cycle: usize, msg: String },
+    #[error("No expected state in {0}, run with {BLESS_ENV}=1 to create it")]
+    NotBlessed(PathBuf),
+    #[error("Golden state mismatch for {0}\n{1}")]
+    Mismatch(PathBuf, String),
+}
+
+pub type GoldenResult<T> = Result<T, GoldenErr>;
+
+/// How long to run for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunLength {
+    Cycles(usize),
+    Instructions(usize),
+}
+
+/// Machine state to compare against
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoldenState {
+    pub mem_sha1: String,
+    pub regs: BTreeMap<String, u64>,
+}
+
+/// A golden test as stored on disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoldenSpec {
+    /// Image to load, relative to the spec file
+    pub image: PathBuf,
+    pub load_addr: usize,
+    pub run: RunLength,
+    pub expected: Option<GoldenState>,
+}
+
+/// A machine the harness can run
+pub trait GoldenTarget {
+    type Err: std::fmt::Display;
+
+    /// Cycles since the machine was created, must never go backwards
+    fn cycles(&self) -> usize;
+    fn step(&mut self) -> Result<(), Self::Err>;
+    /// Opcodes executed, interrupt entries and reset aren't instructions
+    fn instructions(&self) -> usize;
+    fn regs(&self) -> BTreeMap<String, u64>;
+    fn mem(&self) -> &dyn MemoryIO;
+    fn mem_mut(&mut self) -> &mut dyn MemoryIO;
+}
+
+/// Runs a machine and compares the result with a golden state
+/// A memory dump is kept next to the spec, with a .bin extension,
+/// so mismatches can show which bytes changed
+#[derive(Debug, Clone)]
+pub struct Golden {
+    pub spec: GoldenSpec,
+    spec_file: PathBuf,
+    bless: bool,
+}
+
+fn read_file(path: &Path) -> GoldenResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| GoldenErr::Io(path.to_path_buf(), e))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> GoldenResult<()> {
+    std::fs::write(path, data).map_err(|e| GoldenErr::Io(path.to_path_buf(), e))
+}
+
+/// Every byte in range, unmapped addresses read as 0
+fn dump_mem(mem: &dyn MemoryIO) -> Vec<u8> {
+    mem.get_range()
+        .map(|a| mem.inspect_byte(a).unwrap_or(0))
+        .collect()
+}
+
+impl Golden {
+    pub fn load<P: AsRef<Path>>(spec_file: P) -> GoldenResult<Self> {
+        let spec_file = spec_file.as_ref().to_path_buf();
+        let txt = read_file(&spec_file)?;
+        let spec =
+            serde_yaml::from_slice(&txt).map_err(|e| GoldenErr::Yaml(spec_file.clone(), e))?;
+
+        Ok(Self {
+            spec,
+            spec_file,
+            bless: std::env::var(BLESS_ENV).is_ok_and(|v| v != "0"),
+        })
+    }
+
+    /// Force bless mode on or off
+    pub fn bless(self, bless: bool) -> Self {
+        Self { bless, ..self }
+    }
+
+    fn image_file(&self) -> PathBuf {
+        let dir = self.spec_file.parent().unwrap_or(Path::new("."));
+        dir.join(&self.spec.image)
+    }
+
+    fn dump_file(&self) -> PathBuf {
+        self.spec_file.with_extension("bin")
+    }
+
+    /// Upload the image into the target
+    pub fn prepare<T: GoldenTarget>(&self, target: &mut T) -> GoldenResult<()> {
+        let image = self.image_file();
+        let data = read_file(&image)?;
+
+        target
+            .mem_mut()
+            .upload(self.spec.load_addr, &data)
+            .map_err(|e| GoldenErr::Step {
+                cycle: 0,
+                msg: format!("Can't load {}: {e}", image.display()),
+            })
+    }
+
+    /// Step the target for the run length in the spec
+    pub fn run<T: GoldenTarget>(&self, target: &mut T) -> GoldenResult<()> {
+        let start_cycles = target.cycles();
+        let start_instructions = target.instructions();
+
+        let done = |t: &T| match self.spec.run {
+            RunLength::Cycles(n) => t.cycles() - start_cycles >= n,
+            RunLength::Instructions(n) => t.instructions() - start_instructions >= n,
+        };
+
+        while !done(target) {
+            target.step().map_err(|e| GoldenErr::Step {
+                cycle: target.cycles(),
+                msg: e.to_string(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Compare the target with the expected state
+    /// In bless mode the spec and memory dump are rewritten instead
+    pub fn check<T: GoldenTarget>(&mut self, target: &T) -> GoldenResult<()> {
+        let actual = GoldenState {
+            mem_sha1: target.mem().get_sha1_string(),
+            regs: target.regs(),
+        };
+
+        if self.bless {
+            return self.write(&actual, &dump_mem(target.mem()));
+        }
+
+        let expected = self
+            .spec
+            .expected
+            .as_ref()
+            .ok_or_else(|| GoldenErr::NotBlessed(self.spec_file.clone()))?;
+
+        if *expected == actual {
+            return Ok(());
+        }
+
+        let old_dump = read_file(&self.dump_file()).ok();
+        let report = diff_report(expected, &actual, old_dump.as_deref(), target.mem());
+
+        Err(GoldenErr::Mismatch(self.spec_file.clone(), report))
+    }
+
+    /// Run and check in one go
+    pub fn run_and_check<T: GoldenTarget>(&mut self, target: &mut T) -> GoldenResult<()> {
+        self.run(target)?;
+        self.check(target)
+    }
+
+    fn write(&mut self, state: &GoldenState, dump: &[u8]) -> GoldenResult<()> {
+        self.spec.expected = Some(state.clone());
+
+        let txt = serde_yaml::to_string(&self.spec)
+            .map_err(|e| GoldenErr::Yaml(self.spec_file.clone(), e))?;
+
+        write_file(&self.spec_file, txt.as_bytes())?;
+        write_file(&self.dump_file(), dump)
+    }
+}
+
+/// Readable diff of registers and memory
+pub fn diff_report(
+    expected: &GoldenState,
+    actual: &GoldenState,
+    old_dump: Option<&[u8]>,
+    mem: &dyn MemoryIO,
+) -> String {
+    let mut out = String::new();
+
+    for (name, want) in &expected.regs {
+        match actual.regs.get(name) {
+            Some(got) if got == want => (),
+            Some(got) => writeln!(out, "  {name:>4}: expected ${want:04x} got ${got:04x}").unwrap(),
+            None => writeln!(out, "  {name:>4}: missing").unwrap(),
+        }
+    }
+
+    if expected.mem_sha1 == actual.mem_sha1 {
+        return out;
+    }
+
+    writeln!(
+        out,
+        "  memory sha1: expected {} got {}",
+        expected.mem_sha1, actual.mem_sha1
+    )
+    .unwrap();
+
+    let Some(old) = old_dump else {
+        writeln!(out, "  no memory dump to compare against").unwrap();
+        return out;
+    };
+
+    let range = mem.get_range();
+    let new = dump_mem(mem);
+    let differs = |i: usize| old.get(i) != new.get(i);
+
+    let mut i = 0;
+    let mut runs = 0;
+
+    while i < new.len().max(old.len()) {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < new.len().max(old.len()) && differs(i) {
+            i += 1;
+        }
+
+        runs += 1;
+        if runs > MAX_DIFF_RUNS {
+            writeln!(out, "  ... more differences").unwrap();
+            break;
+        }
+
+        for line in (start..i).step_by(16) {
+            let end = (line + 16).min(i);
+            let hex = |d: &[u8]| {
+                (line..end)
+                    .map(|a| d.get(a).map(|b| format!("{b:02x}")).unwrap_or("--".into()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            writeln!(out, "  ${:04x}: -{}", range.start + line, hex(old)).unwrap();
+            writeln!(out, "         +{}", hex(&new)).unwrap();
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmachine::Toy;
+
+    #[test]
+    fn bless_then_check() {
+        let dir = std::env::temp_dir().join(format!("golden-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // inc $10, jmp 0
+        std::fs::write(dir.join("image.bin"), [0x08, 0x10, 0x09, 0x00]).unwrap();
+
+        let spec = GoldenSpec {
+            image: "image.bin".into(),
+            load_addr: 0,
+            run: RunLength::Instructions(40),
+            expected: None,
+        };
+        let spec_file = dir.join("count.yaml");
+        std::fs::write(&spec_file, serde_yaml::to_string(&spec).unwrap()).unwrap();
+
+        let mut g = Golden::load(&spec_file).unwrap().bless(false);
+        let mut m = Toy::new(&[]);
+        g.prepare(&mut m).unwrap();
+        g.run(&mut m).unwrap();
+        assert!(matches!(g.check(&m), Err(GoldenErr::NotBlessed(_))));
+
+        let mut g = g.bless(true);
+        g.check(&m).unwrap();
+
+        let mut g = Golden::load(&spec_file).unwrap().bless(false);
+        let mut m = Toy::new(&[]);
+        g.prepare(&mut m).unwrap();
+        g.run_and_check(&mut m).unwrap();
+
+        // One more inc changes pc and a byte
+        m.step().unwrap();
+
+        match g.check(&m) {
+            Err(GoldenErr::Mismatch(_, report)) => {
+                assert!(report.contains("pc: expected $0000 got $0002"));
+                assert!(report.contains("$0010: -14\n         +15"));
+            }
+            r => panic!("expected mismatch, got {r:?}"),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
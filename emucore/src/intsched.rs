@@ -0,0 +1,277 @@
+use crate::replay::{Pin, ReplayTarget};
+use std::fmt;
+
+/// A machine whose interrupt lines can be scheduled
+pub trait InterruptTarget: ReplayTarget {
+    /// Would the cpu ignore this line right now
+    fn is_masked(&self, pin: Pin) -> bool;
+    /// The hardware interrupt the last step entered, if any
+    fn taken_interrupt(&self) -> Option<Pin>;
+}
+
+/// One assertion of a line and what became of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptRecord {
+    pub pin: Pin,
+    /// Cycle the line was asserted
+    pub asserted: usize,
+    /// Cycle the handler's first instruction started, None if never taken
+    pub taken: Option<usize>,
+    /// Cycles spent asserted while the cpu had the line masked
+    pub masked: usize,
+}
+
+impl InterruptRecord {
+    /// Cycles from assertion to the first handler instruction
+    pub fn latency(&self) -> Option<usize> {
+        self.taken.map(|t| t - self.asserted)
+    }
+}
+
+/// Summary of the records for one line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyStats {
+    pub taken: usize,
+    /// Assertions dropped before the cpu took them
+    pub missed: usize,
+    pub min: usize,
+    pub max: usize,
+    pub total: usize,
+    pub max_masked: usize,
+}
+
+impl LatencyStats {
+    pub fn mean(&self) -> Option<f64> {
+        (self.taken != 0).then(|| self.total as f64 / self.taken as f64)
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "taken {} missed {}", self.taken, self.missed)?;
+        if let Some(mean) = self.mean() {
+            write!(
+                f,
+                ", latency min {} max {} mean {mean:.1}",
+                self.min, self.max
+            )?;
+        }
+        write!(f, ", max masked {}", self.max_masked)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    pin: Pin,
+    next_assert: Option<usize>,
+    deassert: Option<usize>,
+    width: Option<usize>,
+    period: Option<usize>,
+    active: bool,
+}
+
+/// Drives interrupt lines from a timeline of assertions
+/// and records the latency the cpu showed for each
+///
+/// Call before_step and after_step around every step
+#[derive(Debug, Clone, Default)]
+pub struct InterruptScheduler {
+    sources: Vec<Source>,
+    records: Vec<InterruptRecord>,
+    /// Indices of records not yet taken or dropped, with mask state for this step
+    open: Vec<(usize, bool)>,
+    step_start: usize,
+}
+
+impl InterruptScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, pin: Pin, at: usize, width: Option<usize>, period: Option<usize>) {
+        self.sources.push(Source {
+            pin,
+            next_assert: Some(at),
+            deassert: None,
+            width,
+            period,
+            active: false,
+        })
+    }
+
+    /// Assert pin at cycle, dropping it after width cycles
+    /// With no width the line stays asserted until deassert_at
+    pub fn assert_at(&mut self, pin: Pin, at: usize, width: Option<usize>) {
+        self.add(pin, at, width, None)
+    }
+
+    /// Drop pin at cycle, ending assertions made with no width
+    pub fn deassert_at(&mut self, pin: Pin, at: usize) {
+        for s in self
+            .sources
+            .iter_mut()
+            .filter(|s| s.pin == pin && s.width.is_none())
+        {
+            match s.next_assert {
+                Some(a) if a <= at => s.width = Some(at - a),
+                _ => s.deassert = Some(at),
+            }
+        }
+    }
+
+    /// Pulse pin for width cycles every period cycles, starting at start
+    pub fn pulse_every(&mut self, pin: Pin, start: usize, period: usize, width: usize) {
+        assert!(period > width, "pulse must be shorter than its period");
+        self.add(pin, start, Some(width), Some(period))
+    }
+
+    pub fn records(&self) -> &[InterruptRecord] {
+        &self.records
+    }
+
+    pub fn stats(&self, pin: Pin) -> LatencyStats {
+        let mut stats = LatencyStats::default();
+
+        for (i, r) in self
+            .records
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.pin == pin)
+        {
+            stats.max_masked = stats.max_masked.max(r.masked);
+
+            match r.latency() {
+                Some(l) => {
+                    stats.min = if stats.taken == 0 {
+                        l
+                    } else {
+                        stats.min.min(l)
+                    };
+                    stats.max = stats.max.max(l);
+                    stats.total += l;
+                    stats.taken += 1;
+                }
+                None if !self.open.iter().any(|(o, _)| *o == i) => stats.missed += 1,
+                None => (),
+            }
+        }
+
+        stats
+    }
+
+    fn is_asserted(&self, pin: Pin) -> bool {
+        self.sources.iter().any(|s| s.pin == pin && s.active)
+    }
+
+    fn set_level<T: InterruptTarget>(&mut self, target: &mut T, pin: Pin) {
+        let level = self.is_asserted(pin);
+        target.set_pin(pin, level);
+
+        if !level {
+            // Line dropped before the cpu got to it
+            self.open.retain(|(i, _)| self.records[*i].pin != pin);
+        }
+    }
+
+    /// Apply any line changes that are due
+    pub fn before_step<T: InterruptTarget>(&mut self, target: &mut T) {
+        let now = target.cycles();
+
+        for i in 0..self.sources.len() {
+            let s = &mut self.sources[i];
+            let pin = s.pin;
+
+            if s.deassert.is_some_and(|d| d <= now) {
+                s.deassert = None;
+                s.active = false;
+                self.set_level(target, pin);
+            }
+
+            let s = &mut self.sources[i];
+
+            if let Some(at) = s.next_assert.filter(|a| *a <= now) {
+                s.active = true;
+                s.deassert = s.width.map(|w| at + w);
+                s.next_assert = s.period.map(|p| at + p);
+
+                self.open.push((self.records.len(), false));
+                self.records.push(InterruptRecord {
+                    pin,
+                    asserted: at,
+                    taken: None,
+                    masked: 0,
+                });
+                self.set_level(target, pin);
+            }
+        }
+
+        for (i, masked) in &mut self.open {
+            *masked = target.is_masked(self.records[*i].pin);
+        }
+
+        self.step_start = now;
+    }
+
+    /// Account for the step just run
+    pub fn after_step<T: InterruptTarget>(&mut self, target: &T) {
+        let now = target.cycles();
+        let taken = target.taken_interrupt();
+        let records = &mut self.records;
+
+        self.open.retain(|(i, masked)| {
+            let r = &mut records[*i];
+
+            if *masked {
+                r.masked += now - self.step_start;
+            }
+
+            if taken == Some(r.pin) {
+                r.taken = Some(now);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::GoldenTarget;
+    use crate::testmachine::Toy;
+
+    #[test]
+    fn latency_and_masking() {
+        // IRQ masked from $08 to $10 and $18 to $1c, handler is just rti
+        let mut code = [0u8; 0x20];
+        code[0x08] = 0x0a;
+        code[0x10] = 0x0b;
+        code[0x18] = 0x0a;
+        code[0x1c] = 0x0b;
+        let mut toy = Toy::new(&[(0, &code), (0x80, &[0x03])]);
+
+        let mut sched = InterruptScheduler::new();
+        sched.assert_at(Pin::Irq, 2, Some(20));
+        sched.assert_at(Pin::Irq, 30, Some(30));
+        sched.assert_at(Pin::Irq, 74, Some(1));
+
+        while toy.cycles() < 96 {
+            sched.before_step(&mut toy);
+            toy.step().unwrap();
+            sched.after_step(&toy);
+        }
+
+        let r = sched.records();
+        assert_eq!(r[0].latency(), Some(8));
+        assert_eq!(r[1].masked, 16);
+        assert_eq!(r[1].latency(), Some(24));
+        assert_eq!(r[2].latency(), None);
+        assert_eq!(r[2].masked, 2);
+
+        let stats = sched.stats(Pin::Irq);
+        assert_eq!((stats.taken, stats.missed), (2, 1));
+        assert_eq!(stats.mean(), Some(16.0));
+        assert_eq!(stats.max_masked, 16);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::replay::{Pin, ReplayTarget};
+use std::fmt;
+
+/// How the cpu senses a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Pending for as long as any source asserts it
+    Level,
+    /// Pending once per low to high transition
+    Edge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId {
+    pub pin: Pin,
+    index: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+    name: String,
+    asserted: bool,
+}
+
+/// An open collector interrupt line shared by several devices
+/// The level is the OR of every source driving it
+#[derive(Debug, Clone)]
+pub struct IrqLine {
+    pin: Pin,
+    trigger: Trigger,
+    sources: Vec<Source>,
+    edge: bool,
+}
+
+impl IrqLine {
+    pub fn new(pin: Pin, trigger: Trigger) -> Self {
+        Self {
+            pin,
+            trigger,
+            sources: vec![],
+            edge: false,
+        }
+    }
+
+    pub fn pin(&self) -> Pin {
+        self.pin
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        self.trigger
+    }
+
+    pub fn add_source(&mut self, name: &str) -> SourceId {
+        self.sources.push(Source {
+            name: name.to_string(),
+            asserted: false,
+        });
+        SourceId {
+            pin: self.pin,
+            index: self.sources.len() - 1,
+        }
+    }
+
+    pub fn set(&mut self, id: SourceId, level: bool) {
+        let was = self.level();
+        self.sources[id.index].asserted = level;
+
+        if !was && self.level() {
+            self.edge = true
+        }
+    }
+
+    /// OR of all sources
+    pub fn level(&self) -> bool {
+        self.sources.iter().any(|s| s.asserted)
+    }
+
+    /// Does the cpu see an interrupt on this line
+    pub fn is_pending(&self) -> bool {
+        match self.trigger {
+            Trigger::Level => self.level(),
+            Trigger::Edge => self.edge,
+        }
+    }
+
+    /// Clear a latched edge once the cpu has it
+    pub fn acknowledge(&mut self) {
+        self.edge = false
+    }
+
+    /// Names of the sources asserting the line
+    pub fn asserting(&self) -> impl Iterator<Item = &str> {
+        self.sources
+            .iter()
+            .filter(|s| s.asserted)
+            .map(|s| s.name.as_str())
+    }
+}
+
+impl fmt::Display for IrqLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let asserting: Vec<_> = self.asserting().collect();
+        write!(f, "{:?}: ", self.pin)?;
+
+        if asserting.is_empty() {
+            write!(f, "-")?;
+        } else {
+            write!(f, "{}", asserting.join(", "))?;
+        }
+
+        if self.trigger == Trigger::Edge && self.edge {
+            write!(f, " (edge pending)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The interrupt lines of a cpu, IRQ and FIRQ level triggered,
+/// NMI edge triggered as on the 6800 and 6809
+#[derive(Debug, Clone)]
+pub struct InterruptLines {
+    lines: [IrqLine; 3],
+}
+
+impl Default for InterruptLines {
+    fn default() -> Self {
+        Self {
+            lines: [
+                IrqLine::new(Pin::Irq, Trigger::Level),
+                IrqLine::new(Pin::Firq, Trigger::Level),
+                IrqLine::new(Pin::Nmi, Trigger::Edge),
+            ],
+        }
+    }
+}
+
+impl InterruptLines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// None for Pin::Reset, which isn't an interrupt line
+    pub fn line(&self, pin: Pin) -> Option<&IrqLine> {
+        self.lines.iter().find(|l| l.pin == pin)
+    }
+
+    pub fn line_mut(&mut self, pin: Pin) -> Option<&mut IrqLine> {
+        self.lines.iter_mut().find(|l| l.pin == pin)
+    }
+
+    /// None if pin isn't an interrupt line
+    pub fn add_source(&mut self, pin: Pin, name: &str) -> Option<SourceId> {
+        self.line_mut(pin).map(|l| l.add_source(name))
+    }
+
+    pub fn set(&mut self, id: SourceId, level: bool) {
+        if let Some(l) = self.line_mut(id.pin) {
+            l.set(id, level)
+        }
+    }
+
+    /// Is any source driving pin
+    pub fn level(&self, pin: Pin) -> bool {
+        self.line(pin).is_some_and(IrqLine::level)
+    }
+
+    /// Names of the sources asserting pin
+    pub fn asserting(&self, pin: Pin) -> Vec<&str> {
+        self.line(pin)
+            .map(|l| l.asserting().collect())
+            .unwrap_or_default()
+    }
+
+    /// Take a pending edge on pin, true if there was one
+    pub fn take_edge(&mut self, pin: Pin) -> bool {
+        match self.line_mut(pin) {
+            Some(l) if l.trigger == Trigger::Edge && l.edge => {
+                l.acknowledge();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Pass the lines to the cpu, call before every step
+    /// Edges are handed over once, the cpu latches them itself
+    pub fn drive<T: ReplayTarget + ?Sized>(&mut self, target: &mut T) {
+        for line in &mut self.lines {
+            match line.trigger {
+                Trigger::Level => target.set_pin(line.pin, line.level()),
+                Trigger::Edge if line.edge => {
+                    target.set_pin(line.pin, true);
+                    line.acknowledge()
+                }
+                Trigger::Edge => (),
+            }
+        }
+    }
+}
+
+impl fmt::Display for InterruptLines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for l in &self.lines {
+            writeln!(f, "{l}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wired_or() {
+        let mut lines = InterruptLines::new();
+        let via = lines.add_source(Pin::Irq, "via").unwrap();
+        let acia = lines.add_source(Pin::Irq, "acia").unwrap();
+        assert!(lines.add_source(Pin::Reset, "reset").is_none());
+
+        lines.set(via, true);
+        lines.set(acia, true);
+        lines.set(via, false);
+        assert!(lines.level(Pin::Irq));
+        assert_eq!(lines.asserting(Pin::Irq), vec!["acia"]);
+
+        lines.set(acia, false);
+        assert!(!lines.level(Pin::Irq));
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let mut lines = InterruptLines::new();
+        let a = lines.add_source(Pin::Nmi, "a").unwrap();
+        let b = lines.add_source(Pin::Nmi, "b").unwrap();
+
+        lines.set(a, true);
+        assert!(lines.line(Pin::Nmi).unwrap().is_pending());
+        assert!(lines.take_edge(Pin::Nmi));
+
+        // Already low to high, a second source makes no new edge
+        lines.set(b, true);
+        assert!(!lines.line(Pin::Nmi).unwrap().is_pending());
+
+        lines.set(a, false);
+        lines.set(b, false);
+        lines.set(b, true);
+        assert!(lines.line(Pin::Nmi).unwrap().is_pending());
+        assert_eq!(
+            lines.to_string(),
+            "Irq: -\nFirq: -\nNmi: b (edge pending)\n"
+        );
+    }
+}
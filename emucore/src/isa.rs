@@ -0,0 +1,634 @@
+use crate::flagmods::{FlagModErr, FlagMods};
+use crate::mem::MemoryIO;
+use crate::traits::{InstructionDbaseTrait, InstructionInfoTrait, SingleInstructionTrait};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// A problem found in an ISA description
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IsaIssue {
+    #[error("Opcode ${opcode:02x} is both {first} and {second}")]
+    DuplicateOpcode {
+        opcode: usize,
+        first: String,
+        second: String,
+    },
+    #[error("{mnemonic} has {mode} more than once")]
+    DuplicateMode { mnemonic: String, mode: String },
+    #[error("{mnemonic} {mode} (${opcode:02x}) is {size} bytes, the mode needs {expected}")]
+    SizeMismatch {
+        mnemonic: String,
+        mode: String,
+        opcode: usize,
+        size: usize,
+        expected: usize,
+    },
+    #[error("{mnemonic} uses {mode} which isn't in the addressing mode table")]
+    MissingMode { mnemonic: String, mode: String },
+    #[error("{mnemonic}: {mode} isn't an addressing mode")]
+    UnknownMode { mnemonic: String, mode: String },
+    #[error("{mnemonic} {mode}: can't read opcode {text}")]
+    BadOpcode {
+        mnemonic: String,
+        mode: String,
+        text: String,
+    },
+    #[error("{mnemonic}: flags {flags} don't fit flag order {order}")]
+    BadFlags {
+        mnemonic: String,
+        flags: String,
+        order: String,
+    },
+}
+
+fn issues_text(issues: &[IsaIssue]) -> String {
+    issues
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Error, Debug)]
+pub enum IsaErr {
+    #[error("{0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("ISA file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("ISA file isn't UTF-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("ISA has problems:\n{}", issues_text(.0))]
+    Invalid(Vec<IsaIssue>),
+}
+
+pub type IsaResult<T> = Result<T, IsaErr>;
+
+/// An opcode as a number or a hex string
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OpcodeText {
+    Num(usize),
+    Hex(String),
+}
+
+impl OpcodeText {
+    pub fn value(&self) -> Option<usize> {
+        match self {
+            OpcodeText::Num(n) => Some(*n),
+            OpcodeText::Hex(s) => {
+                let s = s.trim_start_matches("0x").trim_start_matches('$');
+                usize::from_str_radix(s, 16).ok()
+            }
+        }
+    }
+}
+
+impl fmt::Display for OpcodeText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpcodeText::Num(n) => write!(f, "{n}"),
+            OpcodeText::Hex(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+/// One addressing mode of an instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpDef {
+    pub opcode: OpcodeText,
+    pub cycles: usize,
+    /// Whole instruction including opcode bytes
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModeDef {
+    /// Size with a one byte opcode, not checked if not given
+    #[serde(default)]
+    pub size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InsDef {
+    /// One of -01+ per flag in flag_order, empty if not known
+    #[serde(default)]
+    pub flags: String,
+    /// Other names for the same instruction, eg asl for lsl
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub addr_modes: BTreeMap<String, OpDef>,
+}
+
+/// The ISA description file all the cores share
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IsaFile {
+    /// Flag names, most significant first
+    #[serde(default)]
+    pub flag_order: String,
+    /// Every addressing mode the instructions may use
+    #[serde(default)]
+    pub addr_modes: BTreeMap<String, ModeDef>,
+    /// What an opcode not in the table decodes to
+    #[serde(default)]
+    pub unknown: Option<OpDef>,
+    pub instructions: BTreeMap<String, InsDef>,
+}
+
+impl IsaFile {
+    pub fn from_json(text: &str) -> IsaResult<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// What an addressing mode enum needs to be read from an ISA file
+pub trait IsaMode: Copy + Eq + Hash + fmt::Debug + Default + DeserializeOwned {}
+
+impl<T: Copy + Eq + Hash + fmt::Debug + Default + DeserializeOwned> IsaMode for T {}
+
+fn parse_mode<A: DeserializeOwned>(name: &str) -> Option<A> {
+    let de: serde::de::value::StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+    A::deserialize(de).ok()
+}
+
+/// One opcode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsaOp<A> {
+    pub mnemonic: String,
+    pub addr_mode: A,
+    pub opcode: usize,
+    pub cycles: usize,
+    pub size: usize,
+    /// One of -01+ per flag in the ISA's flag order, empty if not known
+    pub flags: String,
+}
+
+impl<A: fmt::Debug> IsaOp<A> {
+    pub fn new(mnemonic: &str, addr_mode: A, opcode: usize, cycles: usize, size: usize) -> Self {
+        Self {
+            mnemonic: mnemonic.to_string(),
+            addr_mode,
+            opcode,
+            cycles,
+            size,
+            flags: String::new(),
+        }
+    }
+
+    pub fn with_flags(self, flags: &str) -> Self {
+        Self {
+            flags: flags.to_string(),
+            ..self
+        }
+    }
+
+    /// Bytes taken by the opcode, more than one for prefixed opcodes
+    pub fn opcode_bytes(&self) -> usize {
+        let bits = usize::BITS - self.opcode.leading_zeros();
+        (bits as usize).div_ceil(8).max(1)
+    }
+
+    fn name(&self) -> String {
+        format!("{} {:?}", self.mnemonic, self.addr_mode)
+    }
+}
+
+impl<A: IsaMode> SingleInstructionTrait<A> for IsaOp<A> {
+    fn get_addressing_mode(&self) -> A {
+        self.addr_mode
+    }
+
+    fn get_cycles(&self) -> usize {
+        self.cycles
+    }
+
+    fn get_bytes(&self) -> usize {
+        self.size
+    }
+}
+
+/// An instruction and all of its addressing modes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsaInfo<A: IsaMode> {
+    pub mnemonic: String,
+    pub modes: HashMap<A, IsaOp<A>>,
+}
+
+impl<A: IsaMode> InstructionInfoTrait<A, IsaOp<A>> for IsaInfo<A> {
+    fn supports_addr_mode(&self, m: A) -> bool {
+        self.modes.contains_key(&m)
+    }
+
+    fn get_instruction(&self, amode: A) -> Option<&IsaOp<A>> {
+        self.modes.get(&amode)
+    }
+}
+
+/// Instruction set lookups built from an ISA file or a core's own tables
+/// Bad entries are kept out of the lookups and reported by validate
+#[derive(Debug, Clone)]
+pub struct IsaDbase<A: IsaMode> {
+    ops: Vec<IsaOp<A>>,
+    unknown: IsaOp<A>,
+    flag_order: String,
+    /// Declared modes and their sizes
+    modes: HashMap<A, Option<usize>>,
+    aliases: HashMap<String, String>,
+    by_name: HashMap<String, IsaInfo<A>>,
+    by_opcode: HashMap<usize, usize>,
+    /// Found while reading the file
+    issues: Vec<IsaIssue>,
+}
+
+impl<A: IsaMode> IsaDbase<A> {
+    pub fn from_file(file: &IsaFile) -> Self {
+        let mut issues = vec![];
+        let mut ops = vec![];
+
+        let mut modes = vec![];
+        for (name, def) in &file.addr_modes {
+            match parse_mode::<A>(name) {
+                Some(m) => modes.push((m, def.size)),
+                None => issues.push(IsaIssue::UnknownMode {
+                    mnemonic: "addr_modes".into(),
+                    mode: name.clone(),
+                }),
+            }
+        }
+
+        for (mnemonic, ins) in &file.instructions {
+            for (mode, def) in &ins.addr_modes {
+                let Some(addr_mode) = parse_mode::<A>(mode) else {
+                    issues.push(IsaIssue::UnknownMode {
+                        mnemonic: mnemonic.clone(),
+                        mode: mode.clone(),
+                    });
+                    continue;
+                };
+
+                let Some(opcode) = def.opcode.value() else {
+                    issues.push(IsaIssue::BadOpcode {
+                        mnemonic: mnemonic.clone(),
+                        mode: mode.clone(),
+                        text: def.opcode.to_string(),
+                    });
+                    continue;
+                };
+
+                let op = IsaOp::new(mnemonic, addr_mode, opcode, def.cycles, def.size)
+                    .with_flags(&ins.flags);
+                ops.push(op);
+            }
+        }
+
+        let unknown = file
+            .unknown
+            .as_ref()
+            .map(|u| {
+                let opcode = u.opcode.value().unwrap_or(0);
+                IsaOp::new("unknown", A::default(), opcode, u.cycles, u.size)
+            })
+            .unwrap_or_else(|| IsaOp::new("unknown", A::default(), 0, 1, 1));
+
+        let mut ret = Self::from_data(ops, unknown)
+            .with_flag_order(&file.flag_order)
+            .with_modes(modes);
+
+        for (mnemonic, ins) in &file.instructions {
+            for alias in &ins.aliases {
+                ret = ret.with_alias(alias, mnemonic);
+            }
+        }
+
+        ret.issues = issues;
+        ret
+    }
+
+    pub fn parse(text: &str) -> IsaResult<Self> {
+        Ok(Self::from_file(&IsaFile::from_json(text)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> IsaResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| IsaErr::Io(path.to_path_buf(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn with_flag_order(mut self, order: &str) -> Self {
+        self.flag_order = order.to_string();
+        self
+    }
+
+    /// Declare the addressing modes, size is with a one byte opcode
+    pub fn with_modes<I: IntoIterator<Item = (A, Option<usize>)>>(mut self, modes: I) -> Self {
+        self.modes.extend(modes);
+        self
+    }
+
+    pub fn with_alias(mut self, alias: &str, mnemonic: &str) -> Self {
+        self.aliases
+            .insert(alias.to_lowercase(), mnemonic.to_lowercase());
+        self
+    }
+
+    pub fn ops(&self) -> &[IsaOp<A>] {
+        &self.ops
+    }
+
+    pub fn flag_order(&self) -> &str {
+        &self.flag_order
+    }
+
+    pub fn unknown(&self) -> &IsaOp<A> {
+        &self.unknown
+    }
+
+    /// The op for this opcode, unknown if there isn't one
+    pub fn get(&self, opcode: usize) -> &IsaOp<A> {
+        self.op(opcode).unwrap_or(&self.unknown)
+    }
+
+    pub fn op(&self, opcode: usize) -> Option<&IsaOp<A>> {
+        self.by_opcode.get(&opcode).map(|i| &self.ops[*i])
+    }
+
+    pub fn mnemonics(&self) -> impl Iterator<Item = &IsaInfo<A>> {
+        self.by_name.values()
+    }
+
+    /// The op at addr, trying a prefixed opcode if the first byte isn't one
+    pub fn decode(&self, mem: &dyn MemoryIO, addr: usize) -> Option<&IsaOp<A>> {
+        let first = mem.inspect_byte(addr).ok()? as usize;
+
+        self.op(first).or_else(|| {
+            let second = mem.inspect_byte(addr + 1).ok()? as usize;
+            self.op((first << 8) | second)
+        })
+    }
+
+    /// How op changes a status register laid out as layout, None if the ISA doesn't say
+    /// layout is flag names most significant first with - for unused bits
+    pub fn flag_mods(&self, op: &IsaOp<A>, layout: &str) -> Result<Option<FlagMods>, FlagModErr> {
+        if op.flags.is_empty() {
+            Ok(None)
+        } else {
+            FlagMods::from_named(&self.flag_order, &op.flags, layout).map(Some)
+        }
+    }
+
+    /// Everything wrong with the description
+    pub fn validate(&self) -> Vec<IsaIssue> {
+        let mut issues = self.issues.clone();
+
+        let mut add = |issue: IsaIssue| {
+            if !issues.contains(&issue) {
+                issues.push(issue)
+            }
+        };
+
+        let mut seen_opcodes: HashMap<usize, &IsaOp<A>> = HashMap::new();
+        let mut seen_modes = HashMap::new();
+
+        for op in &self.ops {
+            let mode = format!("{:?}", op.addr_mode);
+
+            if let Some(first) = seen_opcodes.get(&op.opcode) {
+                add(IsaIssue::DuplicateOpcode {
+                    opcode: op.opcode,
+                    first: first.name(),
+                    second: op.name(),
+                });
+            } else {
+                seen_opcodes.insert(op.opcode, op);
+            }
+
+            let key = (op.mnemonic.to_lowercase(), op.addr_mode);
+            if seen_modes.insert(key, op.opcode).is_some() {
+                add(IsaIssue::DuplicateMode {
+                    mnemonic: op.mnemonic.clone(),
+                    mode: mode.clone(),
+                });
+            }
+
+            match self.modes.get(&op.addr_mode) {
+                Some(Some(size)) => {
+                    let expected = size + op.opcode_bytes() - 1;
+                    if op.size != expected {
+                        add(IsaIssue::SizeMismatch {
+                            mnemonic: op.mnemonic.clone(),
+                            mode,
+                            opcode: op.opcode,
+                            size: op.size,
+                            expected,
+                        })
+                    }
+                }
+                Some(None) => (),
+                None if self.modes.is_empty() => (),
+                None => add(IsaIssue::MissingMode {
+                    mnemonic: op.mnemonic.clone(),
+                    mode,
+                }),
+            }
+
+            let flags_ok = op.flags.is_empty()
+                || (op.flags.chars().count() == self.flag_order.chars().count()
+                    && op.flags.chars().all(|c| "-01+".contains(c)));
+
+            if !flags_ok {
+                add(IsaIssue::BadFlags {
+                    mnemonic: op.mnemonic.clone(),
+                    flags: op.flags.clone(),
+                    order: self.flag_order.clone(),
+                })
+            }
+        }
+
+        issues
+    }
+
+    /// Self if there's nothing wrong with it
+    pub fn checked(self) -> IsaResult<Self> {
+        let issues = self.validate();
+        if issues.is_empty() {
+            Ok(self)
+        } else {
+            Err(IsaErr::Invalid(issues))
+        }
+    }
+}
+
+impl<A: IsaMode> InstructionDbaseTrait<A, IsaOp<A>, IsaInfo<A>> for IsaDbase<A> {
+    fn from_text(json_str: &str) -> Self {
+        Self::parse(json_str).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn from_filename(file_name: &str) -> Self {
+        Self::load(file_name).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// First opcode and first mode win, the rest show up in validate
+    fn from_data(instructions: Vec<IsaOp<A>>, unknown: IsaOp<A>) -> Self {
+        let mut by_name: HashMap<String, IsaInfo<A>> = HashMap::new();
+        let mut by_opcode = HashMap::new();
+
+        for (i, op) in instructions.iter().enumerate() {
+            if by_opcode.contains_key(&op.opcode) {
+                continue;
+            }
+            by_opcode.insert(op.opcode, i);
+
+            let info = by_name
+                .entry(op.mnemonic.to_lowercase())
+                .or_insert_with(|| IsaInfo {
+                    mnemonic: op.mnemonic.clone(),
+                    modes: HashMap::new(),
+                });
+
+            info.modes.entry(op.addr_mode).or_insert_with(|| op.clone());
+        }
+
+        Self {
+            ops: instructions,
+            unknown,
+            flag_order: String::new(),
+            modes: HashMap::new(),
+            aliases: HashMap::new(),
+            by_name,
+            by_opcode,
+            issues: vec![],
+        }
+    }
+
+    fn get_opcode_info(&self, input: &str) -> Option<&IsaInfo<A>> {
+        let name = input.to_lowercase();
+        let name = self.aliases.get(&name).unwrap_or(&name);
+        self.by_name.get(name)
+    }
+
+    fn get_opcode_info_from_opcode(&self, opcode: usize) -> Option<&IsaInfo<A>> {
+        let op = self.op(opcode)?;
+        self.by_name.get(&op.mnemonic.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+    enum Mode {
+        #[default]
+        Inherent,
+        Immediate,
+        Direct,
+        Extended,
+    }
+
+    const GOOD: &str = r#"{
+        "flag_order": "NZC",
+        "addr_modes": { "Inherent": { "size": 1 }, "Immediate": { "size": 2 }, "Direct": {} },
+        "instructions": {
+            "Lsl": { "flags": "+++", "aliases": ["asl"], "addr_modes": {
+                "Inherent": { "opcode": "48", "cycles": 2, "size": 1 },
+                "Direct": { "opcode": 8, "cycles": 6, "size": 2 }
+            }},
+            "Lda": { "flags": "++-", "addr_modes": {
+                "Immediate": { "opcode": "0x86", "cycles": 2, "size": 2 }
+            }},
+            "Ldy": { "addr_modes": {
+                "Immediate": { "opcode": "108e", "cycles": 4, "size": 3 }
+            }}
+        }
+    }"#;
+
+    #[test]
+    fn lookups() {
+        let isa = IsaDbase::<Mode>::parse(GOOD).unwrap().checked().unwrap();
+
+        let asl = isa.get_opcode_info("ASL").unwrap();
+        assert_eq!(asl.mnemonic, "Lsl");
+        assert!(asl.supports_addr_mode(Mode::Direct));
+        assert!(!asl.supports_addr_mode(Mode::Immediate));
+        assert_eq!(asl.get_instruction(Mode::Direct).unwrap().opcode, 8);
+
+        let lda = isa.get(0x86);
+        assert_eq!((lda.mnemonic.as_str(), lda.get_bytes()), ("Lda", 2));
+        assert_eq!(isa.get(0x108e).opcode_bytes(), 2);
+        assert_eq!(
+            isa.get_opcode_info_from_opcode(0x48).unwrap().mnemonic,
+            "Lsl"
+        );
+        assert_eq!(isa.get(0x01).mnemonic, "unknown");
+    }
+
+    #[test]
+    fn validation() {
+        let text = r#"{
+            "flag_order": "NZC",
+            "addr_modes": { "Inherent": { "size": 1 }, "Immediate": { "size": 2 } },
+            "instructions": {
+                "Clr": { "flags": "01", "addr_modes": {
+                    "Inherent": { "opcode": "4f", "cycles": 2, "size": 2 },
+                    "Extended": { "opcode": "7f", "cycles": 6, "size": 3 },
+                    "Indexed": { "opcode": "6f", "cycles": 6, "size": 2 }
+                }},
+                "Lda": { "addr_modes": {
+                    "Immediate": { "opcode": "zz", "cycles": 2, "size": 2 },
+                    "Inherent": { "opcode": 79, "cycles": 2, "size": 1 }
+                }}
+            }
+        }"#;
+
+        let isa = IsaDbase::<Mode>::parse(text).unwrap();
+        let issues = isa.validate();
+
+        let expected = [
+            IsaIssue::UnknownMode {
+                mnemonic: "Clr".into(),
+                mode: "Indexed".into(),
+            },
+            IsaIssue::BadOpcode {
+                mnemonic: "Lda".into(),
+                mode: "Immediate".into(),
+                text: "zz".into(),
+            },
+            IsaIssue::MissingMode {
+                mnemonic: "Clr".into(),
+                mode: "Extended".into(),
+            },
+            IsaIssue::BadFlags {
+                mnemonic: "Clr".into(),
+                flags: "01".into(),
+                order: "NZC".into(),
+            },
+            IsaIssue::SizeMismatch {
+                mnemonic: "Clr".into(),
+                mode: "Inherent".into(),
+                opcode: 0x4f,
+                size: 2,
+                expected: 1,
+            },
+            IsaIssue::DuplicateOpcode {
+                opcode: 0x4f,
+                first: "Clr Inherent".into(),
+                second: "Lda Inherent".into(),
+            },
+        ];
+
+        for e in &expected {
+            assert!(issues.contains(e), "missing {e}");
+        }
+        assert_eq!(issues.len(), expected.len(), "{issues:#?}");
+
+        assert!(matches!(isa.checked(), Err(IsaErr::Invalid(_))));
+    }
+}
@@ -5,8 +5,34 @@ pub mod mem;
 pub mod instructions;
 pub mod breakpoints;
 pub mod traits;
+pub mod flow;
+pub mod symbols;
+pub mod profiler;
+pub mod callstack;
+pub mod replay;
+pub mod golden;
+pub mod stackguard;
+pub mod smc;
+pub mod regwatch;
+pub mod intsched;
+pub mod irqline;
+pub mod address;
+pub mod coverage;
+pub mod lockstep;
+pub mod reftrace;
+pub mod run;
+pub mod logpoint;
+pub mod memview;
+pub mod device;
+pub mod runner;
+pub mod isa;
+pub mod flagcheck;
+pub mod diss;
+pub mod flowdiss;
 pub use byteorder;
 
 // Reexport sha1
 pub use sha1;
 
+#[cfg(test)]
+mod testmachine;
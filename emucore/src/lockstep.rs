@@ -0,0 +1,329 @@
+use crate::run::RunTarget;
+use crate::mem::{Access, BusEvent, BusFilter, BusObserver, MemoryIO};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// Turns the bytes at an address into text for reports
+pub type Describe = Box<dyn Fn(&dyn MemoryIO, usize) -> String>;
+
+/// Collects writes made by a machine, subscribe it to the machine's MemMap
+/// with WriteLog::filter
+#[derive(Debug, Clone, Default)]
+pub struct WriteLog {
+    writes: Vec<(usize, u8)>,
+}
+
+impl WriteLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter() -> BusFilter {
+        BusFilter::new(0..0x1_0000, Access::WRITE)
+    }
+
+    /// Writes since last asked, one entry per byte
+    pub fn take(&mut self) -> Vec<(usize, u8)> {
+        std::mem::take(&mut self.writes)
+    }
+}
+
+impl BusObserver for WriteLog {
+    fn on_access(&mut self, event: &BusEvent) {
+        if event.word {
+            let [hi, lo] = event.val.to_be_bytes();
+            self.writes.push((event.addr, hi));
+            self.writes.push((event.addr + 1, lo));
+        } else {
+            self.writes.push((event.addr, event.val as u8));
+        }
+    }
+}
+
+/// What one machine did in one instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepRecord {
+    pub pc: usize,
+    pub text: String,
+    pub cycles: usize,
+    pub regs: BTreeMap<String, u64>,
+    pub writes: Vec<(usize, u8)>,
+}
+
+fn fmt_regs(regs: &BTreeMap<String, u64>) -> String {
+    regs.iter()
+        .map(|(k, v)| format!("{k}:{v:04x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn fmt_writes(writes: &[(usize, u8)]) -> String {
+    if writes.is_empty() {
+        return "-".to_string();
+    }
+
+    writes
+        .iter()
+        .map(|(a, v)| format!("${a:04x}={v:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl fmt::Display for StepRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "${:04x}: {:<20} {:>3}cy {}",
+            self.pc,
+            self.text,
+            self.cycles,
+            fmt_regs(&self.regs)
+        )
+    }
+}
+
+/// The first instruction the machines disagreed on
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Instructions run before this one
+    pub instruction: usize,
+    pub a: StepRecord,
+    pub b: StepRecord,
+    pub differences: Vec<String>,
+    /// Earlier instructions from machine a, oldest first
+    pub context: Vec<StepRecord>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Diverged at instruction {}", self.instruction)?;
+
+        for r in &self.context {
+            writeln!(f, "    {r}")?;
+        }
+
+        writeln!(f, "a > {}", self.a)?;
+        writeln!(f, "b > {}", self.b)?;
+
+        for d in &self.differences {
+            writeln!(f, "  {d}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LockstepErr {
+    #[error("Machine {side} failed at instruction {instruction}: {msg}")]
+    Step {
+        side: char,
+        instruction: usize,
+        msg: String,
+    },
+    #[error("{0}")]
+    Diverged(Box<Divergence>),
+}
+
+pub type LockstepResult<T> = Result<T, LockstepErr>;
+
+/// Runs two machines an instruction at a time and stops at the first difference
+/// in registers, cycles or memory writes
+pub struct Lockstep<A: RunTarget, B: RunTarget> {
+    pub a: A,
+    pub b: B,
+    log_a: Arc<Mutex<WriteLog>>,
+    log_b: Arc<Mutex<WriteLog>>,
+    history: VecDeque<StepRecord>,
+    context: usize,
+    instruction: usize,
+    describe: Describe,
+}
+
+/// Hex of the bytes at addr
+fn describe_bytes(mem: &dyn MemoryIO, addr: usize) -> String {
+    (addr..addr + 3)
+        .map(|a| {
+            mem.inspect_byte(a & 0xffff)
+                .map(|b| format!("{b:02x}"))
+                .unwrap_or("--".into())
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl<A: RunTarget, B: RunTarget> Lockstep<A, B> {
+    /// The write logs must already be subscribed to each machine's memory
+    pub fn new(a: A, log_a: Arc<Mutex<WriteLog>>, b: B, log_b: Arc<Mutex<WriteLog>>) -> Self {
+        Self {
+            a,
+            b,
+            log_a,
+            log_b,
+            history: VecDeque::new(),
+            context: 16,
+            instruction: 0,
+            describe: Box::new(describe_bytes),
+        }
+    }
+
+    /// How many earlier instructions to show when they diverge
+    pub fn with_context(self, context: usize) -> Self {
+        Self { context, ..self }
+    }
+
+    /// Use a disassembler to show instructions
+    pub fn with_describe(self, describe: Describe) -> Self {
+        Self { describe, ..self }
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.instruction
+    }
+
+    fn step_one<T: RunTarget>(
+        side: char,
+        instruction: usize,
+        t: &mut T,
+        log: &Mutex<WriteLog>,
+        describe: &Describe,
+    ) -> LockstepResult<StepRecord> {
+        let pc = t.pc();
+        let text = describe(t.mem(), pc);
+        let cycles = t.cycles();
+
+        log.lock().unwrap().take();
+
+        t.step().map_err(|e| LockstepErr::Step {
+            side,
+            instruction,
+            msg: e.to_string(),
+        })?;
+
+        Ok(StepRecord {
+            pc,
+            text,
+            cycles: t.cycles() - cycles,
+            regs: t.regs(),
+            writes: log.lock().unwrap().take(),
+        })
+    }
+
+    /// Step both machines and compare them
+    pub fn step(&mut self) -> LockstepResult<()> {
+        let n = self.instruction;
+        let a = Self::step_one('a', n, &mut self.a, &self.log_a, &self.describe)?;
+        let b = Self::step_one('b', n, &mut self.b, &self.log_b, &self.describe)?;
+
+        let differences = compare(&a, &b);
+
+        if !differences.is_empty() {
+            return Err(LockstepErr::Diverged(Box::new(Divergence {
+                instruction: n,
+                a,
+                b,
+                differences,
+                context: self.history.iter().cloned().collect(),
+            })));
+        }
+
+        if self.context != 0 {
+            if self.history.len() == self.context {
+                self.history.pop_front();
+            }
+            self.history.push_back(a);
+        }
+
+        self.instruction += 1;
+        Ok(())
+    }
+
+    /// Step up to n instructions
+    pub fn run(&mut self, n: usize) -> LockstepResult<()> {
+        (0..n).try_for_each(|_| self.step())
+    }
+}
+
+/// Registers only one machine has are ignored
+fn compare(a: &StepRecord, b: &StepRecord) -> Vec<String> {
+    let mut out = vec![];
+
+    if a.pc != b.pc {
+        out.push(format!("pc before: a ${:04x} b ${:04x}", a.pc, b.pc))
+    }
+
+    for (name, va) in &a.regs {
+        if let Some(vb) = b.regs.get(name) {
+            if va != vb {
+                out.push(format!("{name}: a ${va:04x} b ${vb:04x}"))
+            }
+        }
+    }
+
+    if a.cycles != b.cycles {
+        out.push(format!("cycles: a {} b {}", a.cycles, b.cycles))
+    }
+
+    if a.writes != b.writes {
+        out.push(format!(
+            "writes: a {} b {}",
+            fmt_writes(&a.writes),
+            fmt_writes(&b.writes)
+        ))
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmachine::Toy;
+
+    fn toy(buggy: bool) -> (Toy, Arc<Mutex<WriteLog>>) {
+        // add #0, sta $80, clc, add #1, sta $81
+        let code = [0x04, 0x00, 0x05, 0x80, 0x07, 0x04, 0x01, 0x05, 0x81];
+        let mut t = Toy::new(&[(0, &code)]);
+        t.buggy = buggy;
+
+        let log = Arc::new(Mutex::new(WriteLog::new()));
+        t.mem.subscribe(WriteLog::filter(), Box::new(log.clone()));
+        (t, log)
+    }
+
+    #[test]
+    fn stops_at_divergence() {
+        let (a, la) = toy(false);
+        let (b, lb) = toy(true);
+        let mut ls = Lockstep::new(a, la, b, lb).with_context(1);
+
+        match ls.run(5) {
+            Err(LockstepErr::Diverged(d)) => {
+                assert_eq!(d.instruction, 2);
+                assert_eq!(d.context.len(), 1);
+                assert_eq!(d.context[0].pc, 2);
+                assert_eq!(d.context[0].writes, vec![(0x80, 0)]);
+                assert_eq!(d.differences, vec!["sr: a $0002 b $0000"]);
+                assert!(d.to_string().contains("a > $0004: 07 04 01"));
+            }
+            r => panic!("expected divergence, got {r:?}"),
+        }
+
+        // Memory that started out different shows in the writes
+        let (mut a, la) = toy(false);
+        let (mut b, lb) = toy(false);
+        a.mem.upload(0, &[0x08, 0x20]).unwrap();
+        b.mem.upload(0, &[0x08, 0x20]).unwrap();
+        let mut ls = Lockstep::new(a, la, b, lb);
+        ls.b.mem.store_byte(0x20, 1).unwrap();
+
+        match ls.step() {
+            Err(LockstepErr::Diverged(d)) => {
+                assert_eq!(d.differences, vec!["writes: a $0020=01 b $0020=02"]);
+            }
+            r => panic!("expected divergence, got {r:?}"),
+        }
+    }
+}
@@ -0,0 +1,695 @@
+use crate::address::{Address, BankMap};
+use crate::breakpoints::{BreakPointTypes, BreakPoints};
+use crate::mem::MemoryIO;
+use crate::run::RunTarget;
+use grl_eval::{
+    infix_expr_to_value, Eval, ExprItem, GenericEvalErrorKind, GetPriority, ItemTraits, Operation,
+    OperationError, OperationErrorKind, OperatorTraits,
+};
+use grl_symbols::{ScopeIdTraits, SymIdTraits, SymbolTree};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LogErr {
+    #[error("{msg} in {text:?}")]
+    Parse { text: String, msg: String },
+    #[error("There is already a logpoint at {0}")]
+    Exists(Address),
+}
+
+pub type LogResult<T> = Result<T, LogErr>;
+
+/// A number in a logpoint expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value(pub i64);
+
+impl GetPriority for Value {}
+
+macro_rules! value_op {
+    ($trait:ident, $fn:ident, $checked:ident, $err:ident) => {
+        impl std::ops::$trait for Value {
+            type Output = OperationError<Value>;
+            fn $fn(self, rhs: Self) -> Self::Output {
+                self.0
+                    .$checked(rhs.0 as _)
+                    .map(Value)
+                    .ok_or(OperationErrorKind::$err)
+            }
+        }
+    };
+    ($trait:ident, $fn:ident, $op:tt) => {
+        impl std::ops::$trait for Value {
+            type Output = OperationError<Value>;
+            fn $fn(self, rhs: Self) -> Self::Output {
+                Ok(Value(self.0 $op rhs.0))
+            }
+        }
+    };
+}
+
+value_op!(Add, add, checked_add, Overflow);
+value_op!(Sub, sub, checked_sub, Overflow);
+value_op!(Mul, mul, checked_mul, Overflow);
+value_op!(Div, div, checked_div, DivideByZero);
+value_op!(Rem, rem, checked_rem, DivideByZero);
+value_op!(Shl, shl, checked_shl, IllegalShift);
+value_op!(Shr, shr, checked_shr, IllegalShift);
+value_op!(BitOr, bitor, |);
+value_op!(BitAnd, bitand, &);
+value_op!(BitXor, bitxor, ^);
+
+impl OperatorTraits for Value {}
+
+type Item = ExprItem<Value>;
+
+struct Evaluator;
+
+impl Eval<Item, GenericEvalErrorKind> for Evaluator {
+    fn eval_expr(&self, i: &Item) -> Result<Value, GenericEvalErrorKind> {
+        let items = i.expr().ok_or(GenericEvalErrorKind::ExpectedValue)?;
+        infix_expr_to_value(items, self)
+    }
+}
+
+/// An expression as written, names and memory are looked up when it runs
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Num(i64),
+    Name(String),
+    /// word is None for a plain [expr], the hole's format decides
+    Mem {
+        addr: Vec<Term>,
+        word: Option<bool>,
+    },
+    Group(Vec<Term>),
+    Op(Operation),
+}
+
+/// What a hole can see when it is evaluated
+struct Scope<'a> {
+    regs: &'a BTreeMap<String, u64>,
+    mem: &'a dyn MemoryIO,
+    symbols: &'a HashMap<String, i64>,
+}
+
+impl Scope<'_> {
+    /// Registers by lower case name, then symbols
+    fn lookup(&self, name: &str) -> Result<i64, String> {
+        self.regs
+            .get(&name.to_lowercase())
+            .map(|v| *v as i64)
+            .or_else(|| self.symbols.get(name).copied())
+            .ok_or_else(|| format!("unknown name {name}"))
+    }
+
+    fn resolve(&self, terms: &[Term]) -> Result<Vec<Item>, String> {
+        terms
+            .iter()
+            .map(|t| {
+                let v = match t {
+                    Term::Num(n) => *n,
+                    Term::Name(name) => self.lookup(name)?,
+                    Term::Mem { addr, word } => {
+                        let addr = self.eval(addr)? as usize;
+                        let r = if word.unwrap_or(false) {
+                            self.mem.inspect_word(addr)
+                        } else {
+                            self.mem.inspect_byte(addr).map(u16::from)
+                        };
+                        r.map_err(|e| e.to_string())? as i64
+                    }
+                    Term::Group(inner) => return Ok(Item::Expr(self.resolve(inner)?)),
+                    Term::Op(op) => return Ok(Item::Op(*op)),
+                };
+                Ok(Item::Val(Value(v)))
+            })
+            .collect()
+    }
+
+    fn eval(&self, terms: &[Term]) -> Result<i64, String> {
+        let items = self.resolve(terms)?;
+        infix_expr_to_value(&items, &Evaluator)
+            .map(|v| v.0)
+            .map_err(|e: GenericEvalErrorKind| e.to_string())
+    }
+}
+
+/// Parses the expression in a hole
+/// Numbers are $hex, 0xhex, %binary or decimal, [expr].b reads a byte and
+/// [expr].w a word, [expr] reads whichever the hole's format is wide enough for
+struct ExprParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl ExprParser<'_> {
+    fn skip_space(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> String {
+        let mut ret = String::new();
+        while let Some(c) = self.chars.next_if(|c| f(*c)) {
+            ret.push(c)
+        }
+        ret
+    }
+
+    fn number(&mut self, radix: u32) -> Result<Term, String> {
+        let txt = self.take_while(|c| c.is_digit(radix) || c == '_');
+        i64::from_str_radix(&txt.replace('_', ""), radix)
+            .map(Term::Num)
+            .map_err(|_| format!("bad number {txt:?}"))
+    }
+
+    fn operand(&mut self) -> Result<Term, String> {
+        self.skip_space();
+
+        match self.chars.peek().copied() {
+            Some('(') => {
+                self.chars.next();
+                Ok(Term::Group(self.expr(Some(')'))?))
+            }
+            Some('[') => {
+                self.chars.next();
+                let addr = self.expr(Some(']'))?;
+                let word = if self.chars.next_if_eq(&'.').is_some() {
+                    match self.chars.next().map(|c| c.to_ascii_lowercase()) {
+                        Some('w') => Some(true),
+                        Some('b') => Some(false),
+                        _ => return Err("expected .w or .b after ]".into()),
+                    }
+                } else {
+                    None
+                };
+                Ok(Term::Mem { addr, word })
+            }
+            Some('$') => {
+                self.chars.next();
+                self.number(16)
+            }
+            Some('%') => {
+                self.chars.next();
+                self.number(2)
+            }
+            Some('0') => {
+                self.chars.next();
+                if self.chars.next_if(|c| *c == 'x' || *c == 'X').is_some() {
+                    self.number(16)
+                } else if self.chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.number(10)
+                } else {
+                    Ok(Term::Num(0))
+                }
+            }
+            Some(c) if c.is_ascii_digit() => self.number(10),
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                loop {
+                    name += &self.take_while(|c| c.is_alphanumeric() || c == '_');
+                    // Scoped symbols, foo::bar
+                    let mut ahead = self.chars.clone();
+                    if ahead.next() == Some(':') && ahead.next() == Some(':') {
+                        self.chars.next();
+                        self.chars.next();
+                        name += "::";
+                    } else {
+                        break;
+                    }
+                }
+                Ok(Term::Name(name))
+            }
+            Some(c) => Err(format!("unexpected {c:?}")),
+            None => Err("expected a value".into()),
+        }
+    }
+
+    fn op(&mut self) -> Result<Option<Operation>, String> {
+        use Operation::*;
+        self.skip_space();
+
+        let Some(c) = self.chars.peek().copied() else {
+            return Ok(None);
+        };
+
+        let op = match c {
+            '+' => Add,
+            '-' => Sub,
+            '*' => Mul,
+            '/' => Div,
+            '%' => Rem,
+            '&' => BitAnd,
+            '|' => BitOr,
+            '^' => BitXor,
+            '<' | '>' => {
+                self.chars.next();
+                if self.chars.next_if_eq(&c).is_none() {
+                    return Err(format!("expected {c}{c}"));
+                }
+                return Ok(Some(if c == '<' { ShiftLeft } else { ShiftRight }));
+            }
+            _ => return Ok(None),
+        };
+
+        self.chars.next();
+        Ok(Some(op))
+    }
+
+    /// Operands separated by operators up to end, or the end of the text
+    fn expr(&mut self, end: Option<char>) -> Result<Vec<Term>, String> {
+        let mut ret = vec![self.operand()?];
+
+        while let Some(op) = self.op()? {
+            ret.push(Term::Op(op));
+            ret.push(self.operand()?);
+        }
+
+        self.skip_space();
+
+        match (self.chars.next(), end) {
+            (None, None) => Ok(ret),
+            (Some(c), Some(e)) if c == e => Ok(ret),
+            (None, Some(e)) => Err(format!("missing {e:?}")),
+            (Some(c), _) => Err(format!("unexpected {c:?}")),
+        }
+    }
+}
+
+/// How a hole's value is written, eg 04x
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Spec {
+    zero: bool,
+    width: usize,
+    radix: char,
+}
+
+impl Spec {
+    fn parse(txt: &str) -> Option<Self> {
+        let (num, radix) = match txt.chars().last() {
+            Some(c @ ('x' | 'X' | 'b' | 'd')) => (&txt[..txt.len() - 1], c),
+            _ => (txt, 'd'),
+        };
+
+        let width = if num.is_empty() { 0 } else { num.parse().ok()? };
+
+        Some(Self {
+            zero: num.starts_with('0'),
+            width,
+            radix,
+        })
+    }
+
+    /// Is it wide enough to show a 16 bit value
+    fn is_word(&self) -> bool {
+        match self.radix {
+            'x' | 'X' => self.width > 2,
+            'b' => self.width > 8,
+            _ => false,
+        }
+    }
+
+    fn format(&self, v: i64) -> String {
+        let txt = match self.radix {
+            'x' => format!("{v:x}"),
+            'X' => format!("{v:X}"),
+            'b' => format!("{v:b}"),
+            _ => v.to_string(),
+        };
+
+        let pad = if self.zero { '0' } else { ' ' };
+        let n = self.width.saturating_sub(txt.len());
+
+        match txt.strip_prefix('-') {
+            Some(digits) if self.zero => format!("-{}{digits}", "0".repeat(n)),
+            _ => format!("{}{txt}", pad.to_string().repeat(n)),
+        }
+    }
+}
+
+/// Give plain [expr] reads the size the format asks for
+fn size_reads(terms: &mut [Term], word: bool) {
+    for t in terms {
+        match t {
+            Term::Mem { addr, word: w } => {
+                size_reads(addr, word);
+                w.get_or_insert(word);
+            }
+            Term::Group(inner) => size_reads(inner, word),
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Hole { expr: Vec<Term>, spec: Spec },
+}
+
+/// A logpoint message, text with {expr} or {expr:spec} holes
+/// Use {{ and }} for braces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    parts: Vec<Part>,
+}
+
+/// Splits "expr:spec", ignoring the colons in foo::bar
+fn split_spec(hole: &str) -> (&str, Option<&str>) {
+    let b = hole.as_bytes();
+
+    let pos = (0..b.len())
+        .rev()
+        .find(|&i| b[i] == b':' && b.get(i + 1) != Some(&b':') && (i == 0 || b[i - 1] != b':'));
+
+    match pos {
+        Some(i) => (&hole[..i], Some(&hole[i + 1..])),
+        None => (hole, None),
+    }
+}
+
+impl Message {
+    pub fn parse(text: &str) -> LogResult<Self> {
+        let err = |msg: String| LogErr::Parse {
+            text: text.to_string(),
+            msg,
+        };
+
+        let mut parts = vec![];
+        let mut lit = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => lit.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => lit.push('}'),
+                '}' => return Err(err("unmatched }".into())),
+                '{' => {
+                    let mut hole = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => hole.push(c),
+                            None => return Err(err("unclosed {".into())),
+                        }
+                    }
+
+                    let (expr, spec) = split_spec(&hole);
+                    let spec = match spec {
+                        Some(s) => {
+                            Spec::parse(s).ok_or_else(|| err(format!("bad format {s:?}")))?
+                        }
+                        None => Spec::default(),
+                    };
+
+                    let mut expr = ExprParser {
+                        chars: expr.chars().peekable(),
+                    }
+                    .expr(None)
+                    .map_err(|msg| err(format!("{{{hole}}}: {msg}")))?;
+                    size_reads(&mut expr, spec.is_word());
+
+                    if !lit.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut lit)));
+                    }
+                    parts.push(Part::Hole { expr, spec });
+                }
+                _ => lit.push(c),
+            }
+        }
+
+        if !lit.is_empty() {
+            parts.push(Part::Text(lit));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Fill in the holes, a hole that can't be evaluated shows the error
+    pub fn render(
+        &self,
+        regs: &BTreeMap<String, u64>,
+        mem: &dyn MemoryIO,
+        symbols: &HashMap<String, i64>,
+    ) -> String {
+        let scope = Scope { regs, mem, symbols };
+
+        self.parts
+            .iter()
+            .map(|p| match p {
+                Part::Text(t) => t.clone(),
+                Part::Hole { expr, spec } => match scope.eval(expr) {
+                    Ok(v) => spec.format(v),
+                    Err(e) => format!("<{e}>"),
+                },
+            })
+            .collect()
+    }
+}
+
+/// A line written by a logpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub cycle: usize,
+    pub pc: usize,
+    pub text: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10} ${:04x}: {}", self.cycle, self.pc, self.text)
+    }
+}
+
+/// Where logpoint output goes
+pub trait LogSink {
+    fn log(&mut self, line: LogLine);
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrSink;
+
+impl LogSink for StderrSink {
+    fn log(&mut self, line: LogLine) {
+        eprintln!("{line}")
+    }
+}
+
+pub struct FileSink {
+    out: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+impl LogSink for FileSink {
+    fn log(&mut self, line: LogLine) {
+        if let Err(e) = writeln!(self.out, "{line}") {
+            log::error!("Logpoint output: {e}")
+        }
+    }
+}
+
+/// Keeps lines in memory, share it with Arc<Mutex<..>> to read them back
+#[derive(Debug, Clone, Default)]
+pub struct BufferSink {
+    lines: Vec<LogLine>,
+}
+
+impl BufferSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> &[LogLine] {
+        &self.lines
+    }
+
+    pub fn take(&mut self) -> Vec<LogLine> {
+        std::mem::take(&mut self.lines)
+    }
+}
+
+impl LogSink for BufferSink {
+    fn log(&mut self, line: LogLine) {
+        self.lines.push(line)
+    }
+}
+
+impl<T: LogSink> LogSink for Arc<Mutex<T>> {
+    fn log(&mut self, line: LogLine) {
+        self.lock().unwrap().log(line)
+    }
+}
+
+/// Breakpoints that write a message instead of stopping
+/// A tracepoint has no message and writes every register
+pub struct Logpoints {
+    breakpoints: BreakPoints,
+    messages: HashMap<usize, Option<Message>>,
+    symbols: HashMap<String, i64>,
+    sink: Box<dyn LogSink + Send>,
+}
+
+impl Logpoints {
+    /// Send so a runner thread can own them
+    pub fn new(sink: Box<dyn LogSink + Send>) -> Self {
+        Self {
+            breakpoints: BreakPoints::new(),
+            messages: HashMap::new(),
+            symbols: HashMap::new(),
+            sink,
+        }
+    }
+
+    /// Names holes can use, registers win over symbols
+    pub fn add_symbol(&mut self, name: &str, value: i64) {
+        self.symbols.insert(name.to_string(), value);
+    }
+
+    pub fn with_symbol_tree<SCOPEID, SYMID>(
+        mut self,
+        syms: &SymbolTree<SCOPEID, SYMID, i64>,
+    ) -> Self
+    where
+        SCOPEID: ScopeIdTraits,
+        SYMID: SymIdTraits,
+    {
+        for si in syms.symbols() {
+            if let Some(v) = si.value {
+                self.add_symbol(si.scoped_name().trim_start_matches("::"), v)
+            }
+        }
+        self
+    }
+
+    fn insert<A: Into<Address>>(&mut self, addr: A, msg: Option<Message>) -> LogResult<usize> {
+        let addr = addr.into();
+        let id = self
+            .breakpoints
+            .add(addr, BreakPointTypes::EXEC)
+            .ok_or(LogErr::Exists(addr))?;
+        self.messages.insert(id, msg);
+        Ok(id)
+    }
+
+    pub fn add<A: Into<Address>>(&mut self, addr: A, msg: &str) -> LogResult<usize> {
+        let msg = Message::parse(msg)?;
+        self.insert(addr, Some(msg))
+    }
+
+    pub fn add_tracepoint<A: Into<Address>>(&mut self, addr: A) -> LogResult<usize> {
+        self.insert(addr, None)
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.breakpoints.remove_by_id(id);
+        self.messages.remove(&id);
+    }
+
+    /// To enable or disable logpoints
+    pub fn breakpoints_mut(&mut self) -> &mut BreakPoints {
+        &mut self.breakpoints
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    /// Call before each step, logs anything at the pc
+    /// Returns how many lines were written
+    pub fn check<T: RunTarget>(&mut self, target: &T, map: &dyn BankMap) -> usize {
+        let pc = target.pc();
+        let hits: Vec<_> = self
+            .breakpoints
+            .hits(pc, BreakPointTypes::EXEC, map)
+            .iter()
+            .map(|b| b.id())
+            .collect();
+
+        if hits.is_empty() {
+            return 0;
+        }
+
+        let regs = target.regs();
+
+        for id in &hits {
+            let text = match self.messages.get(id) {
+                Some(Some(msg)) => msg.render(&regs, target.mem(), &self.symbols),
+                _ => regs
+                    .iter()
+                    .map(|(k, v)| format!("{k}=${v:x}"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+
+            self.sink.log(LogLine {
+                cycle: target.cycles(),
+                pc,
+                text,
+            })
+        }
+
+        hits.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemBlock;
+
+    fn mem() -> MemBlock<byteorder::BigEndian> {
+        let mut m = MemBlock::new("ram", false, &(0xc800..0xc900));
+        m.store_word(0xc880, 0x1234).unwrap();
+        m
+    }
+
+    #[test]
+    fn render() {
+        let regs: BTreeMap<_, _> = [("a".to_string(), 0x12), ("x".to_string(), 3)].into();
+        let syms: HashMap<_, _> = [("enemies::base".to_string(), 0xc880)].into();
+        let mem = mem();
+
+        let msg = Message::parse("enemy {x}: pos=${[$C880].w:04x} A={A:02X} {{ok}}").unwrap();
+        assert_eq!(
+            msg.render(&regs, &mem, &syms),
+            "enemy 3: pos=$1234 A=12 {ok}"
+        );
+
+        let msg = Message::parse("{[enemies::base + x * (1 + 1) - 6]:x} {%101 << 2:b}").unwrap();
+        assert_eq!(msg.render(&regs, &mem, &syms), "12 10100");
+
+        let msg = Message::parse("{y} {a / 0}").unwrap();
+        assert_eq!(
+            msg.render(&regs, &mem, &syms),
+            "<unknown name y> <Divide by zero>"
+        );
+
+        // The example from the docs, the format asks for a word
+        let msg = Message::parse("enemy {x}: pos=${[$C880]:04x} A={A}").unwrap();
+        assert_eq!(msg.render(&regs, &mem, &syms), "enemy 3: pos=$1234 A=18");
+
+        let msg = Message::parse("{[$C880].b:04x} {[$C880]:02x} {[[$c880]]:04x}").unwrap();
+        assert_eq!(
+            msg.render(&regs, &mem, &syms),
+            "0012 12 <Illegal address 0x1234>"
+        );
+
+        assert!(Message::parse("{a +}").is_err());
+        assert!(Message::parse("{[a].q}").is_err());
+        assert!(Message::parse("{[a}").is_err());
+        assert!(Message::parse("{a:q}").is_err());
+    }
+}
@@ -126,6 +126,14 @@ impl MemoryIO for LoggingMemMap {
         self.mem_map.get_name()
     }
 
+    fn set_bus_cycle(&mut self, cycle: usize) {
+        self.mem_map.set_bus_cycle(cycle)
+    }
+
+    fn take_wait_cycles(&mut self) -> usize {
+        self.mem_map.take_wait_cycles()
+    }
+
     fn get_range(&self) -> std::ops::Range<usize> {
         self.mem_map.get_range()
     }
@@ -12,6 +12,8 @@ pub enum MemErrorTypes {
     IllegalWrite(usize),
     #[error("Illegal read 0x{0:0X}")]
     IllegalRead(usize),
+    #[error("Illegal execute 0x{0:0X}")]
+    IllegalExec(usize),
 }
 
 pub type MemResult<T> = std::result::Result<T, MemErrorTypes>;
@@ -66,6 +68,28 @@ pub trait MemoryIO {
 
     // Min implementation end
 
+    /// Read of an opcode byte, a normal read unless overridden
+    fn fetch_byte(&mut self, addr: usize) -> MemResult<u8> {
+        self.load_byte(addr)
+    }
+
+    /// Read of an operand from the instruction stream
+    fn fetch_operand_byte(&mut self, addr: usize) -> MemResult<u8> {
+        self.load_byte(addr)
+    }
+
+    fn fetch_operand_word(&mut self, addr: usize) -> MemResult<u16> {
+        self.load_word(addr)
+    }
+
+    /// Tell the memory an instruction is starting and on which cycle
+    fn set_bus_cycle(&mut self, _cycle: usize) {}
+
+    /// Wait and contention cycles added by accesses since last asked
+    fn take_wait_cycles(&mut self) -> usize {
+        0
+    }
+
     fn get_name(&self) -> String {
         "default".to_string()
     }
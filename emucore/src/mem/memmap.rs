@@ -1,10 +1,14 @@
 // use mem::Memory;
+use super::{Access, BoxedObserver, BusEvent, BusFilter, BusObservers, ObserverId};
+use super::{BusTiming, ContentionFn, WaitStates};
 use super::{MemErrorTypes, MemResult, MemoryIO};
 use sha1::Sha1;
 use std::fmt;
+use std::ops::Range;
 
 pub trait MemMapIO {
-    fn add_memory(&mut self, mem: Box<dyn MemoryIO>);
+    /// Regions are Send so the map can move to a runner thread
+    fn add_memory(&mut self, mem: Box<dyn MemoryIO + Send>);
 
     fn add_mem_block(&mut self, _name: &str, _read_only: bool, _start: u16, _size: u32) {
         todo!()
@@ -13,8 +17,11 @@ pub trait MemMapIO {
 
 #[derive(Default)]
 pub struct MemMap {
-    all_memory: Vec<Box<dyn MemoryIO>>,
+    all_memory: Vec<Box<dyn MemoryIO + Send>>,
     name: String,
+    observers: BusObservers,
+    no_exec: Vec<Range<usize>>,
+    timing: BusTiming,
 }
 
 impl fmt::Debug for MemMap {
@@ -32,12 +39,12 @@ impl fmt::Debug for MemMap {
 }
 
 impl MemoryIO for MemMap {
-    fn inspect_byte(&self, _addr: usize) -> MemResult<u8> {
-       panic!() 
+    fn inspect_byte(&self, addr: usize) -> MemResult<u8> {
+        self.find_region(addr)?.inspect_byte(addr)
     }
 
-    fn inspect_word(&self, _addr: usize) -> MemResult<u16> {
-        panic!()
+    fn inspect_word(&self, addr: usize) -> MemResult<u16> {
+        self.find_region(addr)?.inspect_word(addr)
     }
     fn update_sha1(&self, digest: &mut Sha1) {
         for m in &self.all_memory {
@@ -61,29 +68,81 @@ impl MemoryIO for MemMap {
         0..0x1_0000
     }
 
+    fn set_bus_cycle(&mut self, cycle: usize) {
+        self.timing.set_cycle(cycle);
+        self.observers.instruction()
+    }
+
+    fn take_wait_cycles(&mut self) -> usize {
+        self.timing.take()
+    }
+
     fn load_byte(&mut self, addr: usize) -> MemResult<u8> {
         let m = self.get_region(addr)?;
-        m.load_byte(addr)
+        let val = m.load_byte(addr)?;
+        self.notify(Access::READ, addr, val.into(), false);
+        Ok(val)
+    }
+
+    fn fetch_byte(&mut self, addr: usize) -> MemResult<u8> {
+        self.check_exec(addr, 1)?;
+        let m = self.get_region(addr)?;
+        let val = m.fetch_byte(addr)?;
+        self.notify(Access::FETCH, addr, val.into(), false);
+        Ok(val)
+    }
+
+    fn fetch_operand_byte(&mut self, addr: usize) -> MemResult<u8> {
+        self.check_exec(addr, 1)?;
+        let m = self.get_region(addr)?;
+        let val = m.fetch_operand_byte(addr)?;
+        self.notify(Access::OPERAND, addr, val.into(), false);
+        Ok(val)
     }
+
+    fn fetch_operand_word(&mut self, addr: usize) -> MemResult<u16> {
+        self.check_exec(addr, 2)?;
+        let m = self.get_region(addr)?;
+        let val = m.fetch_operand_word(addr)?;
+        self.notify(Access::OPERAND, addr, val, true);
+        Ok(val)
+    }
+
     fn load_word(&mut self, addr: usize) -> MemResult<u16> {
         let m = self.get_region(addr)?;
-        m.load_word(addr)
+        let val = m.load_word(addr)?;
+        self.notify(Access::READ, addr, val, true);
+        Ok(val)
     }
 
     fn store_byte(&mut self, addr: usize, val: u8) -> MemResult<()> {
+        let old = self.old_value(addr, false);
         let m = self.get_region(addr)?;
-        m.store_byte(addr, val)
+        m.store_byte(addr, val)?;
+        self.notify_write(addr, val.into(), old, false);
+        Ok(())
     }
 
     fn store_word(&mut self, addr: usize, val: u16) -> MemResult<()> {
+        let old = self.old_value(addr, true);
         let m = self.get_region(addr)?;
-        m.store_word(addr, val)
+        m.store_word(addr, val)?;
+        self.notify_write(addr, val, old, true);
+        Ok(())
     }
 }
 
 #[allow(dead_code)]
 impl MemMap {
-    fn get_region(&mut self, addr: usize) -> MemResult<&mut Box<dyn MemoryIO>> {
+    fn find_region(&self, addr: usize) -> MemResult<&dyn MemoryIO> {
+        self.all_memory
+            .iter()
+            .find(|m| m.is_in_range(addr))
+            .map(|m| m.as_ref() as &dyn MemoryIO)
+            .ok_or(MemErrorTypes::IllegalAddress(addr))
+    }
+
+    fn get_region(&mut self, addr: usize) -> MemResult<&mut Box<dyn MemoryIO + Send>> {
         for m in &mut self.all_memory {
             if m.is_in_range(addr) {
                 return Ok(m);
@@ -97,12 +156,180 @@ impl MemMap {
         Self {
             all_memory: Vec::with_capacity(64*1024),
             name: "all memory".to_string(),
+            observers: BusObservers::new(),
+            no_exec: vec![],
+            timing: BusTiming::default(),
+        }
+    }
+
+    /// Instruction fetches from this range will fail
+    pub fn set_no_exec(&mut self, range: Range<usize>) {
+        self.no_exec.push(range)
+    }
+
+    /// Flag a whole region as non executable, returns its range if found
+    pub fn set_no_exec_by_name(&mut self, name: &str) -> Option<Range<usize>> {
+        let range = self
+            .all_memory
+            .iter()
+            .find(|m| m.get_name() == name)
+            .map(|m| m.get_range())?;
+
+        self.set_no_exec(range.clone());
+        Some(range)
+    }
+
+    pub fn clear_no_exec(&mut self) {
+        self.no_exec.clear()
+    }
+
+    pub fn is_executable(&self, addr: usize) -> bool {
+        !self.no_exec.iter().any(|r| r.contains(&addr))
+    }
+
+    /// Add cycles to every access in range
+    pub fn set_wait_states(&mut self, range: Range<usize>, waits: WaitStates) {
+        self.timing.set_wait_states(range, waits)
+    }
+
+    /// Add wait states to a whole region, returns its range if found
+    pub fn set_wait_states_by_name(&mut self, name: &str, waits: WaitStates) -> Option<Range<usize>> {
+        let range = self
+            .all_memory
+            .iter()
+            .find(|m| m.get_name() == name)
+            .map(|m| m.get_range())?;
+
+        self.set_wait_states(range.clone(), waits);
+        Some(range)
+    }
+
+    /// Ask f for stall cycles on every access in range
+    pub fn set_contention(&mut self, range: Range<usize>, f: ContentionFn) {
+        self.timing.set_contention(range, f)
+    }
+
+    pub fn clear_wait_states(&mut self) {
+        self.timing.clear()
+    }
+
+    /// Observe accesses that match the filter
+    pub fn subscribe(&mut self, filter: BusFilter, observer: BoxedObserver) -> ObserverId {
+        self.observers.subscribe(filter, observer)
+    }
+
+    pub fn unsubscribe(&mut self, id: ObserverId) -> Option<BoxedObserver> {
+        self.observers.unsubscribe(id)
+    }
+
+    #[inline]
+    fn notify(&mut self, access: Access, addr: usize, val: u16, word: bool) {
+        self.charge(access, addr, word);
+
+        if !self.observers.is_empty() {
+            self.observers.notify(&BusEvent {
+                access,
+                addr,
+                val,
+                old: val,
+                word,
+            })
+        }
+    }
+
+    /// Only read what a write replaces if someone is watching
+    #[inline]
+    fn old_value(&self, addr: usize, word: bool) -> Option<u16> {
+        let watched = |a| self.observers.is_watched(a, Access::WRITE);
+
+        if !(watched(addr) || word && watched(addr + 1)) {
+            return None;
+        }
+
+        if word {
+            self.inspect_word(addr).ok()
+        } else {
+            self.inspect_byte(addr).ok().map(u16::from)
+        }
+    }
+
+    #[inline]
+    fn notify_write(&mut self, addr: usize, val: u16, old: Option<u16>, word: bool) {
+        self.charge(Access::WRITE, addr, word);
+
+        if let Some(old) = old {
+            self.observers.notify(&BusEvent {
+                access: Access::WRITE,
+                addr,
+                val,
+                old,
+                word,
+            })
+        }
+    }
+
+    #[inline]
+    fn charge(&mut self, access: Access, addr: usize, word: bool) {
+        let len = if word { 2 } else { 1 };
+        self.timing.charge(addr..addr + len, access)
+    }
+
+    fn check_exec(&self, addr: usize, len: usize) -> MemResult<()> {
+        match (addr..addr + len).find(|a| !self.is_executable(*a)) {
+            Some(a) => Err(MemErrorTypes::IllegalExec(a)),
+            None => Ok(()),
         }
     }
 }
 
 impl MemMapIO for MemMap {
-    fn add_memory(&mut self, mem: Box<dyn MemoryIO>) {
+    fn add_memory(&mut self, mem: Box<dyn MemoryIO + Send>) {
         self.all_memory.push(mem)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemBlock;
+    use byteorder::BigEndian;
+
+    #[test]
+    fn no_exec() {
+        let mut mm = MemMap::new();
+        mm.add_memory(Box::new(MemBlock::<BigEndian>::new("ram", false, &(0..0x100))));
+        mm.add_memory(Box::new(MemBlock::<BigEndian>::new("io", false, &(0x100..0x110))));
+
+        assert_eq!(mm.set_no_exec_by_name("io"), Some(0x100..0x110));
+        assert_eq!(mm.set_no_exec_by_name("rom"), None);
+
+        assert!(mm.fetch_byte(0xff).is_ok());
+        assert!(mm.load_byte(0x100).is_ok());
+        assert_eq!(mm.fetch_byte(0x100), Err(MemErrorTypes::IllegalExec(0x100)));
+        assert_eq!(mm.fetch_byte(0x110), Err(MemErrorTypes::IllegalAddress(0x110)));
+    }
+
+    #[test]
+    fn wait_states() {
+        let mut mm = MemMap::new();
+        mm.add_memory(Box::new(MemBlock::<BigEndian>::new("ram", false, &(0..0x100))));
+        mm.add_memory(Box::new(MemBlock::<BigEndian>::new("rom", false, &(0x100..0x200))));
+
+        mm.set_wait_states_by_name("rom", WaitStates::new(1, 0));
+        // Video steals odd cycles
+        mm.set_contention(0x80..0x100, Box::new(|_, _, cycle| cycle % 2));
+
+        mm.set_bus_cycle(10);
+        mm.fetch_byte(0x100).unwrap();
+        mm.fetch_operand_word(0x101).unwrap();
+        mm.store_byte(0x180, 0).unwrap();
+        assert_eq!(mm.take_wait_cycles(), 3);
+        assert_eq!(mm.take_wait_cycles(), 0);
+
+        mm.set_bus_cycle(20);
+        mm.load_byte(0x80).unwrap();
+        mm.load_byte(0x81).unwrap();
+        mm.inspect_byte(0x82).unwrap();
+        assert_eq!(mm.take_wait_cycles(), 1);
+    }
+}
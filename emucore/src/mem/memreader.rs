@@ -24,13 +24,10 @@ impl<'a> MemReader<'a> {
         self.start_addr = addr;
     }
 
-    pub fn get_taken_bytes(&self) -> Vec<u8> {
-        let r= self.get_taken_range();
-        let mut ret = vec![];
-        for a in r {
-            ret.push( self.mem.inspect_byte(a).unwrap() );
-        }
-        ret
+    pub fn get_taken_bytes(&self) -> Result<Vec<u8>, MemErrorTypes> {
+        self.get_taken_range()
+            .map(|a| self.mem.inspect_byte(a))
+            .collect()
     }
     pub fn skip_bytes(&mut self, n : usize)  {
         self.addr += n
@@ -54,6 +51,13 @@ impl<'a> MemReader<'a> {
         self.addr += 1;
         ret
     }
+    /// Like next_byte but reads as an opcode fetch
+    pub fn fetch_byte(&mut self) -> Result<u8, MemErrorTypes> {
+        let ret = self.mem.fetch_byte(self.addr );
+        self.addr += 1;
+        ret
+    }
+
     pub fn next_word(&mut self) -> Result<u16, MemErrorTypes> {
         let ret = self.mem.load_word(self.addr );
         self.addr += 2;
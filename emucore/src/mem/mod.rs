@@ -4,11 +4,15 @@ mod maker;
 mod memblock;
 mod memcore;
 mod memmap;
+mod observer;
 mod region;
+mod waitstates;
 
 pub use lmemmap::*;
 pub use memblock::*;
 pub use memcore::*;
 pub use memmap::*;
+pub use observer::*;
 pub use region::*;
 pub use memreader::*;
+pub use waitstates::*;
@@ -0,0 +1,228 @@
+use bitflags::bitflags;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Access: u8 {
+        const READ = 0x01;
+        const WRITE = 0x02;
+        /// Opcode fetch
+        const FETCH = 0x04;
+        /// Operand fetch from the instruction stream
+        const OPERAND = 0x08;
+        const CODE = Self::FETCH.bits() | Self::OPERAND.bits();
+        const ALL = Self::READ.bits() | Self::WRITE.bits() | Self::CODE.bits();
+    }
+}
+
+/// A single bus access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusEvent {
+    pub access: Access,
+    pub addr: usize,
+    pub val: u16,
+    /// Value before a write, the same as val for other accesses
+    pub old: u16,
+    pub word: bool,
+}
+
+impl BusEvent {
+    /// Addresses touched by this access
+    pub fn range(&self) -> Range<usize> {
+        let len = if self.word { 2 } else { 1 };
+        self.addr..self.addr + len
+    }
+}
+
+pub trait BusObserver {
+    fn on_access(&mut self, event: &BusEvent);
+    /// The cpu is about to start an instruction
+    fn on_instruction(&mut self) {}
+}
+
+impl<F: FnMut(&BusEvent)> BusObserver for F {
+    fn on_access(&mut self, event: &BusEvent) {
+        self(event)
+    }
+}
+
+/// Lets the subscriber keep a handle to read results back
+impl<T: BusObserver> BusObserver for Arc<Mutex<T>> {
+    fn on_access(&mut self, event: &BusEvent) {
+        self.lock().unwrap().on_access(event)
+    }
+
+    fn on_instruction(&mut self) {
+        self.lock().unwrap().on_instruction()
+    }
+}
+
+/// Observers are Send so a memory map can move to a runner thread
+pub type BoxedObserver = Box<dyn BusObserver + Send>;
+
+/// Which accesses an observer wants to see
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusFilter {
+    pub range: Range<usize>,
+    pub access: Access,
+}
+
+impl BusFilter {
+    pub fn new(range: Range<usize>, access: Access) -> Self {
+        Self { range, access }
+    }
+
+    pub fn all() -> Self {
+        Self::new(0..0x1_0000, Access::ALL)
+    }
+
+    pub fn matches(&self, event: &BusEvent) -> bool {
+        let r = event.range();
+        self.access.intersects(event.access) && r.start < self.range.end && self.range.start < r.end
+    }
+}
+
+pub type ObserverId = usize;
+
+struct Subscription {
+    id: ObserverId,
+    filter: BusFilter,
+    observer: BoxedObserver,
+}
+
+/// Observers and a per address map of what is watched
+/// so unwatched accesses only cost a table lookup
+#[derive(Default)]
+pub struct BusObservers {
+    subs: Vec<Subscription>,
+    watched: Vec<Access>,
+    next_id: ObserverId,
+}
+
+impl BusObservers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subs.is_empty()
+    }
+
+    pub fn subscribe(&mut self, filter: BusFilter, observer: BoxedObserver) -> ObserverId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subs.push(Subscription {
+            id,
+            filter,
+            observer,
+        });
+        self.rebuild();
+        id
+    }
+
+    /// Hands the observer back so its results can be read
+    pub fn unsubscribe(&mut self, id: ObserverId) -> Option<BoxedObserver> {
+        let pos = self.subs.iter().position(|s| s.id == id)?;
+        let sub = self.subs.remove(pos);
+        self.rebuild();
+        Some(sub.observer)
+    }
+
+    fn rebuild(&mut self) {
+        self.watched.clear();
+
+        let end = self.subs.iter().map(|s| s.filter.range.end).max();
+
+        if let Some(end) = end {
+            self.watched.resize(end, Access::empty());
+
+            for s in &self.subs {
+                for a in s.filter.range.clone() {
+                    self.watched[a] |= s.filter.access;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_watched(&self, addr: usize, access: Access) -> bool {
+        self.watched.get(addr).is_some_and(|w| w.intersects(access))
+    }
+
+    pub fn notify(&mut self, event: &BusEvent) {
+        let r = event.range();
+
+        if !r.clone().any(|a| self.is_watched(a, event.access)) {
+            return;
+        }
+
+        for s in &mut self.subs {
+            if s.filter.matches(event) {
+                s.observer.on_access(event)
+            }
+        }
+    }
+
+    pub fn instruction(&mut self) {
+        for s in &mut self.subs {
+            s.observer.on_instruction()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Counter {
+        hits: Vec<usize>,
+    }
+
+    impl BusObserver for Counter {
+        fn on_access(&mut self, event: &BusEvent) {
+            self.hits.push(event.addr)
+        }
+    }
+
+    fn ev(access: Access, addr: usize, word: bool) -> BusEvent {
+        BusEvent {
+            access,
+            addr,
+            val: 0,
+            old: 0,
+            word,
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let writes = Arc::new(Mutex::new(Counter::default()));
+        let mut obs = BusObservers::new();
+
+        obs.subscribe(
+            BusFilter::new(0x100..0x200, Access::WRITE),
+            Box::new(writes.clone()),
+        );
+
+        obs.notify(&ev(Access::WRITE, 0x100, false));
+        obs.notify(&ev(Access::READ, 0x100, false));
+        obs.notify(&ev(Access::WRITE, 0x200, false));
+        obs.notify(&ev(Access::WRITE, 0xff, true));
+        obs.notify(&ev(Access::WRITE, 0x1000, false));
+
+        assert_eq!(writes.lock().unwrap().hits, vec![0x100, 0xff]);
+        assert!(!obs.is_watched(0x1ff, Access::FETCH));
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let mut obs = BusObservers::new();
+        let id = obs.subscribe(BusFilter::all(), Box::new(|_: &BusEvent| ()));
+        assert!(obs.is_watched(0x1234, Access::READ));
+        assert!(obs.unsubscribe(id).is_some());
+        assert!(!obs.is_watched(0x1234, Access::READ));
+        assert!(obs.is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use super::Access;
+use std::fmt;
+use std::ops::Range;
+
+/// Works out stall cycles for an access
+/// Called with the address, the kind of access and the cycle it lands on
+pub type ContentionFn = Box<dyn FnMut(usize, Access, usize) -> usize + Send>;
+
+/// Extra cycles per byte accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WaitStates {
+    /// Reads and instruction fetches
+    pub read: usize,
+    pub write: usize,
+}
+
+impl WaitStates {
+    pub fn new(read: usize, write: usize) -> Self {
+        Self { read, write }
+    }
+
+    fn for_access(&self, access: Access) -> usize {
+        if access.contains(Access::WRITE) {
+            self.write
+        } else {
+            self.read
+        }
+    }
+}
+
+struct Contention {
+    range: Range<usize>,
+    f: ContentionFn,
+}
+
+/// Bus stretching for a memory map
+/// Cycles accumulate as accesses are made and the cpu collects them
+/// at the end of each instruction
+#[derive(Default)]
+pub struct BusTiming {
+    waits: Vec<(Range<usize>, WaitStates)>,
+    contention: Vec<Contention>,
+    /// Cycle the current instruction started on
+    cycle: usize,
+    /// Accesses made by the current instruction
+    accesses: usize,
+    pending: usize,
+}
+
+impl fmt::Debug for BusTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BusTiming")
+            .field("waits", &self.waits)
+            .field("contention", &self.contention.len())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl BusTiming {
+    pub fn is_empty(&self) -> bool {
+        self.waits.is_empty() && self.contention.is_empty()
+    }
+
+    pub fn set_wait_states(&mut self, range: Range<usize>, waits: WaitStates) {
+        self.waits.push((range, waits))
+    }
+
+    pub fn set_contention(&mut self, range: Range<usize>, f: ContentionFn) {
+        self.contention.push(Contention { range, f })
+    }
+
+    pub fn clear(&mut self) {
+        self.waits.clear();
+        self.contention.clear();
+        self.pending = 0;
+    }
+
+    pub fn set_cycle(&mut self, cycle: usize) {
+        self.cycle = cycle;
+        self.accesses = 0;
+    }
+
+    /// Account for an access to every byte in addrs
+    pub fn charge(&mut self, addrs: Range<usize>, access: Access) {
+        if self.is_empty() {
+            return;
+        }
+
+        for addr in addrs {
+            // Assumes each access takes a cycle, near enough to find
+            // where in a frame a contended access lands
+            let cycle = self.cycle + self.accesses + self.pending;
+            self.accesses += 1;
+
+            for (r, w) in &self.waits {
+                if r.contains(&addr) {
+                    self.pending += w.for_access(access)
+                }
+            }
+
+            for c in &mut self.contention {
+                if c.range.contains(&addr) {
+                    self.pending += (c.f)(addr, access, cycle)
+                }
+            }
+        }
+    }
+
+    /// Stall cycles since last asked
+    pub fn take(&mut self) -> usize {
+        std::mem::take(&mut self.pending)
+    }
+}
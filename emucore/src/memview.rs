@@ -0,0 +1,554 @@
+use crate::mem::{MemErrorTypes, MemoryIO};
+use grl_symbols::{ScopeIdTraits, SymIdTraits, SymbolTree};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ViewErr {
+    #[error("{0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Layout file: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("Layout file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("No layout called {0}")]
+    UnknownLayout(String),
+    #[error("No symbol called {0}")]
+    UnknownSymbol(String),
+    #[error("No struct called {0} in the symbols")]
+    UnknownStruct(String),
+    #[error(transparent)]
+    Memory(#[from] MemErrorTypes),
+}
+
+pub type ViewResult<T> = Result<T, ViewErr>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    Byte,
+    Word,
+    SByte,
+    SWord,
+    /// A word holding an address
+    Pointer,
+}
+
+impl FieldKind {
+    pub fn size(&self) -> usize {
+        match self {
+            FieldKind::Byte | FieldKind::SByte => 1,
+            _ => 2,
+        }
+    }
+
+    fn read(&self, mem: &dyn MemoryIO, addr: usize) -> ViewResult<i64> {
+        let v = match self {
+            FieldKind::Byte => mem.inspect_byte(addr)? as i64,
+            FieldKind::SByte => mem.inspect_byte(addr)? as i8 as i64,
+            FieldKind::Word | FieldKind::Pointer => mem.inspect_word(addr)? as i64,
+            FieldKind::SWord => mem.inspect_word(addr)? as i16 as i64,
+        };
+        Ok(v)
+    }
+
+    fn format(&self, v: i64) -> String {
+        match self {
+            FieldKind::Byte => format!("${v:02x}"),
+            FieldKind::Word | FieldKind::Pointer => format!("${v:04x}"),
+            FieldKind::SByte | FieldKind::SWord => v.to_string(),
+        }
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldDef {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    /// Follows the previous field if not given
+    #[serde(default)]
+    pub offset: Option<usize>,
+    /// More than one makes an array
+    #[serde(default = "one")]
+    pub count: usize,
+    /// Layout a pointer points at
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+impl FieldDef {
+    pub fn new(name: &str, kind: FieldKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            offset: None,
+            count: 1,
+            to: None,
+        }
+    }
+
+    pub fn array(name: &str, kind: FieldKind, count: usize) -> Self {
+        Self {
+            count,
+            ..Self::new(name, kind)
+        }
+    }
+}
+
+/// A structure as named fields
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layout {
+    pub name: String,
+    pub fields: Vec<FieldDef>,
+    /// Stride between structures in a table, the end of the last field if not given
+    #[serde(default)]
+    pub size: Option<usize>,
+}
+
+impl Layout {
+    /// Fields with their offsets worked out
+    pub fn offsets(&self) -> Vec<(usize, &FieldDef)> {
+        let mut next = 0;
+
+        self.fields
+            .iter()
+            .map(|f| {
+                let offset = f.offset.unwrap_or(next);
+                next = offset + f.kind.size() * f.count;
+                (offset, f)
+            })
+            .collect()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size.unwrap_or_else(|| {
+            self.offsets()
+                .iter()
+                .map(|(o, f)| o + f.kind.size() * f.count)
+                .max()
+                .unwrap_or(0)
+        })
+    }
+
+    /// From an assembler struct, a scope whose symbols are field offsets
+    /// Fields are typed by the gap to the next one, 1 is a byte, 2 a word and
+    /// anything else a byte array
+    /// A size symbol in the scope gives the size of the struct
+    pub fn from_symbols<SCOPEID, SYMID>(
+        syms: &SymbolTree<SCOPEID, SYMID, i64>,
+        name: &str,
+    ) -> ViewResult<Self>
+    where
+        SCOPEID: ScopeIdTraits,
+        SYMID: SymIdTraits,
+    {
+        let prefix = format!("::{name}::");
+
+        let mut offsets: Vec<(usize, String)> = syms
+            .symbols()
+            .filter_map(|si| {
+                let field = si.scoped_name().strip_prefix(&prefix)?;
+                let v = si.value?;
+                (!field.contains("::")).then(|| (v as usize, field.to_string()))
+            })
+            .collect();
+
+        if offsets.is_empty() {
+            return Err(ViewErr::UnknownStruct(name.to_string()));
+        }
+
+        let size = offsets
+            .iter()
+            .position(|(_, n)| n == "size")
+            .map(|i| offsets.remove(i).0);
+
+        offsets.sort();
+
+        let fields = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, (offset, name))| {
+                let end = offsets.get(i + 1).map(|(o, _)| *o).or(size);
+                let gap = end.map_or(1, |e| e.saturating_sub(*offset));
+
+                let mut f = match gap {
+                    2 => FieldDef::new(name, FieldKind::Word),
+                    0 | 1 => FieldDef::new(name, FieldKind::Byte),
+                    n => FieldDef::array(name, FieldKind::Byte, n),
+                };
+                f.offset = Some(*offset);
+                f
+            })
+            .collect();
+
+        Ok(Self {
+            name: name.to_string(),
+            fields,
+            size,
+        })
+    }
+}
+
+/// Where a view sits, in YAML a number is an address and text a symbol
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Location {
+    Addr(usize),
+    Symbol(String),
+}
+
+/// A layout attached to memory, count > 1 for a table of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewDef {
+    pub name: String,
+    pub layout: String,
+    pub at: Location,
+    #[serde(default = "one")]
+    pub count: usize,
+}
+
+/// What a layout file holds
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewFile {
+    #[serde(default)]
+    pub layouts: Vec<Layout>,
+    #[serde(default)]
+    pub views: Vec<ViewDef>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldValue {
+    pub name: String,
+    pub addr: usize,
+    pub kind: FieldKind,
+    pub values: Vec<i64>,
+    pub to: Option<String>,
+    /// Differs from the last refresh
+    pub changed: bool,
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vals: Vec<_> = self.values.iter().map(|v| self.kind.format(*v)).collect();
+        let mark = if self.changed { "*" } else { " " };
+
+        write!(f, "{mark} {:<12} = ", self.name)?;
+
+        if vals.len() == 1 {
+            write!(f, "{}", vals[0])?;
+        } else {
+            write!(f, "[{}]", vals.join(", "))?;
+        }
+
+        if let Some(to) = &self.to {
+            write!(f, " -> {to}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One structure read from memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructValue {
+    /// View name, with an index for tables
+    pub name: String,
+    pub layout: String,
+    pub addr: usize,
+    pub fields: Vec<FieldValue>,
+}
+
+impl fmt::Display for StructValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} @ ${:04x} ({})", self.name, self.addr, self.layout)?;
+
+        for field in &self.fields {
+            writeln!(f, "  {field}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Layouts attached to memory, rendered as structures
+#[derive(Debug, Clone, Default)]
+pub struct MemViews {
+    layouts: BTreeMap<String, Layout>,
+    views: Vec<ViewDef>,
+    symbols: HashMap<String, i64>,
+    current: Vec<StructValue>,
+}
+
+impl MemViews {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file(file: ViewFile) -> Self {
+        let mut ret = Self::new();
+        for l in file.layouts {
+            ret.add_layout(l)
+        }
+        ret.views = file.views;
+        ret
+    }
+
+    pub fn from_yaml(txt: &str) -> ViewResult<Self> {
+        Ok(Self::from_file(serde_yaml::from_str(txt)?))
+    }
+
+    pub fn from_json(txt: &str) -> ViewResult<Self> {
+        Ok(Self::from_file(serde_json::from_str(txt)?))
+    }
+
+    /// JSON if the extension is .json, otherwise YAML
+    pub fn load<P: AsRef<Path>>(path: P) -> ViewResult<Self> {
+        let path = path.as_ref();
+        let txt = std::fs::read_to_string(path).map_err(|e| ViewErr::Io(path.to_path_buf(), e))?;
+
+        if path.extension().is_some_and(|e| e == "json") {
+            Self::from_json(&txt)
+        } else {
+            Self::from_yaml(&txt)
+        }
+    }
+
+    /// Symbols views can be attached to
+    pub fn with_symbol_tree<SCOPEID, SYMID>(
+        mut self,
+        syms: &SymbolTree<SCOPEID, SYMID, i64>,
+    ) -> Self
+    where
+        SCOPEID: ScopeIdTraits,
+        SYMID: SymIdTraits,
+    {
+        for si in syms.symbols() {
+            if let Some(v) = si.value {
+                self.add_symbol(si.scoped_name().trim_start_matches("::"), v)
+            }
+        }
+        self
+    }
+
+    pub fn add_symbol(&mut self, name: &str, value: i64) {
+        self.symbols.insert(name.to_string(), value);
+    }
+
+    pub fn add_layout(&mut self, layout: Layout) {
+        self.layouts.insert(layout.name.clone(), layout);
+    }
+
+    pub fn layout(&self, name: &str) -> Option<&Layout> {
+        self.layouts.get(name)
+    }
+
+    pub fn attach(
+        &mut self,
+        name: &str,
+        layout: &str,
+        at: Location,
+        count: usize,
+    ) -> ViewResult<()> {
+        if !self.layouts.contains_key(layout) {
+            return Err(ViewErr::UnknownLayout(layout.to_string()));
+        }
+
+        self.views.push(ViewDef {
+            name: name.to_string(),
+            layout: layout.to_string(),
+            at,
+            count,
+        });
+        Ok(())
+    }
+
+    pub fn detach(&mut self, name: &str) {
+        self.views.retain(|v| v.name != name);
+        self.current
+            .retain(|s| s.name != name && !s.name.starts_with(&format!("{name}[")));
+    }
+
+    fn addr(&self, at: &Location) -> ViewResult<usize> {
+        match at {
+            Location::Addr(a) => Ok(*a),
+            Location::Symbol(s) => self
+                .symbols
+                .get(s)
+                .map(|v| *v as usize)
+                .ok_or_else(|| ViewErr::UnknownSymbol(s.clone())),
+        }
+    }
+
+    /// Read one structure at an address
+    pub fn read(
+        &self,
+        mem: &dyn MemoryIO,
+        layout: &str,
+        name: &str,
+        addr: usize,
+    ) -> ViewResult<StructValue> {
+        let l = self
+            .layouts
+            .get(layout)
+            .ok_or_else(|| ViewErr::UnknownLayout(layout.to_string()))?;
+
+        let fields = l
+            .offsets()
+            .into_iter()
+            .map(|(offset, f)| {
+                let addr = addr + offset;
+                let values = (0..f.count)
+                    .map(|i| f.kind.read(mem, addr + i * f.kind.size()))
+                    .collect::<ViewResult<_>>()?;
+
+                Ok(FieldValue {
+                    name: f.name.clone(),
+                    addr,
+                    kind: f.kind,
+                    values,
+                    to: f.to.clone(),
+                    changed: false,
+                })
+            })
+            .collect::<ViewResult<_>>()?;
+
+        Ok(StructValue {
+            name: name.to_string(),
+            layout: layout.to_string(),
+            addr,
+            fields,
+        })
+    }
+
+    /// Every attached view as structures
+    pub fn render(&self, mem: &dyn MemoryIO) -> ViewResult<Vec<StructValue>> {
+        let mut ret = vec![];
+
+        for v in &self.views {
+            let base = self.addr(&v.at)?;
+            let size = self.layouts.get(&v.layout).map_or(0, |l| l.size());
+
+            for i in 0..v.count {
+                let name = if v.count == 1 {
+                    v.name.clone()
+                } else {
+                    format!("{}[{i}]", v.name)
+                };
+                ret.push(self.read(mem, &v.layout, &name, base + i * size)?);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Render again, marking fields that changed since the last refresh
+    /// Call between steps
+    pub fn refresh(&mut self, mem: &dyn MemoryIO) -> ViewResult<&[StructValue]> {
+        let mut new = self.render(mem)?;
+
+        for s in new.iter_mut() {
+            let old = self
+                .current
+                .iter()
+                .find(|o| o.name == s.name && o.addr == s.addr);
+
+            if let Some(old) = old {
+                for (f, of) in s.fields.iter_mut().zip(old.fields.iter()) {
+                    f.changed = f.values != of.values;
+                }
+            }
+        }
+
+        self.current = new;
+        Ok(&self.current)
+    }
+
+    pub fn current(&self) -> &[StructValue] {
+        &self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemBlock;
+
+    const LAYOUT: &str = "
+layouts:
+  - name: enemy
+    size: 8
+    fields:
+      - { name: x, type: word }
+      - { name: hp, type: sbyte }
+      - { name: flags, type: byte, count: 3 }
+      - { name: next, type: pointer, to: enemy, offset: 6 }
+views:
+  - { name: enemies, layout: enemy, at: enemy_table, count: 2 }
+";
+
+    #[test]
+    fn table() {
+        let mut mem = MemBlock::<byteorder::BigEndian>::new("ram", false, &(0xc800..0xc900));
+        mem.upload(0xc880, &[0x01, 0x20, 0xfd, 1, 2, 3, 0xc8, 0x88])
+            .unwrap();
+
+        let mut views = MemViews::from_yaml(LAYOUT).unwrap();
+        views.add_symbol("enemy_table", 0xc880);
+
+        let s = views.refresh(&mem).unwrap();
+        assert_eq!(s.len(), 2);
+        assert_eq!(s[1].addr, 0xc888);
+        assert_eq!(s[0].fields[1].values, vec![-3]);
+        assert_eq!(
+            s[0].to_string(),
+            "enemies[0] @ $c880 (enemy)
+    x            = $0120
+    hp           = -3
+    flags        = [$01, $02, $03]
+    next         = $c888 -> enemy
+"
+        );
+
+        mem.store_byte(0xc883, 9).unwrap();
+        let s = views.refresh(&mem).unwrap();
+        let changed: Vec<_> = s[0].fields.iter().map(|f| f.changed).collect();
+        assert_eq!(changed, vec![false, false, true, false]);
+
+        let json = r#"{ "layouts": [ { "name": "pt", "fields": [ { "name": "x", "type": "sword" } ] } ] }"#;
+        let views = MemViews::from_json(json).unwrap();
+        assert_eq!(views.layout("pt").unwrap().size(), 2);
+    }
+
+    #[test]
+    fn from_symbols() {
+        let mut st: SymbolTree<u64, u64, i64> = SymbolTree::new();
+        let mut w = st.get_root_writer();
+        w.create_or_set_scope("enemy");
+        for (n, v) in [("x", 0), ("hp", 2), ("name", 3), ("size", 7)] {
+            w.create_and_set_symbol(n, v).unwrap();
+        }
+
+        let l = Layout::from_symbols(&st, "enemy").unwrap();
+        assert_eq!(l.size(), 7);
+        let fields: Vec<_> = l
+            .offsets()
+            .iter()
+            .map(|(o, f)| (*o, f.kind, f.count))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                (0, FieldKind::Word, 1),
+                (2, FieldKind::Byte, 1),
+                (3, FieldKind::Byte, 4)
+            ]
+        );
+        assert!(Layout::from_symbols(&st, "player").is_err());
+    }
+}
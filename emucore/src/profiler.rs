@@ -0,0 +1,289 @@
+use crate::flow::Flow;
+use crate::symbols::SymbolMap;
+use std::collections::HashMap;
+
+/// A node in the call graph
+/// Every distinct call path gets its own node
+#[derive(Debug, Clone)]
+struct CallNode {
+    routine: usize,
+    parent: Option<usize>,
+    children: HashMap<usize, usize>,
+    cycles: u64,
+    calls: u64,
+}
+
+impl CallNode {
+    fn new(routine: usize, parent: Option<usize>) -> Self {
+        Self {
+            routine,
+            parent,
+            children: Default::default(),
+            cycles: 0,
+            calls: 0,
+        }
+    }
+}
+
+/// Cycle totals for one routine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileEntry {
+    pub addr: usize,
+    pub name: String,
+    pub calls: u64,
+    /// Cycles spent in this routine only
+    pub exclusive: u64,
+    /// Cycles spent in this routine and everything it called
+    pub inclusive: u64,
+}
+
+/// Attributes cycles to the pc and the routine that used them
+/// Feed it every step along with the flow the core recorded
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    nodes: Vec<CallNode>,
+    current: usize,
+    pc_cycles: HashMap<usize, u64>,
+    total_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default()
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Record a step
+    /// pc is the address of the instruction executed
+    /// Interrupt entry cycles are charged to the handler
+    pub fn add_step(&mut self, pc: usize, cycles: usize, flow: Flow) {
+        if self.nodes.is_empty() {
+            self.nodes.push(CallNode::new(pc, None));
+            self.current = 0;
+        }
+
+        if let Flow::Interrupt { dest, .. } = flow {
+            self.enter(dest);
+        }
+
+        let cycles = cycles as u64;
+        *self.pc_cycles.entry(pc).or_default() += cycles;
+        self.nodes[self.current].cycles += cycles;
+        self.total_cycles += cycles;
+
+        match flow {
+            Flow::Call { dest, .. } => self.enter(dest),
+            Flow::Return { .. } | Flow::ReturnFromInterrupt { .. } => self.leave(),
+            Flow::Normal | Flow::Interrupt { .. } => (),
+        }
+    }
+
+    fn enter(&mut self, routine: usize) {
+        let next_id = self.nodes.len();
+        let current = self.current;
+
+        let id = *self.nodes[current]
+            .children
+            .entry(routine)
+            .or_insert(next_id);
+
+        if id == next_id {
+            self.nodes.push(CallNode::new(routine, Some(current)));
+        }
+
+        self.nodes[id].calls += 1;
+        self.current = id;
+    }
+
+    fn leave(&mut self) {
+        // Returning past where we started profiling leaves us at the root
+        if let Some(parent) = self.nodes[self.current].parent {
+            self.current = parent
+        }
+    }
+
+    /// Cycles spent per instruction address, highest first
+    pub fn pc_cycles(&self) -> Vec<(usize, u64)> {
+        let mut ret: Vec<_> = self.pc_cycles.iter().map(|(a, c)| (*a, *c)).collect();
+        ret.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ret
+    }
+
+    fn node_totals(&self) -> Vec<u64> {
+        let mut totals: Vec<u64> = self.nodes.iter().map(|n| n.cycles).collect();
+
+        // Children are always created after their parents
+        for i in (0..self.nodes.len()).rev() {
+            if let Some(p) = self.nodes[i].parent {
+                totals[p] += totals[i];
+            }
+        }
+        totals
+    }
+
+    fn is_recursive(&self, id: usize) -> bool {
+        let routine = self.nodes[id].routine;
+        let mut parent = self.nodes[id].parent;
+
+        while let Some(p) = parent {
+            if self.nodes[p].routine == routine {
+                return true;
+            }
+            parent = self.nodes[p].parent;
+        }
+        false
+    }
+
+    /// Per routine totals, highest inclusive first
+    pub fn report(&self, syms: &SymbolMap) -> Vec<ProfileEntry> {
+        let totals = self.node_totals();
+        let mut by_routine: HashMap<usize, ProfileEntry> = HashMap::new();
+
+        for (id, n) in self.nodes.iter().enumerate() {
+            let e = by_routine.entry(n.routine).or_insert_with(|| ProfileEntry {
+                addr: n.routine,
+                name: syms.describe(n.routine),
+                calls: 0,
+                exclusive: 0,
+                inclusive: 0,
+            });
+
+            e.calls += n.calls;
+            e.exclusive += n.cycles;
+
+            // Recursive calls are already inside an outer frame's total
+            if !self.is_recursive(id) {
+                e.inclusive += totals[id];
+            }
+        }
+
+        let mut ret: Vec<_> = by_routine.into_values().collect();
+        ret.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(a.addr.cmp(&b.addr)));
+        ret
+    }
+
+    /// Flat table of the report
+    pub fn table(&self, syms: &SymbolMap) -> String {
+        let mut lines = vec![format!(
+            "{:>10} {:>6} {:>10} {:>6} {:>8}  name",
+            "inclusive", "%", "exclusive", "%", "calls"
+        )];
+
+        let pc = |c: u64| {
+            if self.total_cycles == 0 {
+                0.0
+            } else {
+                (c as f64 * 100.0) / self.total_cycles as f64
+            }
+        };
+
+        for e in self.report(syms) {
+            lines.push(format!(
+                "{:>10} {:>6.2} {:>10} {:>6.2} {:>8}  {}",
+                e.inclusive,
+                pc(e.inclusive),
+                e.exclusive,
+                pc(e.exclusive),
+                e.calls,
+                e.name
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    fn stack_names(&self, id: usize, syms: &SymbolMap) -> String {
+        let mut names = vec![];
+        let mut node = Some(id);
+
+        while let Some(n) = node {
+            names.push(syms.describe(self.nodes[n].routine));
+            node = self.nodes[n].parent;
+        }
+
+        names.reverse();
+        names.join(";")
+    }
+
+    /// Folded stacks, one line per call path with its exclusive cycles
+    /// Suitable for flamegraph.pl, inferno and speedscope
+    pub fn folded_stacks(&self, syms: &SymbolMap) -> String {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.cycles > 0)
+            .map(|(id, n)| format!("{} {}", self.stack_names(id, syms), n.cycles))
+            .collect();
+
+        lines.sort();
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(dest: usize, ret: usize) -> Flow {
+        Flow::Call { dest, ret }
+    }
+
+    fn ret(dest: usize) -> Flow {
+        Flow::Return { dest }
+    }
+
+    #[test]
+    fn inclusive_and_exclusive() {
+        let mut p = Profiler::new();
+        let mut syms = SymbolMap::new();
+        syms.add(0x1000, "main");
+        syms.add(0x2000, "sub");
+        syms.add(0x3000, "leaf");
+
+        p.add_step(0x1000, 2, Flow::Normal);
+        p.add_step(0x1002, 6, call(0x2000, 0x1005));
+        p.add_step(0x2000, 4, call(0x3000, 0x2003));
+        p.add_step(0x3000, 3, Flow::Normal);
+        p.add_step(0x3001, 5, ret(0x2003));
+        p.add_step(0x2003, 5, ret(0x1005));
+        p.add_step(0x1005, 2, Flow::Normal);
+
+        let r = p.report(&syms);
+        let get = |n: &str| r.iter().find(|e| e.name == n).unwrap().clone();
+
+        assert_eq!(p.total_cycles(), 27);
+        assert_eq!(get("main").inclusive, 27);
+        assert_eq!(get("main").exclusive, 10);
+        assert_eq!(get("sub").inclusive, 17);
+        assert_eq!(get("sub").exclusive, 9);
+        assert_eq!(get("sub").calls, 1);
+        assert_eq!(get("leaf").inclusive, 8);
+
+        let folded = p.folded_stacks(&syms);
+        assert_eq!(folded, "main 10\nmain;sub 9\nmain;sub;leaf 8");
+    }
+
+    #[test]
+    fn recursion_not_counted_twice() {
+        let mut p = Profiler::new();
+
+        p.add_step(0x1000, 1, call(0x2000, 0x1003));
+        p.add_step(0x2000, 1, call(0x2000, 0x2003));
+        p.add_step(0x2000, 1, ret(0x2003));
+        p.add_step(0x2003, 1, ret(0x1003));
+
+        let r = p.report(&SymbolMap::new());
+        let sub = r.iter().find(|e| e.addr == 0x2000).unwrap();
+        assert_eq!(sub.inclusive, 3);
+        assert_eq!(sub.exclusive, 3);
+        assert_eq!(sub.calls, 2);
+    }
+}
@@ -0,0 +1,435 @@
+use crate::golden::GoldenTarget;
+use crate::lockstep::Describe;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TraceErr {
+    #[error("{0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Trace format: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("Line {line}: {msg}")]
+    Parse { line: usize, msg: String },
+    #[error("Step failed at trace line {line}: {msg}")]
+    Step { line: usize, msg: String },
+    #[error("{0}")]
+    Mismatch(Box<TraceMismatch>),
+}
+
+pub type TraceResult<T> = Result<T, TraceErr>;
+
+/// Where a value sits on a trace line
+/// In YAML a number is an index and text a tag
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Field {
+    /// Nth whitespace or comma separated token
+    Index(usize),
+    /// Token written as TAG:value or TAG=value, any case
+    Tag(String),
+}
+
+fn default_radix() -> u32 {
+    16
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+    /// pc, cycles or a register name as the machine reports it
+    pub name: String,
+    pub field: Field,
+    #[serde(default = "default_radix")]
+    pub radix: u32,
+    /// Flag names msb first, used to say which flags differ
+    #[serde(default)]
+    pub flags: Option<String>,
+}
+
+/// How to read a text trace, one instruction per line
+/// Each line is the machine state before the instruction runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceFormat {
+    /// Lines starting with this are skipped
+    #[serde(default)]
+    pub comment: Option<String>,
+    pub columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Line number in the trace, from 1
+    pub line: usize,
+    pub pc: usize,
+    pub cycles: Option<usize>,
+    pub regs: BTreeMap<String, u64>,
+}
+
+impl TraceFormat {
+    pub fn from_yaml(txt: &str) -> TraceResult<Self> {
+        Ok(serde_yaml::from_str(txt)?)
+    }
+
+    /// Tagged tokens for each name, eg "PC:c000 A:00 CC=50"
+    pub fn tagged(names: &[&str]) -> Self {
+        let columns = names
+            .iter()
+            .map(|n| Column {
+                name: n.to_lowercase(),
+                field: Field::Tag(n.to_string()),
+                radix: if *n == "cycles" { 10 } else { 16 },
+                flags: None,
+            })
+            .collect();
+
+        Self {
+            comment: None,
+            columns,
+        }
+    }
+
+    /// Name the flags of a column, msb first
+    pub fn with_flags(mut self, column: &str, flags: &str) -> Self {
+        for c in self.columns.iter_mut().filter(|c| c.name == column) {
+            c.flags = Some(flags.to_string())
+        }
+        self
+    }
+
+    fn field<'a>(tokens: &[&'a str], field: &Field) -> Option<&'a str> {
+        match field {
+            Field::Index(i) => tokens.get(*i).copied(),
+            Field::Tag(tag) => tokens.iter().enumerate().find_map(|(i, t)| {
+                if !t.get(..tag.len())?.eq_ignore_ascii_case(tag) {
+                    return None;
+                }
+                let rest = t[tag.len()..].strip_prefix([':', '='])?;
+                // Allow "A: 12"
+                if rest.is_empty() {
+                    tokens.get(i + 1).copied()
+                } else {
+                    Some(rest)
+                }
+            }),
+        }
+    }
+
+    fn parse_value(txt: &str, radix: u32) -> Option<u64> {
+        let txt = txt
+            .trim_start_matches('$')
+            .trim_start_matches("0x")
+            .trim_start_matches("0X");
+        u64::from_str_radix(txt, radix).ok()
+    }
+
+    /// None for blank lines and comments
+    pub fn parse_line(&self, line: usize, text: &str) -> TraceResult<Option<TraceEntry>> {
+        let text = text.trim();
+
+        let is_comment = self.comment.as_ref().is_some_and(|c| text.starts_with(c));
+        if text.is_empty() || is_comment {
+            return Ok(None);
+        }
+
+        let tokens: Vec<_> = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        let err = |msg: String| TraceErr::Parse { line, msg };
+
+        let mut pc = None;
+        let mut cycles = None;
+        let mut regs = BTreeMap::new();
+
+        for c in &self.columns {
+            let txt = Self::field(&tokens, &c.field)
+                .ok_or_else(|| err(format!("no {} in {text:?}", c.name)))?;
+            let v = Self::parse_value(txt, c.radix)
+                .ok_or_else(|| err(format!("bad {} value {txt:?}", c.name)))?;
+
+            match c.name.as_str() {
+                "pc" => pc = Some(v as usize),
+                "cycles" => cycles = Some(v as usize),
+                _ => {
+                    regs.insert(c.name.clone(), v);
+                }
+            }
+        }
+
+        let pc = pc.ok_or_else(|| err("format has no pc column".into()))?;
+
+        Ok(Some(TraceEntry {
+            line,
+            pc,
+            cycles,
+            regs,
+        }))
+    }
+
+    pub fn read(&self, text: &str) -> TraceResult<Vec<TraceEntry>> {
+        let mut ret = vec![];
+
+        for (i, l) in text.lines().enumerate() {
+            if let Some(e) = self.parse_line(i + 1, l)? {
+                ret.push(e)
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> TraceResult<Vec<TraceEntry>> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|e| TraceErr::Io(path.to_path_buf(), e))?;
+        self.read(&text)
+    }
+}
+
+/// The first place the machine and the trace disagree
+#[derive(Debug, Clone)]
+pub struct TraceMismatch {
+    pub line: usize,
+    /// Instructions matched before this one
+    pub instruction: usize,
+    pub pc: usize,
+    pub field: String,
+    pub expected: u64,
+    pub actual: u64,
+    /// Which flags differ, if the field has named flags
+    pub flags: Option<String>,
+    /// Instructions before this one, oldest first
+    pub context: Vec<String>,
+    pub current: String,
+}
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Trace line {} (instruction {}): {} expected ${:x} got ${:x}",
+            self.line, self.instruction, self.field, self.expected, self.actual
+        )?;
+
+        if let Some(flags) = &self.flags {
+            writeln!(f, "  flags {flags}")?;
+        }
+
+        for c in &self.context {
+            writeln!(f, "    {c}")?;
+        }
+
+        writeln!(f, "  > {}", self.current)
+    }
+}
+
+/// Which named flags differ, eg "Z=1 (expected 0)"
+fn flag_diff(names: &str, expected: u64, actual: u64) -> String {
+    let n = names.len();
+
+    names
+        .chars()
+        .enumerate()
+        .filter_map(|(i, name)| {
+            let bit = 1 << (n - 1 - i);
+            let (e, a) = (expected & bit != 0, actual & bit != 0);
+            (e != a).then(|| format!("{name}={} (expected {})", a as u8, e as u8))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Runs a machine alongside a reference trace
+pub struct TraceChecker {
+    describe: Describe,
+    context: usize,
+    flags: BTreeMap<String, String>,
+}
+
+impl TraceChecker {
+    /// describe shows instructions, pass a core's disassembler
+    pub fn new(format: &TraceFormat, describe: Describe) -> Self {
+        let flags = format
+            .columns
+            .iter()
+            .filter_map(|c| c.flags.clone().map(|f| (c.name.clone(), f)))
+            .collect();
+
+        Self {
+            describe,
+            context: 8,
+            flags,
+        }
+    }
+
+    pub fn with_context(self, context: usize) -> Self {
+        Self { context, ..self }
+    }
+
+    fn describe<T: GoldenTarget>(&self, target: &T, pc: usize) -> String {
+        format!("${pc:04x}: {}", (self.describe)(target.mem(), pc))
+    }
+
+    /// Compare before each step, stopping at the first difference
+    /// Cycles are compared relative to the first line
+    /// Returns the number of instructions checked
+    pub fn check<T: GoldenTarget>(
+        &self,
+        target: &mut T,
+        trace: &[TraceEntry],
+    ) -> TraceResult<usize> {
+        let start_cycles = target.cycles();
+        let trace_start = trace.first().and_then(|e| e.cycles);
+        let mut history = VecDeque::with_capacity(self.context);
+
+        for (i, e) in trace.iter().enumerate() {
+            let regs = target.regs();
+            let pc = regs.get("pc").copied().unwrap_or(0) as usize;
+
+            let mismatch = |field: &str, expected: u64, actual: u64| {
+                let flags = self
+                    .flags
+                    .get(field)
+                    .map(|f| flag_diff(f, expected, actual));
+
+                TraceErr::Mismatch(Box::new(TraceMismatch {
+                    line: e.line,
+                    instruction: i,
+                    pc,
+                    field: field.to_string(),
+                    expected,
+                    actual,
+                    flags,
+                    context: history.iter().cloned().collect(),
+                    current: self.describe(target, pc),
+                }))
+            };
+
+            if pc != e.pc {
+                return Err(mismatch("pc", e.pc as u64, pc as u64));
+            }
+
+            for (name, expected) in &e.regs {
+                let actual = regs.get(name).copied().ok_or_else(|| TraceErr::Parse {
+                    line: e.line,
+                    msg: format!("machine has no register {name}"),
+                })?;
+
+                if actual != *expected {
+                    return Err(mismatch(name, *expected, actual));
+                }
+            }
+
+            if let (Some(c), Some(start)) = (e.cycles, trace_start) {
+                let expected = c.checked_sub(start);
+                let actual = target.cycles().checked_sub(start_cycles);
+
+                match (expected, actual) {
+                    (Some(e), Some(a)) if e != a => {
+                        return Err(mismatch("cycles", e as u64, a as u64))
+                    }
+                    (Some(_), Some(_)) => (),
+                    // Counts that run backwards or wrapped, show them as they are
+                    _ => return Err(mismatch("cycles", c as u64, target.cycles() as u64)),
+                }
+            }
+
+            if self.context != 0 {
+                if history.len() == self.context {
+                    history.pop_front();
+                }
+                history.push_back(self.describe(target, pc));
+            }
+
+            target.step().map_err(|err| TraceErr::Step {
+                line: e.line,
+                msg: err.to_string(),
+            })?;
+        }
+
+        Ok(trace.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        let fmt = TraceFormat::tagged(&["PC", "A", "CC", "cycles"]);
+        let e = fmt
+            .parse_line(3, "PC:C000 A=12  cc: $d4 LDA #$12 cycles:100")
+            .unwrap()
+            .unwrap();
+        assert_eq!(e.pc, 0xc000);
+        assert_eq!(e.cycles, Some(100));
+        assert_eq!(e.regs["a"], 0x12);
+        assert_eq!(e.regs["cc"], 0xd4);
+
+        let yaml = "
+comment: '#'
+columns:
+  - { name: pc, field: 0 }
+  - { name: x, field: 2 }
+";
+        let fmt = TraceFormat::from_yaml(yaml).unwrap();
+        let trace = fmt
+            .read("# header\n\n0100 nop 1234\n0101,ldx,0x0010\n")
+            .unwrap();
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[1].line, trace[1].regs["x"]), (4, 0x10));
+
+        assert!(matches!(
+            fmt.parse_line(1, "0100 nop"),
+            Err(TraceErr::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn check_against_machine() {
+        use crate::testmachine::Toy;
+
+        // add #1, add #1, nop
+        let toy = || Toy::new(&[(0, &[0x04, 0x01, 0x04, 0x01, 0x00])]);
+        let fmt = TraceFormat::tagged(&["PC", "ACC", "cycles"]);
+        let checker = TraceChecker::new(&fmt, Box::new(|_, _| "..".into()));
+
+        let good = fmt
+            .read("PC:00 ACC:00 cycles:100\nPC:02 ACC:01 cycles:102\n")
+            .unwrap();
+        assert_eq!(checker.check(&mut toy(), &good).unwrap(), 2);
+
+        let bad = fmt
+            .read("PC:00 ACC:00 cycles:100\nPC:02 ACC:01 cycles:102\nPC:04 ACC:03 cycles:104\n")
+            .unwrap();
+        let Err(TraceErr::Mismatch(m)) = checker.check(&mut toy(), &bad) else {
+            panic!("expected a mismatch")
+        };
+        assert_eq!((m.line, m.instruction, m.pc), (3, 2, 4));
+        assert_eq!((m.field.as_str(), m.expected, m.actual), ("acc", 3, 2));
+        assert_eq!(m.context, vec!["$0000: ..", "$0002: .."]);
+
+        // Cycles going backwards is a mismatch, not an overflow
+        let backwards = fmt
+            .read("PC:00 ACC:00 cycles:100\nPC:02 ACC:01 cycles:50\n")
+            .unwrap();
+        let Err(TraceErr::Mismatch(m)) = checker.check(&mut toy(), &backwards) else {
+            panic!("expected a mismatch")
+        };
+        assert_eq!((m.line, m.field.as_str()), (2, "cycles"));
+        assert_eq!((m.expected, m.actual), (50, 2));
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(
+            flag_diff("EFHINZVC", 0x84, 0x85),
+            "C=1 (expected 0)".to_string()
+        );
+    }
+}
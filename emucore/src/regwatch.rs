@@ -0,0 +1,226 @@
+use crate::traits::{RegEnumTrait, RegisterFileTrait};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// When a register watch triggers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegCondition {
+    Equal(u64),
+    NotEqual(u64),
+    /// Any change from the value seen on the previous check
+    Changed,
+    InRange(RangeInclusive<u64>),
+}
+
+impl RegCondition {
+    fn is_true(&self, old: Option<u64>, new: u64) -> bool {
+        match self {
+            Self::Equal(v) => new == *v,
+            Self::NotEqual(v) => new != *v,
+            Self::Changed => old.is_some_and(|o| o != new),
+            Self::InRange(r) => r.contains(&new),
+        }
+    }
+}
+
+impl fmt::Display for RegCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equal(v) => write!(f, "== ${v:x}"),
+            Self::NotEqual(v) => write!(f, "!= ${v:x}"),
+            Self::Changed => write!(f, "changed"),
+            Self::InRange(r) => write!(f, "in ${:x}-${:x}", r.start(), r.end()),
+        }
+    }
+}
+
+pub type RegWatchId = usize;
+
+#[derive(Debug, Clone)]
+struct RegWatch<R: RegEnumTrait> {
+    id: RegWatchId,
+    reg: R,
+    cond: RegCondition,
+    last: Option<u64>,
+    was_true: bool,
+}
+
+/// A register watch that triggered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegHit<R: RegEnumTrait> {
+    pub id: RegWatchId,
+    pub reg: R,
+    pub cond: RegCondition,
+    pub old: Option<u64>,
+    pub new: u64,
+    /// Address of the instruction that was stepped
+    pub pc: usize,
+}
+
+impl<R: RegEnumTrait> fmt::Display for RegHit<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${:04x}: {} {} ", self.pc, self.reg, self.cond)?;
+        match self.old {
+            Some(old) => write!(f, "(${old:x} -> ${:x})", self.new),
+            None => write!(f, "(${:x})", self.new),
+        }
+    }
+}
+
+/// Register watchpoints, checked after each step
+/// Conditions trigger when they become true, not on every step they hold
+#[derive(Debug, Clone)]
+pub struct RegWatches<R: RegEnumTrait> {
+    watches: Vec<RegWatch<R>>,
+    hits: Vec<RegHit<R>>,
+    next_id: RegWatchId,
+}
+
+impl<R: RegEnumTrait> Default for RegWatches<R> {
+    fn default() -> Self {
+        Self {
+            watches: vec![],
+            hits: vec![],
+            next_id: 0,
+        }
+    }
+}
+
+impl<R: RegEnumTrait> RegWatches<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn add(&mut self, reg: R, cond: RegCondition) -> RegWatchId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watches.push(RegWatch {
+            id,
+            reg,
+            cond,
+            last: None,
+            was_true: false,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: RegWatchId) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|w| w.id != id);
+        len != self.watches.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.watches.clear();
+        self.hits.clear();
+    }
+
+    /// Check every watch against the registers after stepping the instruction at pc
+    /// Returns true if any triggered
+    pub fn check<F: RegisterFileTrait<R>>(&mut self, regs: &F, pc: usize) -> bool {
+        let mut triggered = false;
+
+        for w in &mut self.watches {
+            let new = regs.get(&w.reg);
+            let is_true = w.cond.is_true(w.last, new);
+
+            if is_true && (!w.was_true || w.cond == RegCondition::Changed) {
+                triggered = true;
+                self.hits.push(RegHit {
+                    id: w.id,
+                    reg: w.reg.clone(),
+                    cond: w.cond.clone(),
+                    old: w.last,
+                    new,
+                    pc,
+                });
+            }
+
+            w.was_true = is_true;
+            w.last = Some(new);
+        }
+
+        triggered
+    }
+
+    pub fn hits(&self) -> &[RegHit<R>] {
+        &self.hits
+    }
+
+    pub fn take_hits(&mut self) -> Vec<RegHit<R>> {
+        std::mem::take(&mut self.hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+    enum Reg {
+        #[default]
+        A,
+        Dp,
+    }
+
+    impl fmt::Display for Reg {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    impl FromStr for Reg {
+        type Err = ();
+        fn from_str(_s: &str) -> Result<Self, ()> {
+            Err(())
+        }
+    }
+
+    impl RegEnumTrait for Reg {
+        fn get_size_bytes(&self) -> usize {
+            1
+        }
+    }
+
+    struct Regs([u64; 2]);
+
+    impl RegisterFileTrait<Reg> for Regs {
+        fn get(&self, r: &Reg) -> u64 {
+            self.0[*r as usize]
+        }
+        fn set(&mut self, r: &Reg, v: u64) {
+            self.0[*r as usize] = v
+        }
+    }
+
+    #[test]
+    fn triggers() {
+        let mut w = RegWatches::new();
+        let changed = w.add(Reg::A, RegCondition::Changed);
+        let dp = w.add(Reg::Dp, RegCondition::NotEqual(0xd0));
+        w.add(Reg::A, RegCondition::InRange(0x10..=0x1f));
+
+        let mut regs = Regs([0, 0xd0]);
+        assert!(!w.check(&regs, 0x100));
+
+        regs.set(&Reg::A, 0x12);
+        assert!(w.check(&regs, 0x102));
+        regs.set(&Reg::A, 0x13);
+        regs.set(&Reg::Dp, 0);
+        assert!(w.check(&regs, 0x104));
+        assert!(!w.check(&regs, 0x106));
+
+        let hits = w.take_hits();
+        let ids: Vec<_> = hits.iter().map(|h| (h.id, h.pc)).collect();
+        assert_eq!(
+            ids,
+            vec![(changed, 0x102), (2, 0x102), (changed, 0x104), (dp, 0x104)]
+        );
+        assert_eq!(hits[2].to_string(), "$0104: A changed ($12 -> $13)");
+    }
+}
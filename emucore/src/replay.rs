@@ -0,0 +1,322 @@
+use crate::golden::GoldenTarget;
+use crate::mem::MemoryIO;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+/// External input lines a host can drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Pin {
+    Irq,
+    Firq,
+    Nmi,
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputKind {
+    Pin {
+        pin: Pin,
+        level: bool,
+    },
+    /// A device read whose value came from the host
+    PortRead {
+        addr: usize,
+        value: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputEvent {
+    pub cycle: usize,
+    pub kind: InputKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateHash {
+    pub cycle: usize,
+    pub hash: String,
+}
+
+/// Everything needed to replay a run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputLog {
+    pub hash_interval: usize,
+    pub events: Vec<InputEvent>,
+    pub hashes: Vec<StateHash>,
+}
+
+impl InputLog {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(txt: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(txt)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReplayErr {
+    #[error("No recorded read of ${addr:04x} at cycle {cycle}")]
+    MissingPortRead { cycle: usize, addr: usize },
+    #[error("Read of ${addr:04x} at cycle {cycle}, recorded read of ${expected:04x} at cycle {expected_cycle}")]
+    PortReadMismatch {
+        cycle: usize,
+        addr: usize,
+        expected_cycle: usize,
+        expected: usize,
+    },
+    #[error("Runs diverged by cycle {cycle}, last matched at cycle {last_good:?}")]
+    Diverged {
+        cycle: usize,
+        last_good: Option<usize>,
+        expected: String,
+        actual: String,
+    },
+}
+
+pub type ReplayResult<T> = Result<T, ReplayErr>;
+
+/// A machine that can be driven by a replay
+pub trait ReplayTarget: GoldenTarget {
+    fn set_pin(&mut self, pin: Pin, level: bool);
+    /// Hash of everything that determines what happens next
+    fn state_hash(&self) -> String;
+}
+
+/// Sha1 of register bytes followed by memory
+pub fn hash_state<M: MemoryIO + ?Sized>(regs: &[u8], mem: &M) -> String {
+    let mut digest = Sha1::new();
+    digest.update(regs);
+    mem.update_sha1(&mut digest);
+    format!("{:x}", digest.finalize())
+}
+
+/// Records inputs as the host supplies them
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    log: InputLog,
+    next_hash: usize,
+}
+
+impl Recorder {
+    pub fn new(hash_interval: usize) -> Self {
+        Self {
+            log: InputLog {
+                hash_interval,
+                ..Default::default()
+            },
+            next_hash: 0,
+        }
+    }
+
+    pub fn set_pin<T: ReplayTarget>(&mut self, target: &mut T, pin: Pin, level: bool) {
+        self.log.events.push(InputEvent {
+            cycle: target.cycles(),
+            kind: InputKind::Pin { pin, level },
+        });
+        target.set_pin(pin, level)
+    }
+
+    /// Record the value a device got from the host
+    pub fn port_read(&mut self, cycle: usize, addr: usize, value: u8) -> u8 {
+        self.log.events.push(InputEvent {
+            cycle,
+            kind: InputKind::PortRead { addr, value },
+        });
+        value
+    }
+
+    /// Call after every step
+    pub fn after_step<T: ReplayTarget>(&mut self, target: &T) {
+        let cycle = target.cycles();
+
+        if self.log.hash_interval != 0 && cycle >= self.next_hash {
+            self.log.hashes.push(StateHash {
+                cycle,
+                hash: target.state_hash(),
+            });
+            self.next_hash = cycle + self.log.hash_interval;
+        }
+    }
+
+    pub fn log(&self) -> &InputLog {
+        &self.log
+    }
+
+    pub fn finish(self) -> InputLog {
+        self.log
+    }
+}
+
+/// Feeds a recorded log back in and checks the run matches
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    log: InputLog,
+    next_pin: usize,
+    next_read: usize,
+    next_hash: usize,
+    last_good: Option<usize>,
+}
+
+impl Replayer {
+    pub fn new(log: InputLog) -> Self {
+        Self {
+            log,
+            next_pin: 0,
+            next_read: 0,
+            next_hash: 0,
+            last_good: None,
+        }
+    }
+
+    /// Apply any pin changes due, call before every step
+    pub fn before_step<T: ReplayTarget>(&mut self, target: &mut T) {
+        let cycle = target.cycles();
+
+        while let Some(ev) = self.log.events.get(self.next_pin) {
+            match ev.kind {
+                InputKind::Pin { .. } if ev.cycle > cycle => break,
+                InputKind::Pin { pin, level } => target.set_pin(pin, level),
+                InputKind::PortRead { .. } => (),
+            }
+            self.next_pin += 1;
+        }
+    }
+
+    /// The recorded value for a device read
+    pub fn port_read(&mut self, cycle: usize, addr: usize) -> ReplayResult<u8> {
+        while let Some(ev) = self.log.events.get(self.next_read) {
+            self.next_read += 1;
+
+            if let InputKind::PortRead {
+                addr: expected,
+                value,
+            } = ev.kind
+            {
+                if expected != addr || ev.cycle != cycle {
+                    return Err(ReplayErr::PortReadMismatch {
+                        cycle,
+                        addr,
+                        expected_cycle: ev.cycle,
+                        expected,
+                    });
+                }
+                return Ok(value);
+            }
+        }
+
+        Err(ReplayErr::MissingPortRead { cycle, addr })
+    }
+
+    /// Check the state hash if one is due, call after every step
+    pub fn after_step<T: ReplayTarget>(&mut self, target: &T) -> ReplayResult<()> {
+        let cycle = target.cycles();
+
+        let Some(expected) = self.log.hashes.get(self.next_hash) else {
+            return Ok(());
+        };
+
+        if cycle < expected.cycle {
+            return Ok(());
+        }
+
+        let actual = if cycle == expected.cycle {
+            target.state_hash()
+        } else {
+            // Stepped past the recorded point so timing is already different
+            String::new()
+        };
+
+        if actual != expected.hash {
+            return Err(ReplayErr::Diverged {
+                cycle: expected.cycle,
+                last_good: self.last_good,
+                expected: expected.hash.clone(),
+                actual,
+            });
+        }
+
+        self.last_good = Some(cycle);
+        self.next_hash += 1;
+        Ok(())
+    }
+
+    /// True once every event and hash has been used
+    pub fn is_finished(&self) -> bool {
+        self.next_hash >= self.log.hashes.len()
+            && self.next_pin >= self.log.events.len()
+            && !self.log.events[self.next_read..]
+                .iter()
+                .any(|e| matches!(e.kind, InputKind::PortRead { .. }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testmachine::Toy;
+
+    /// Adds the input latch to acc, the irq handler adds one more
+    fn toy() -> Toy {
+        Toy::new(&[(0, &[0x0c; 0x20]), (0x80, &[0x04, 0x01, 0x03])])
+    }
+
+    fn record() -> InputLog {
+        let mut toy = toy();
+        let mut rec = Recorder::new(8);
+
+        for i in 0..16 {
+            if i == 5 {
+                rec.set_pin(&mut toy, Pin::Irq, true);
+            }
+            toy.input = rec.port_read(toy.cycles(), 0xc000, i as u8);
+            toy.step().unwrap();
+            rec.after_step(&toy);
+        }
+        rec.finish()
+    }
+
+    #[test]
+    fn replay_matches() {
+        let log = InputLog::from_json(&record().to_json().unwrap()).unwrap();
+        let mut toy = toy();
+        let mut rep = Replayer::new(log);
+
+        for _ in 0..16 {
+            rep.before_step(&mut toy);
+            toy.input = rep.port_read(toy.cycles(), 0xc000).unwrap();
+            toy.step().unwrap();
+            rep.after_step(&toy).unwrap();
+        }
+        assert!(rep.is_finished());
+    }
+
+    #[test]
+    fn replay_diverges() {
+        let mut toy = toy();
+        let mut rep = Replayer::new(record());
+
+        let res = (0..16).try_for_each(|i| {
+            rep.before_step(&mut toy);
+            toy.input = rep.port_read(toy.cycles(), 0xc000)?;
+            toy.step().unwrap();
+            if i == 9 {
+                // A stray write the recording didn't have
+                toy.mem.store_byte(0x40, 1).unwrap();
+            }
+            rep.after_step(&toy)
+        });
+
+        match res {
+            Err(ReplayErr::Diverged {
+                cycle, last_good, ..
+            }) => {
+                assert_eq!(cycle, 34);
+                assert_eq!(last_good, Some(26));
+            }
+            _ => panic!("expected divergence"),
+        }
+    }
+}
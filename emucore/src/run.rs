@@ -0,0 +1,419 @@
+use crate::address::{BankMap, Flat};
+use crate::breakpoints::{BreakPointTypes, BreakPoints};
+use crate::flow::Flow;
+use crate::golden::GoldenTarget;
+use crate::intsched::InterruptTarget;
+use crate::logpoint::Logpoints;
+use std::collections::BTreeMap;
+
+/// A machine run_until can drive
+pub trait RunTarget: GoldenTarget {
+    /// Address of the next instruction
+    fn pc(&self) -> usize;
+    /// Did a step fail on an illegal or unimplemented opcode
+    fn is_illegal_opcode(err: &Self::Err) -> bool;
+    /// Did a register watch trigger on the last step
+    fn reg_watch_hit(&self) -> bool {
+        false
+    }
+    /// How cpu addresses map to physical ones, for banked breakpoints
+    fn bank_map(&self) -> &dyn BankMap {
+        &Flat
+    }
+}
+
+/// A machine that reports calls and returns, for step over and step out
+pub trait StepTarget: RunTarget + InterruptTarget {
+    /// Flow of the last step
+    fn flow(&self) -> Flow;
+    /// Depth of the shadow call stack
+    fn call_depth(&self) -> usize;
+}
+
+/// Checked after every step, the first that holds stops the run
+pub enum Stop<T> {
+    /// About to execute this address
+    Pc(usize),
+    /// Total cycles reached
+    Cycles(usize),
+    /// Total instructions reached
+    Instructions(usize),
+    /// Byte at addr satisfies the predicate
+    Memory {
+        addr: usize,
+        pred: Box<dyn Fn(u8) -> bool>,
+    },
+    /// An exec breakpoint at the next instruction
+    Breakpoints(BreakPoints),
+    /// Stop instead of failing on a bad opcode
+    IllegalOpcode,
+    /// Any of the machine's register watches triggered
+    RegWatches,
+    /// Host decides
+    Host(Box<dyn FnMut(&T) -> bool>),
+}
+
+/// Why a run stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Pc(usize),
+    Cycles(usize),
+    Instructions(usize),
+    Memory {
+        addr: usize,
+        value: u8,
+    },
+    Breakpoint {
+        id: usize,
+        addr: usize,
+    },
+    IllegalOpcode {
+        pc: usize,
+        msg: String,
+    },
+    /// The machine has the register watch hits
+    RegWatch,
+    Host,
+    /// Step over or step out finished
+    Step,
+}
+
+/// Where the machine was when it stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stopped {
+    pub reason: StopReason,
+    /// Index of the condition that fired, None if a step finished
+    pub condition: Option<usize>,
+    pub pc: usize,
+    pub cycles: usize,
+    pub instructions: usize,
+    pub regs: BTreeMap<String, u64>,
+}
+
+/// A set of stop conditions built up with the methods below
+pub struct StopConditions<T> {
+    conds: Vec<Stop<T>>,
+    /// Checked before every step, they never stop the run
+    logpoints: Option<Logpoints>,
+}
+
+impl<T> Default for StopConditions<T> {
+    fn default() -> Self {
+        Self {
+            conds: vec![],
+            logpoints: None,
+        }
+    }
+}
+
+impl<T: RunTarget> StopConditions<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, stop: Stop<T>) -> Self {
+        self.conds.push(stop);
+        self
+    }
+
+    pub fn pc(self, addr: usize) -> Self {
+        self.with(Stop::Pc(addr))
+    }
+
+    pub fn cycles(self, cycles: usize) -> Self {
+        self.with(Stop::Cycles(cycles))
+    }
+
+    pub fn instructions(self, n: usize) -> Self {
+        self.with(Stop::Instructions(n))
+    }
+
+    pub fn memory<F: Fn(u8) -> bool + 'static>(self, addr: usize, pred: F) -> Self {
+        self.with(Stop::Memory {
+            addr,
+            pred: Box::new(pred),
+        })
+    }
+
+    pub fn breakpoints(self, bp: BreakPoints) -> Self {
+        self.with(Stop::Breakpoints(bp))
+    }
+
+    pub fn illegal_opcode(self) -> Self {
+        self.with(Stop::IllegalOpcode)
+    }
+
+    pub fn reg_watches(self) -> Self {
+        self.with(Stop::RegWatches)
+    }
+
+    pub fn host<F: FnMut(&T) -> bool + 'static>(self, f: F) -> Self {
+        self.with(Stop::Host(Box::new(f)))
+    }
+
+    /// Log as the run goes
+    pub fn logpoints(self, logpoints: Logpoints) -> Self {
+        Self {
+            logpoints: Some(logpoints),
+            ..self
+        }
+    }
+
+    pub fn logpoints_mut(&mut self) -> Option<&mut Logpoints> {
+        self.logpoints.as_mut()
+    }
+
+    fn check(&mut self, target: &T) -> Option<(usize, StopReason)> {
+        let pc = target.pc();
+
+        self.conds.iter_mut().enumerate().find_map(|(i, c)| {
+            let reason = match c {
+                Stop::Pc(addr) => (*addr == pc).then_some(StopReason::Pc(pc)),
+                Stop::Cycles(n) => {
+                    (target.cycles() >= *n).then(|| StopReason::Cycles(target.cycles()))
+                }
+                Stop::Instructions(n) => (target.instructions() >= *n)
+                    .then(|| StopReason::Instructions(target.instructions())),
+                Stop::Memory { addr, pred } => target
+                    .mem()
+                    .inspect_byte(*addr)
+                    .ok()
+                    .filter(|v| pred(*v))
+                    .map(|value| StopReason::Memory { addr: *addr, value }),
+                Stop::Breakpoints(bp) => bp
+                    .hits(pc, BreakPointTypes::EXEC, target.bank_map())
+                    .first()
+                    .map(|b| StopReason::Breakpoint {
+                        id: b.id(),
+                        addr: pc,
+                    }),
+                Stop::IllegalOpcode => None,
+                Stop::RegWatches => target.reg_watch_hit().then_some(StopReason::RegWatch),
+                Stop::Host(f) => f(target).then_some(StopReason::Host),
+            };
+            reason.map(|r| (i, r))
+        })
+    }
+
+    fn stopped(target: &T, condition: Option<usize>, reason: StopReason) -> Stopped {
+        Stopped {
+            reason,
+            condition,
+            pc: target.pc(),
+            cycles: target.cycles(),
+            instructions: target.instructions(),
+            regs: target.regs(),
+        }
+    }
+
+    /// Step until done says so or a condition holds
+    fn run_with<F: FnMut(&T) -> bool>(
+        &mut self,
+        target: &mut T,
+        mut done: F,
+    ) -> Result<Stopped, T::Err> {
+        let illegal = self
+            .conds
+            .iter()
+            .position(|c| matches!(c, Stop::IllegalOpcode));
+
+        loop {
+            let pc = target.pc();
+
+            if let Some(lp) = &mut self.logpoints {
+                lp.check(target, target.bank_map());
+            }
+
+            if let Err(e) = target.step() {
+                return match illegal {
+                    Some(i) if T::is_illegal_opcode(&e) => {
+                        let msg = e.to_string();
+                        Ok(Self::stopped(
+                            target,
+                            Some(i),
+                            StopReason::IllegalOpcode { pc, msg },
+                        ))
+                    }
+                    _ => Err(e),
+                };
+            }
+
+            if done(target) {
+                return Ok(Self::stopped(target, None, StopReason::Step));
+            }
+
+            if let Some((i, reason)) = self.check(target) {
+                return Ok(Self::stopped(target, Some(i), reason));
+            }
+        }
+    }
+
+    /// Step until a condition holds, always runs at least one instruction
+    /// Errors are returned unless they are bad opcodes and IllegalOpcode was asked for
+    pub fn run_until(&mut self, target: &mut T) -> Result<Stopped, T::Err> {
+        self.run_with(target, |_| false)
+    }
+
+    /// Run to the cursor with a temporary breakpoint, removed when the run stops
+    pub fn run_to(&mut self, target: &mut T, addr: usize) -> Result<Stopped, T::Err> {
+        self.conds.push(Stop::Pc(addr));
+        let ret = self.run_until(target);
+        self.conds.pop();
+        ret
+    }
+}
+
+impl<T: StepTarget> StopConditions<T> {
+    /// Run one instruction, a call or software interrupt runs until it returns
+    /// to this depth
+    /// Hardware interrupts taken on the way run through to their RTI
+    pub fn step_over(&mut self, target: &mut T) -> Result<Stopped, T::Err> {
+        let depth = target.call_depth();
+        let mut before = depth;
+        let mut executed = false;
+
+        self.run_with(target, |t| {
+            // Entering an interrupt isn't the instruction being stepped
+            if before <= depth && t.taken_interrupt().is_none() {
+                executed = true;
+            }
+            before = t.call_depth();
+            executed && before <= depth
+        })
+    }
+
+    /// Run until the current routine's RTS or RTI pops its frame
+    pub fn step_out(&mut self, target: &mut T) -> Result<Stopped, T::Err> {
+        let depth = target.call_depth();
+        let mut before = depth;
+
+        self.run_with(target, |t| {
+            let returned = before <= depth && t.flow().is_return();
+            before = t.call_depth();
+            returned || before < depth
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemoryIO;
+    use crate::testmachine::Toy;
+
+    #[test]
+    fn stop_conditions() {
+        // inc $20, four nops, jmp 0
+        let mut m = Toy::new(&[(0, &[0x08, 0x20, 0, 0, 0, 0, 0, 0x09, 0x00])]);
+
+        let mut bp = BreakPoints::new();
+        bp.add(0x5, BreakPointTypes::EXEC);
+
+        let mut conds = StopConditions::new()
+            .memory(0x20, |v| v == 2)
+            .breakpoints(bp)
+            .cycles(100);
+
+        let s = conds.run_until(&mut m).unwrap();
+        assert_eq!(s.reason, StopReason::Breakpoint { id: 0, addr: 5 });
+        assert_eq!((s.condition, s.instructions, s.cycles), (Some(1), 4, 9));
+
+        let s = conds.run_until(&mut m).unwrap();
+        assert_eq!(s.reason, StopReason::Memory { addr: 0x20, value: 2 });
+        assert_eq!(s.regs["pc"], 2);
+
+        m.mem.store_byte(0x4, 0xff).unwrap();
+        let mut conds = StopConditions::new().pc(0x7);
+        assert!(conds.run_until(&mut m).is_err());
+
+        let mut conds = StopConditions::new()
+            .host(|m: &Toy| m.acc == 1)
+            .illegal_opcode();
+        let s = conds.run_until(&mut m).unwrap();
+        assert!(matches!(s.reason, StopReason::IllegalOpcode { pc: 4, .. }));
+    }
+
+    #[test]
+    fn banked_breakpoints() {
+        use crate::address::Address;
+
+        // Eight nops in a window showing bank 1
+        let mut m = Toy::new(&[(0, &[0; 8])]);
+        m.banks.add_window("page", 0..0x100, 0x10000);
+        m.banks.select("page", 1);
+
+        let mut bp = BreakPoints::new();
+        bp.add(Address::banked(4, 0x10004), BreakPointTypes::EXEC);
+        bp.add(Address::banked(6, 0x10106), BreakPointTypes::EXEC);
+
+        let mut conds = StopConditions::new().breakpoints(bp).instructions(8);
+        let s = conds.run_until(&mut m).unwrap();
+        assert_eq!(s.reason, StopReason::Breakpoint { id: 1, addr: 6 });
+    }
+
+    #[test]
+    fn logpoints() {
+        use crate::logpoint::BufferSink;
+        use std::sync::{Arc, Mutex};
+
+        // add #1, jmp 0
+        let mut m = Toy::new(&[(0, &[0x04, 0x01, 0x09, 0x00])]);
+        let buf = Arc::new(Mutex::new(BufferSink::new()));
+        let mut lp = Logpoints::new(Box::new(buf.clone()));
+        lp.add(0x0, "acc={acc}").unwrap();
+
+        // Logged before the first step and each time round
+        let mut conds = StopConditions::new().logpoints(lp).instructions(5);
+        conds.run_until(&mut m).unwrap();
+
+        let lines = buf.lock().unwrap().take();
+        let text: Vec<_> = lines.iter().map(|l| (l.cycle, l.text.as_str())).collect();
+        assert_eq!(text, vec![(0, "acc=0"), (5, "acc=1"), (10, "acc=2")]);
+    }
+
+    fn calls() -> Toy {
+        Toy::new(&[
+            (0x00, &[0x00, 0x01, 0x10, 0x00]),
+            (0x10, &[0x00, 0x01, 0x20, 0x02]),
+            (0x20, &[0x02]),
+            (0x80, &[0x00, 0x03]),
+        ])
+    }
+
+    #[test]
+    fn stepping() {
+        let mut m = calls();
+        let mut conds = StopConditions::new();
+
+        // The interrupt runs to its rti, then the nop is stepped
+        m.irq = true;
+        let s = conds.step_over(&mut m).unwrap();
+        assert_eq!(
+            (s.reason, s.condition, s.pc),
+            (StopReason::Step, None, 0x01)
+        );
+        assert_eq!(s.instructions, 3);
+
+        let s = conds.step_over(&mut m).unwrap();
+        assert_eq!((s.pc, s.instructions), (0x03, 8));
+
+        let mut m = calls();
+        let s = conds.run_to(&mut m, 0x20).unwrap();
+        assert_eq!((s.reason, s.condition), (StopReason::Pc(0x20), Some(0)));
+        assert_eq!(m.call_depth(), 2);
+
+        let s = conds.step_out(&mut m).unwrap();
+        assert_eq!(s.pc, 0x13);
+        m.irq = true;
+        let s = conds.step_out(&mut m).unwrap();
+        assert_eq!((s.pc, m.call_depth()), (0x03, 0));
+
+        let mut bp = BreakPoints::new();
+        bp.add(0x20, BreakPointTypes::EXEC);
+        let mut conds = StopConditions::new().breakpoints(bp);
+        let mut m = calls();
+        conds.step_over(&mut m).unwrap();
+        let s = conds.step_over(&mut m).unwrap();
+        assert_eq!((s.condition, s.pc), (Some(0), 0x20));
+    }
+}
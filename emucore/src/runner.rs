@@ -0,0 +1,384 @@
+use crate::address::Address;
+use crate::breakpoints::{BreakPointTypes, BreakPoints};
+use crate::logpoint::Logpoints;
+use crate::run::RunTarget;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Instructions run between looks at the command channel
+const SLICE: usize = 1000;
+
+/// Sent from the UI to the runner thread
+pub enum Command<T> {
+    Run,
+    Pause,
+    /// Run this many instructions then pause
+    Step(usize),
+    /// Pass an Address with a physical part for a breakpoint on banked code
+    AddBreakpoint(Address),
+    RemoveBreakpoint(Address),
+    /// Replace the logpoints, checked before every instruction
+    SetLogpoints(Logpoints),
+    Poke {
+        addr: usize,
+        data: Vec<u8>,
+    },
+    /// Publish a snapshot now
+    Snapshot,
+    /// Anything else, run at an instruction boundary
+    With(Box<dyn FnOnce(&mut T) + Send>),
+    Quit,
+}
+
+/// Machine state at an instruction boundary
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    pub running: bool,
+    pub pc: usize,
+    pub cycles: usize,
+    pub instructions: usize,
+    pub regs: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PauseReason {
+    Paused,
+    Stepped,
+    Breakpoint { id: usize, addr: usize },
+    Error(String),
+}
+
+/// Sent from the runner thread to the UI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Snapshot(Snapshot),
+    Stopped {
+        reason: PauseReason,
+        snapshot: Snapshot,
+    },
+}
+
+/// The machine and what it's doing, lives on the runner thread
+struct Worker<T: RunTarget> {
+    machine: T,
+    breakpoints: BreakPoints,
+    logpoints: Option<Logpoints>,
+    /// None when free running
+    budget: Option<usize>,
+    running: bool,
+    events: Sender<Event>,
+    latest: Arc<Mutex<Snapshot>>,
+}
+
+impl<T: RunTarget> Worker<T> {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            running: self.running,
+            pc: self.machine.pc(),
+            cycles: self.machine.cycles(),
+            instructions: self.machine.instructions(),
+            regs: self.machine.regs(),
+        }
+    }
+
+    fn publish(&self) -> Snapshot {
+        let s = self.snapshot();
+        *self.latest.lock().unwrap() = s.clone();
+        s
+    }
+
+    fn stop(&mut self, reason: PauseReason) {
+        self.running = false;
+        self.budget = None;
+        let snapshot = self.publish();
+        let _ = self.events.send(Event::Stopped { reason, snapshot });
+    }
+
+    /// False to quit
+    fn handle(&mut self, cmd: Command<T>) -> bool {
+        match cmd {
+            Command::Run => {
+                self.running = true;
+                self.budget = None;
+            }
+            Command::Pause => {
+                if self.running {
+                    self.stop(PauseReason::Paused)
+                }
+            }
+            Command::Step(0) => self.stop(PauseReason::Stepped),
+            Command::Step(n) => {
+                self.running = true;
+                self.budget = Some(n);
+            }
+            Command::AddBreakpoint(addr) => {
+                self.breakpoints.add(addr, BreakPointTypes::EXEC);
+            }
+            Command::RemoveBreakpoint(addr) => self.breakpoints.remove(addr, BreakPointTypes::EXEC),
+            Command::SetLogpoints(lp) => self.logpoints = Some(lp),
+            Command::Poke { addr, data } => {
+                let mem = self.machine.mem_mut();
+                for (i, b) in data.iter().enumerate() {
+                    if let Err(e) = mem.store_byte(addr + i, *b) {
+                        log::error!("Poke ${:04x}: {e}", addr + i)
+                    }
+                }
+            }
+            Command::Snapshot => {
+                let s = self.publish();
+                let _ = self.events.send(Event::Snapshot(s));
+            }
+            Command::With(f) => f(&mut self.machine),
+            Command::Quit => return false,
+        }
+        true
+    }
+
+    /// Run up to a slice of instructions, stopping on breakpoints or errors
+    fn run_slice(&mut self) {
+        for _ in 0..SLICE {
+            if self.budget == Some(0) {
+                self.stop(PauseReason::Stepped);
+                return;
+            }
+
+            if let Some(lp) = &mut self.logpoints {
+                lp.check(&self.machine, self.machine.bank_map());
+            }
+
+            if let Err(e) = self.machine.step() {
+                self.stop(PauseReason::Error(e.to_string()));
+                return;
+            }
+
+            if let Some(n) = self.budget.as_mut() {
+                *n -= 1
+            }
+
+            let pc = self.machine.pc();
+
+            if let Some(bp) = self
+                .breakpoints
+                .hits(pc, BreakPointTypes::EXEC, self.machine.bank_map())
+                .first()
+            {
+                let reason = PauseReason::Breakpoint {
+                    id: bp.id(),
+                    addr: pc,
+                };
+                self.stop(reason);
+                return;
+            }
+        }
+
+        self.publish();
+    }
+
+    fn main(mut self, commands: Receiver<Command<T>>) {
+        self.publish();
+
+        loop {
+            // Block while paused, poll while running
+            let cmd = if self.running {
+                match commands.try_recv() {
+                    Ok(c) => Some(c),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match commands.recv() {
+                    Ok(c) => Some(c),
+                    Err(_) => break,
+                }
+            };
+
+            if let Some(cmd) = cmd {
+                if !self.handle(cmd) {
+                    break;
+                }
+            }
+
+            if self.running {
+                self.run_slice()
+            }
+        }
+    }
+}
+
+/// Owns a machine on its own thread and drives it with commands
+/// The machine is built and stays on that thread, so only the factory has to
+/// be Send, use Command::With to get at it
+pub struct Runner<T: RunTarget + 'static> {
+    commands: Sender<Command<T>>,
+    events: Receiver<Event>,
+    latest: Arc<Mutex<Snapshot>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T: RunTarget + 'static> Runner<T> {
+    /// Starts paused
+    pub fn spawn<F: FnOnce() -> T + Send + 'static>(make: F) -> Self {
+        let (commands, rx) = channel();
+        let (tx, events) = channel();
+        let latest = Arc::new(Mutex::new(Snapshot::default()));
+        let shared = latest.clone();
+
+        let thread = std::thread::spawn(move || {
+            let worker = Worker {
+                machine: make(),
+                breakpoints: BreakPoints::new(),
+                logpoints: None,
+                budget: None,
+                running: false,
+                events: tx,
+                latest: shared,
+            };
+            worker.main(rx)
+        });
+
+        Self {
+            commands,
+            events,
+            latest,
+            thread: Some(thread),
+        }
+    }
+
+    /// False if the runner thread has gone
+    pub fn send(&self, cmd: Command<T>) -> bool {
+        self.commands.send(cmd).is_ok()
+    }
+
+    pub fn run(&self) -> bool {
+        self.send(Command::Run)
+    }
+
+    pub fn pause(&self) -> bool {
+        self.send(Command::Pause)
+    }
+
+    pub fn step(&self, n: usize) -> bool {
+        self.send(Command::Step(n))
+    }
+
+    pub fn poke(&self, addr: usize, data: &[u8]) -> bool {
+        self.send(Command::Poke {
+            addr,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Snapshots and stop events
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    /// The last state published, updated every slice while running
+    pub fn latest(&self) -> Snapshot {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// Stop the thread, false if it had panicked
+    pub fn quit(mut self) -> bool {
+        self.send(Command::Quit);
+        self.thread.take().is_some_and(|t| t.join().is_ok())
+    }
+}
+
+impl<T: RunTarget + 'static> Drop for Runner<T> {
+    fn drop(&mut self) {
+        if let Some(t) = self.thread.take() {
+            self.send(Command::Quit);
+            let _ = t.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logpoint::BufferSink;
+    use crate::testmachine::Toy;
+    use std::time::Duration;
+
+    fn stopped(r: &Runner<Toy>) -> (PauseReason, Snapshot) {
+        loop {
+            match r.events().recv_timeout(Duration::from_secs(5)).unwrap() {
+                Event::Stopped { reason, snapshot } => return (reason, snapshot),
+                Event::Snapshot(_) => (),
+            }
+        }
+    }
+
+    #[test]
+    fn commands() {
+        // All nops round 256 bytes
+        let r = Runner::spawn(|| Toy::new(&[]));
+
+        // Nothing to run, but it still says it stopped
+        r.step(0);
+        let (reason, s) = stopped(&r);
+        assert_eq!((reason, s.pc), (PauseReason::Stepped, 0));
+
+        r.poke(0x10, &[0x04, 0x05]);
+        r.step(0x20);
+        let (reason, s) = stopped(&r);
+        assert_eq!(reason, PauseReason::Stepped);
+        assert_eq!((s.pc, s.regs["acc"], s.running), (0x21, 5, false));
+
+        r.send(Command::AddBreakpoint(0x08.into()));
+        r.run();
+        let (reason, s) = stopped(&r);
+        assert_eq!(reason, PauseReason::Breakpoint { id: 0, addr: 8 });
+        assert_eq!((s.instructions, s.regs["acc"]), (0x107, 5));
+        assert_eq!(r.latest(), s);
+
+        r.send(Command::RemoveBreakpoint(0x08.into()));
+        r.run();
+        r.pause();
+        let (reason, _) = stopped(&r);
+        assert_eq!(reason, PauseReason::Paused);
+
+        r.send(Command::With(Box::new(|m: &mut Toy| m.pc = 0x80)));
+        r.send(Command::Snapshot);
+        match r.events().recv_timeout(Duration::from_secs(5)).unwrap() {
+            Event::Snapshot(s) => assert_eq!(s.pc, 0x80),
+            e => panic!("expected a snapshot, got {e:?}"),
+        }
+
+        // From $80 round to $10 once
+        let buf = Arc::new(Mutex::new(BufferSink::new()));
+        let mut lp = Logpoints::new(Box::new(buf.clone()));
+        lp.add(0x10, "at {pc:02x}").unwrap();
+        r.send(Command::SetLogpoints(lp));
+        r.step(0x100);
+        stopped(&r);
+        let lines = buf.lock().unwrap().take();
+        assert_eq!(lines.len(), 1);
+        assert_eq!((lines[0].pc, lines[0].text.as_str()), (0x10, "at 10"));
+
+        assert!(r.quit());
+    }
+
+    #[test]
+    fn banked_breakpoint() {
+        // All nops, the page shows bank 1
+        let r = Runner::spawn(|| {
+            let mut m = Toy::new(&[]);
+            m.banks.add_window("page", 0..0x100, 0x10000);
+            m.banks.select("page", 1);
+            m
+        });
+
+        r.send(Command::AddBreakpoint(Address::banked(0x08, 0x10008)));
+        r.send(Command::AddBreakpoint(Address::banked(0x0a, 0x1010a)));
+        r.run();
+
+        let (reason, s) = stopped(&r);
+        assert_eq!(reason, PauseReason::Breakpoint { id: 1, addr: 0x0a });
+        assert_eq!(s.instructions, 0x0a);
+        assert!(r.quit());
+    }
+}
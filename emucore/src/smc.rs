@@ -0,0 +1,186 @@
+use crate::mem::{Access, BusEvent, BusFilter, BusObserver};
+use std::ops::Range;
+
+/// A write to a byte that had been executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmcHit {
+    /// Address of the instruction doing the write
+    pub pc: usize,
+    pub addr: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl std::fmt::Display for SmcHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "${:04x}: wrote code at ${:04x} {:02x} -> {:02x}",
+            self.pc, self.addr, self.old, self.new
+        )
+    }
+}
+
+/// Watches for writes to bytes previously fetched as opcodes or operands
+/// Subscribe it to a MemMap with SmcMonitor::filter
+/// The writer is the first opcode fetch after the cpu's set_bus_cycle,
+/// so prefixed opcodes are blamed on their first byte
+#[derive(Debug, Clone)]
+pub struct SmcMonitor {
+    code: Vec<bool>,
+    allowed: Vec<Range<usize>>,
+    pc: usize,
+    /// Next opcode fetch starts an instruction
+    boundary: bool,
+    hits: Vec<SmcHit>,
+    invalidated: Vec<usize>,
+}
+
+impl Default for SmcMonitor {
+    fn default() -> Self {
+        Self {
+            code: vec![false; 0x1_0000],
+            allowed: vec![],
+            pc: 0,
+            boundary: true,
+            hits: vec![],
+            invalidated: vec![],
+        }
+    }
+}
+
+impl SmcMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The accesses the monitor needs to see
+    pub fn filter() -> BusFilter {
+        BusFilter::new(0..0x1_0000, Access::CODE | Access::WRITE)
+    }
+
+    /// Writes into this range are deliberate
+    pub fn allow(&mut self, range: Range<usize>) {
+        self.allowed.push(range)
+    }
+
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.code.get(addr).copied().unwrap_or(false)
+    }
+
+    pub fn hits(&self) -> &[SmcHit] {
+        &self.hits
+    }
+
+    pub fn take_hits(&mut self) -> Vec<SmcHit> {
+        std::mem::take(&mut self.hits)
+    }
+
+    /// Code addresses written since last asked, allowed or not
+    /// Anything caching decoded instructions must drop these
+    pub fn take_invalidated(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.invalidated)
+    }
+
+    fn mark(&mut self, range: Range<usize>) {
+        for a in range {
+            if let Some(c) = self.code.get_mut(a) {
+                *c = true
+            }
+        }
+    }
+
+    fn write(&mut self, addr: usize, old: u8, new: u8) {
+        if !self.is_code(addr) {
+            return;
+        }
+
+        self.invalidated.push(addr);
+
+        if !self.allowed.iter().any(|r| r.contains(&addr)) {
+            self.hits.push(SmcHit {
+                pc: self.pc,
+                addr,
+                old,
+                new,
+            })
+        }
+    }
+}
+
+impl BusObserver for SmcMonitor {
+    fn on_access(&mut self, event: &BusEvent) {
+        if event.access.contains(Access::FETCH) {
+            if self.boundary {
+                self.pc = event.addr;
+                self.boundary = false;
+            }
+            self.mark(event.range());
+        } else if event.access.contains(Access::OPERAND) {
+            self.mark(event.range());
+        } else if event.access.contains(Access::WRITE) {
+            if event.word {
+                // Words are big endian on the cpus here
+                let [old_hi, old_lo] = event.old.to_be_bytes();
+                let [hi, lo] = event.val.to_be_bytes();
+                self.write(event.addr, old_hi, hi);
+                self.write(event.addr + 1, old_lo, lo);
+            } else {
+                self.write(event.addr, event.old as u8, event.val as u8);
+            }
+        }
+    }
+
+    fn on_instruction(&mut self) {
+        self.boundary = true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::{MemBlock, MemMap, MemMapIO, MemoryIO};
+    use byteorder::BigEndian;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn detects_writes_to_code() {
+        let mut mm = MemMap::new();
+        mm.add_memory(Box::new(MemBlock::<BigEndian>::new(
+            "ram",
+            false,
+            &(0..0x100),
+        )));
+
+        let smc = Arc::new(Mutex::new(SmcMonitor::new()));
+        smc.lock().unwrap().allow(0x40..0x48);
+        mm.subscribe(SmcMonitor::filter(), Box::new(smc.clone()));
+
+        let step = |mm: &mut MemMap, code: &[usize]| {
+            mm.set_bus_cycle(0);
+            for a in code {
+                mm.fetch_byte(*a).unwrap();
+            }
+        };
+
+        step(&mut mm, &[0x10]);
+        mm.fetch_operand_word(0x11).unwrap();
+        step(&mut mm, &[0x40]);
+        // A prefixed opcode
+        step(&mut mm, &[0x20, 0x21]);
+
+        mm.store_byte(0x30, 1).unwrap();
+        mm.store_byte(0x40, 2).unwrap();
+        mm.store_word(0x11, 0x1234).unwrap();
+
+        let hits = smc.lock().unwrap().take_hits();
+        let want = |addr, new| SmcHit {
+            pc: 0x20,
+            addr,
+            old: 0,
+            new,
+        };
+        assert_eq!(hits, vec![want(0x11, 0x12), want(0x12, 0x34)]);
+        assert_eq!(smc.lock().unwrap().take_invalidated(), vec![0x40, 0x11, 0x12]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+use thiserror::Error;
+
+/// How many recent stack accesses are kept for diagnostics
+const SHADOW_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackAccess {
+    pub pc: usize,
+    pub addr: usize,
+    pub val: u16,
+    pub word: bool,
+    pub push: bool,
+}
+
+impl fmt::Display for StackAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = if self.push { "push" } else { "pop " };
+        let val = if self.word {
+            format!("{:04x}", self.val)
+        } else {
+            format!("  {:02x}", self.val)
+        };
+        write!(f, "${:04x}: {op} {val} @ ${:04x}", self.pc, self.addr)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{name} stack {kind} at pc ${pc:04x}: access ${addr:04x} outside ${:04x}-${:04x}, depth {depth}\n{recent}", .window.start, .window.end - 1)]
+pub struct StackErr {
+    pub name: String,
+    pub kind: &'static str,
+    pub pc: usize,
+    pub addr: usize,
+    pub window: Range<usize>,
+    /// Bytes in use, negative if popped past the top
+    pub depth: isize,
+    /// Recent pushes and pops, oldest first
+    pub recent: String,
+}
+
+/// Checks every push and pop lands inside the stack's RAM
+/// and tracks the deepest the stack got
+#[derive(Debug, Clone)]
+pub struct StackGuard {
+    name: String,
+    window: Range<usize>,
+    lowest: Option<usize>,
+    shadow: VecDeque<StackAccess>,
+}
+
+impl StackGuard {
+    /// window is the RAM given to the stack, it grows down from the end
+    pub fn new(name: &str, window: Range<usize>) -> Self {
+        Self {
+            name: name.to_string(),
+            window,
+            lowest: None,
+            shadow: VecDeque::with_capacity(SHADOW_SIZE),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn window(&self) -> Range<usize> {
+        self.window.clone()
+    }
+
+    /// Most bytes the stack has used
+    pub fn high_water(&self) -> usize {
+        self.lowest.map(|l| self.window.end - l).unwrap_or(0)
+    }
+
+    pub fn recent(&self) -> impl Iterator<Item = &StackAccess> {
+        self.shadow.iter()
+    }
+
+    pub fn reset(&mut self) {
+        self.lowest = None;
+        self.shadow.clear();
+    }
+
+    pub fn check_push(
+        &mut self,
+        pc: usize,
+        addr: usize,
+        val: u16,
+        word: bool,
+    ) -> Result<(), StackErr> {
+        self.check(StackAccess {
+            pc,
+            addr,
+            val,
+            word,
+            push: true,
+        })
+    }
+
+    pub fn check_pop(
+        &mut self,
+        pc: usize,
+        addr: usize,
+        val: u16,
+        word: bool,
+    ) -> Result<(), StackErr> {
+        self.check(StackAccess {
+            pc,
+            addr,
+            val,
+            word,
+            push: false,
+        })
+    }
+
+    fn check(&mut self, access: StackAccess) -> Result<(), StackErr> {
+        if self.shadow.len() == SHADOW_SIZE {
+            self.shadow.pop_front();
+        }
+        self.shadow.push_back(access);
+
+        let last = access.addr + if access.word { 1 } else { 0 };
+
+        if access.addr < self.window.start || last >= self.window.end {
+            let kind = if access.addr < self.window.start {
+                "overflow"
+            } else {
+                "underflow"
+            };
+            return Err(self.error(kind, &access));
+        }
+
+        if access.push {
+            self.lowest = Some(self.lowest.map_or(access.addr, |l| l.min(access.addr)));
+        }
+
+        Ok(())
+    }
+
+    fn error(&self, kind: &'static str, access: &StackAccess) -> StackErr {
+        let recent: Vec<_> = self.shadow.iter().map(|a| format!("  {a}")).collect();
+
+        StackErr {
+            name: self.name.clone(),
+            kind,
+            pc: access.pc,
+            addr: access.addr,
+            window: self.window.clone(),
+            depth: self.window.end as isize - access.addr as isize,
+            recent: recent.join("\n"),
+        }
+    }
+}
+
+impl fmt::Display for StackGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: ${:04x}-${:04x} high water {} of {} bytes",
+            self.name,
+            self.window.start,
+            self.window.end - 1,
+            self.high_water(),
+            self.window.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_water_and_bounds() {
+        let mut g = StackGuard::new("S", 0x7f00..0x7f08);
+
+        g.check_push(0x1000, 0x7f06, 0x1234, true).unwrap();
+        g.check_push(0x1002, 0x7f05, 0x12, false).unwrap();
+        g.check_pop(0x1004, 0x7f05, 0x12, false).unwrap();
+        assert_eq!(g.high_water(), 3);
+
+        let err = g.check_pop(0x1006, 0x7f07, 0x3456, true).unwrap_err();
+        assert_eq!(err.kind, "underflow");
+        assert_eq!(err.depth, 1);
+
+        g.check_push(0x1008, 0x7f00, 0, true).unwrap();
+        let err = g.check_push(0x100a, 0x7eff, 0, false).unwrap_err();
+        assert_eq!(err.kind, "overflow");
+        assert_eq!(err.pc, 0x100a);
+        assert!(err.recent.contains("$1008: push 0000 @ $7f00"));
+        assert_eq!(g.high_water(), 8);
+    }
+}
@@ -0,0 +1,103 @@
+use grl_symbols::{ScopeIdTraits, SymIdTraits, SymbolTree};
+use std::collections::BTreeMap;
+
+/// Address to name lookup built from a symbol tree
+/// used to put names on addresses in diagnostic output
+#[derive(Debug, Clone, Default)]
+pub struct SymbolMap {
+    addr_to_name: BTreeMap<usize, String>,
+}
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes every symbol with a value that fits in the address space
+    /// If more than one symbol has the same value the least nested one wins
+    pub fn from_symbol_tree<SCOPEID, SYMID>(syms: &SymbolTree<SCOPEID, SYMID, i64>) -> Self
+    where
+        SCOPEID: ScopeIdTraits,
+        SYMID: SymIdTraits,
+    {
+        let mut ret = Self::new();
+
+        for si in syms.symbols() {
+            if let Some(v) = si.value {
+                if (0..0x1_0000).contains(&v) {
+                    let name = si.scoped_name().trim_start_matches("::");
+                    ret.add(v as usize, name);
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addr_to_name.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addr_to_name.len()
+    }
+
+    pub fn add(&mut self, addr: usize, name: &str) {
+        let depth = |n: &str| n.matches("::").count();
+
+        match self.addr_to_name.get(&addr) {
+            Some(old) if depth(old) <= depth(name) => (),
+            _ => {
+                self.addr_to_name.insert(addr, name.to_string());
+            }
+        }
+    }
+
+    /// Symbol exactly at this address
+    pub fn get(&self, addr: usize) -> Option<&str> {
+        self.addr_to_name.get(&addr).map(|s| s.as_str())
+    }
+
+    /// Closest symbol at or below this address and the offset from it
+    pub fn find_nearest(&self, addr: usize) -> Option<(&str, usize)> {
+        self.addr_to_name
+            .range(..=addr)
+            .next_back()
+            .map(|(a, n)| (n.as_str(), addr - a))
+    }
+
+    /// Text for an address, symbol+offset if possible
+    pub fn describe(&self, addr: usize) -> String {
+        match self.find_nearest(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, off)) => format!("{name}+${off:x}"),
+            None => format!("${addr:04x}"),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.addr_to_name.iter().map(|(a, n)| (*a, n.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let mut st: SymbolTree<u64, u64, i64> = SymbolTree::new();
+        let mut w = st.get_root_writer();
+        w.create_and_set_symbol("main", 0x1000).unwrap();
+        w.create_or_set_scope("main");
+        w.create_and_set_symbol("loop", 0x1000).unwrap();
+        w.create_and_set_symbol("exit", 0x1010).unwrap();
+
+        let syms = SymbolMap::from_symbol_tree(&st);
+
+        assert_eq!(syms.get(0x1000), Some("main"));
+        assert_eq!(syms.get(0x1010), Some("main::exit"));
+        assert_eq!(syms.find_nearest(0x1004), Some(("main", 4)));
+        assert_eq!(syms.describe(0x1012), "main::exit+$2");
+        assert_eq!(syms.describe(0x0800), "$0800");
+    }
+}
@@ -0,0 +1,295 @@
+//! A tiny cpu the unit tests share
+//!
+//! 256 bytes of ram, pc wraps round it
+//!
+//!  00     nop
+//!  01 nn  call nn
+//!  02     ret
+//!  03     rti
+//!  04 nn  add #nn, sets Z and C
+//!  05 nn  store acc at nn
+//!  06     sec
+//!  07     clc, also clears Z when buggy
+//!  08 nn  inc nn
+//!  09 nn  jmp nn
+//!  0a     sei
+//!  0b     cli
+//!  0c     add the input latch to acc
+//!  ff     illegal
+//!
+//! A latched irq enters $80 with I set, rti clears it
+//! Banks only change what breakpoints see, memory isn't paged
+use crate::address::{BankMap, Banks};
+use crate::callstack::CallStack;
+use crate::flagcheck::FlagTarget;
+use crate::flow::Flow;
+use crate::golden::GoldenTarget;
+use crate::intsched::InterruptTarget;
+use crate::mem::{MemBlock, MemErrorTypes, MemMap, MemMapIO, MemResult, MemoryIO};
+use crate::replay::{hash_state, Pin, ReplayTarget};
+use crate::run::{RunTarget, StepTarget};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+pub const IRQ_VECTOR: usize = 0xfff8;
+pub const IRQ_ENTRY: usize = 0x80;
+
+pub const I: u8 = 4;
+pub const Z: u8 = 2;
+pub const C: u8 = 1;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ToyErr {
+    #[error("Illegal opcode ${opcode:02x} at ${pc:02x}")]
+    Illegal { pc: usize, opcode: u8 },
+    #[error(transparent)]
+    Mem(#[from] MemErrorTypes),
+}
+
+pub struct Toy {
+    pub mem: MemMap,
+    pub pc: usize,
+    pub acc: u8,
+    pub sr: u8,
+    pub stack: Vec<usize>,
+    pub cycles: usize,
+    pub instructions: usize,
+    pub irq: bool,
+    /// What 0c reads
+    pub input: u8,
+    pub flow: Flow,
+    pub call_stack: CallStack,
+    pub buggy: bool,
+    pub banks: Banks,
+}
+
+impl Toy {
+    /// Ram with each chunk of code loaded at its address
+    pub fn new(code: &[(usize, &[u8])]) -> Self {
+        let mut mem = MemMap::new();
+        mem.add_memory(Box::new(MemBlock::<byteorder::BigEndian>::new(
+            "ram",
+            false,
+            &(0..0x100),
+        )));
+
+        for (addr, bytes) in code {
+            mem.upload(*addr, bytes).unwrap();
+        }
+
+        Self {
+            mem,
+            pc: 0,
+            acc: 0,
+            sr: 0,
+            stack: vec![],
+            cycles: 0,
+            instructions: 0,
+            irq: false,
+            input: 0,
+            flow: Flow::Normal,
+            call_stack: CallStack::new(),
+            buggy: false,
+            banks: Banks::new(),
+        }
+    }
+
+    pub fn buggy(self) -> Self {
+        Self {
+            buggy: true,
+            ..self
+        }
+    }
+
+    fn operand(&mut self) -> MemResult<usize> {
+        self.mem
+            .fetch_operand_byte((self.pc + 1) & 0xff)
+            .map(usize::from)
+    }
+
+    fn set_flag(&mut self, flag: u8, val: bool) {
+        if val {
+            self.sr |= flag
+        } else {
+            self.sr &= !flag
+        }
+    }
+
+    fn execute(&mut self, pc: usize) -> Result<(Flow, usize, usize), ToyErr> {
+        let opcode = self.mem.fetch_byte(pc)?;
+        let next = pc + 1;
+
+        let ret = match opcode {
+            0x00 => (Flow::Normal, next, 2),
+            0x01 => {
+                let dest = self.operand()?;
+                (Flow::Call { dest, ret: pc + 2 }, dest, 4)
+            }
+            0x02 | 0x03 => {
+                let dest = self.stack.pop().unwrap_or(0);
+                if opcode == 0x02 {
+                    (Flow::Return { dest }, dest, 4)
+                } else {
+                    self.sr &= !I;
+                    (Flow::ReturnFromInterrupt { dest }, dest, 4)
+                }
+            }
+            0x04 => {
+                let (v, c) = self.acc.overflowing_add(self.operand()? as u8);
+                self.acc = v;
+                self.set_flag(Z, v == 0);
+                self.set_flag(C, c);
+                (Flow::Normal, pc + 2, 2)
+            }
+            0x05 => {
+                let addr = self.operand()?;
+                self.mem.store_byte(addr, self.acc)?;
+                (Flow::Normal, pc + 2, 3)
+            }
+            0x06 => {
+                self.sr |= C;
+                (Flow::Normal, next, 2)
+            }
+            0x07 => {
+                self.sr &= if self.buggy { !(C | Z) } else { !C };
+                (Flow::Normal, next, 2)
+            }
+            0x08 => {
+                let addr = self.operand()?;
+                let v = self.mem.load_byte(addr)?;
+                self.mem.store_byte(addr, v.wrapping_add(1))?;
+                (Flow::Normal, pc + 2, 3)
+            }
+            0x09 => (Flow::Normal, self.operand()?, 3),
+            0x0a => {
+                self.sr |= I;
+                (Flow::Normal, next, 2)
+            }
+            0x0b => {
+                self.sr &= !I;
+                (Flow::Normal, next, 2)
+            }
+            0x0c => {
+                self.acc = self.acc.wrapping_add(self.input);
+                (Flow::Normal, next, 2)
+            }
+            _ => return Err(ToyErr::Illegal { pc, opcode }),
+        };
+
+        Ok(ret)
+    }
+}
+
+impl GoldenTarget for Toy {
+    type Err = ToyErr;
+
+    fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    fn step(&mut self) -> Result<(), ToyErr> {
+        let pc = self.pc;
+
+        let (flow, dest, cycles) = if self.irq && self.sr & I == 0 {
+            self.irq = false;
+            self.sr |= I;
+            let flow = Flow::Interrupt {
+                vector: IRQ_VECTOR,
+                dest: IRQ_ENTRY,
+                ret: pc,
+            };
+            (flow, IRQ_ENTRY, 8)
+        } else {
+            let ret = self.execute(pc)?;
+            self.instructions += 1;
+            ret
+        };
+
+        if let Flow::Call { ret, .. } | Flow::Interrupt { ret, .. } = flow {
+            self.stack.push(ret)
+        }
+
+        self.flow = flow;
+        self.pc = dest & 0xff;
+        self.cycles += cycles + self.mem.take_wait_cycles();
+        self.call_stack.update(pc, 0x100 - self.stack.len(), flow);
+        Ok(())
+    }
+
+    fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    fn regs(&self) -> BTreeMap<String, u64> {
+        [
+            ("pc".to_string(), self.pc as u64),
+            ("acc".to_string(), self.acc as u64),
+            ("sr".to_string(), self.sr as u64),
+        ]
+        .into()
+    }
+
+    fn mem(&self) -> &dyn MemoryIO {
+        &self.mem
+    }
+
+    fn mem_mut(&mut self) -> &mut dyn MemoryIO {
+        &mut self.mem
+    }
+}
+
+impl ReplayTarget for Toy {
+    fn set_pin(&mut self, pin: Pin, level: bool) {
+        if pin == Pin::Irq {
+            self.irq = level
+        }
+    }
+
+    fn state_hash(&self) -> String {
+        let mut bytes = vec![self.pc as u8, self.acc, self.sr, self.irq as u8];
+        bytes.extend(self.stack.iter().map(|r| *r as u8));
+        hash_state(&bytes, &self.mem)
+    }
+}
+
+impl RunTarget for Toy {
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn is_illegal_opcode(err: &ToyErr) -> bool {
+        matches!(err, ToyErr::Illegal { .. })
+    }
+
+    fn bank_map(&self) -> &dyn BankMap {
+        &self.banks
+    }
+}
+
+impl InterruptTarget for Toy {
+    fn is_masked(&self, pin: Pin) -> bool {
+        pin == Pin::Irq && self.sr & I != 0
+    }
+
+    fn taken_interrupt(&self) -> Option<Pin> {
+        matches!(self.flow, Flow::Interrupt { .. }).then_some(Pin::Irq)
+    }
+}
+
+impl StepTarget for Toy {
+    fn flow(&self) -> Flow {
+        self.flow
+    }
+
+    fn call_depth(&self) -> usize {
+        self.call_stack.depth()
+    }
+}
+
+impl FlagTarget for Toy {
+    const FLAG_LAYOUT: &'static str = "-----IZC";
+
+    fn flags(&self) -> u64 {
+        self.sr as u64
+    }
+}
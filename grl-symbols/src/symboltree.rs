@@ -357,6 +357,11 @@ where
             .ok_or(SymbolError::NotFound)
     }
 
+    /// Iterate over every symbol in every scope
+    pub fn symbols(&self) -> impl Iterator<Item = &SymbolInfo<SCOPEID, SYMID, V>> {
+        self.scope_id_to_symbol_info.values()
+    }
+
     pub fn get_scope_info_from_id(&self, scope_id: SCOPEID) -> Option<ScopeInfo<SCOPEID>> {
         let x = self.etree.get_scope(scope_id).ok()?;
 